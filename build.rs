@@ -1,6 +1,7 @@
 pub fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=js/tiptap-link.js");
 
     let root_dir: std::path::PathBuf = std::env::var("CARGO_MANIFEST_DIR").unwrap().into();
     let generated_dir = root_dir.join("generated");
@@ -11,4 +12,9 @@ pub fn main() {
 
     std::fs::create_dir_all(js_dir.clone()).unwrap();
     println!("cargo:warning=js dir created");
+
+    std::fs::write(js_dir.join("tiptap-bundle.min.js"), leptos_tiptap_build::TIPTAP_BUNDLE_MIN_JS).unwrap();
+    std::fs::write(js_dir.join("tiptap.js"), leptos_tiptap_build::TIPTAP_JS).unwrap();
+    std::fs::copy(root_dir.join("js").join("tiptap-link.js"), js_dir.join("tiptap-link.js")).unwrap();
+    println!("cargo:warning=tiptap written");
 }
@@ -0,0 +1,32 @@
+// A link mark for the caption editor, the Tiptap bundle has no link extension. Loaded after the
+// bundle, it is registered as `window.TipTapLink.Link` and added to the starter kit, which every
+// editor is created with. Links are kept to web and mail addresses when a caption is stored, see
+// `rich_text::sanitize_html`.
+window.TipTapLink = {
+  Link: window.TipTap.Mark.create({
+    name: 'link',
+    priority: 1000,
+    inclusive: false,
+    addAttributes() {
+      return { href: { default: null } };
+    },
+    parseHTML() {
+      return [{ tag: 'a[href]' }];
+    },
+    renderHTML({ HTMLAttributes }) {
+      return ['a', window.TipTap.mergeAttributes(HTMLAttributes, { rel: 'nofollow noopener noreferrer', target: '_blank' }), 0];
+    },
+    addCommands() {
+      return {
+        setLink: attributes => ({ chain }) => chain().setMark(this.name, attributes).run(),
+        unsetLink: () => ({ chain }) => chain().unsetMark(this.name, { extendEmptyMarkRange: true }).run(),
+      };
+    },
+  }),
+};
+
+window.TipTapStarterKit.StarterKit = window.TipTapStarterKit.StarterKit.extend({
+  addExtensions() {
+    return [...this.parent(), window.TipTapLink.Link];
+  },
+});
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::Error;
+use crate::hash_route::HashState;
+use crate::object_store::{list_all, ObjectStore};
+use crate::{ImageInfo, S3ObjectInfo};
+
+/// Albums are stored as `albums/<id>.json`.
+pub const ALBUM_PREFIX: &str = "albums/";
+
+/// A collection of the site's images, listed by their ids in the album's order.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Album {
+    pub id: String,
+    // names the album in its link
+    pub slug: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    // the id of the image shown for the album, the first image when it has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover_image: Option<String>,
+    #[serde(default)]
+    pub image_ids: Vec<String>,
+}
+
+impl Album {
+    /// An empty album, its slug is made from `title` and differs from the slugs of `albums`.
+    pub fn new(title: &str, albums: &[Album]) -> Self {
+        let base = slug(title);
+        let mut slug = base.clone();
+        let mut count = 1;
+        while albums.iter().any(|album| album.slug == slug) {
+            count += 1;
+            slug = format!("{}-{}", base, count);
+        }
+        Self {
+            id: Uuid::new_v4().to_string(),
+            slug,
+            title: title.to_string(),
+            description: String::new(),
+            cover_image: None,
+            image_ids: vec![],
+        }
+    }
+
+    pub fn key(&self) -> String {
+        format!("{}{}.json", ALBUM_PREFIX, self.id)
+    }
+
+    /// The album's images from `gallery`, in the album's order. Images no longer in the gallery,
+    /// deleted or hidden, are left out.
+    pub fn images(&self, gallery: &[S3ObjectInfo]) -> Vec<S3ObjectInfo> {
+        self.image_ids.iter()
+            .filter_map(|id| gallery.iter().find(|image| image.id().as_ref() == Some(id)).cloned())
+            .collect()
+    }
+
+    pub fn cover<'a>(&self, gallery: &'a [S3ObjectInfo]) -> Option<&'a S3ObjectInfo> {
+        let id = self.cover_image.as_ref().or(self.image_ids.first())?;
+        gallery.iter().find(|image| image.id().as_ref() == Some(id))
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.image_ids.iter().any(|image_id| image_id == id)
+    }
+
+    /// Adds the image `id` at the end of the album, unless it is already in it.
+    pub fn add(&mut self, id: &str) {
+        if !self.contains(id) {
+            self.image_ids.push(id.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.image_ids.retain(|image_id| image_id != id);
+        if self.cover_image.as_deref() == Some(id) {
+            self.cover_image = None;
+        }
+    }
+
+    /// Moves the image `id` `offset` places later in the album, or earlier when negative.
+    pub fn move_image(&mut self, id: &str, offset: isize) {
+        if let Some(index) = self.image_ids.iter().position(|image_id| image_id == id) {
+            let to = (index as isize + offset).clamp(0, self.image_ids.len() as isize - 1) as usize;
+            let image_id = self.image_ids.remove(index);
+            self.image_ids.insert(to, image_id);
+        }
+    }
+
+    /// The album's link on the public site at `base`, e.g. `#/album/<slug>`.
+    pub fn link(&self, base: &str) -> String {
+        format!("{}#{}", base, HashState { album: Some(self.slug.clone()), current_image: None }.route())
+    }
+}
+
+/// `title` in lower case, with runs of anything but letters and digits as a '-'.
+pub fn slug(title: &str) -> String {
+    let slug = title.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    match slug.is_empty() {
+        true => "album".to_string(),
+        false => slug,
+    }
+}
+
+/// The albums the album picker offers along with "All Images", `None` when it offers no choice. A
+/// visitor shown an album, e.g. from a shared link, is not offered the rest of the site.
+pub fn picker_albums(albums: &[Album], current: Option<&Album>, can_edit: bool) -> Option<Vec<Album>> {
+    match (can_edit, current) {
+        (true, _) => Some(albums.to_vec()),
+        (false, Some(_)) => None,
+        (false, None) if albums.is_empty() => None,
+        (false, None) => Some(albums.to_vec()),
+    }
+}
+
+/// The site's albums, ordered by title.
+pub async fn list_albums(store: &dyn ObjectStore) -> Result<Vec<Album>, Error> {
+    let mut albums = vec![];
+    for object in list_all(store, ALBUM_PREFIX).await? {
+        let bytes = store.get(&object.key()).await?;
+        match serde_json::from_slice::<Album>(&bytes) {
+            Ok(album) => albums.push(album),
+            Err(err) => log::info!("unreadable album {}: {}", object.key(), err),
+        }
+    }
+    albums.sort_by_key(|album| album.title.to_lowercase());
+    Ok(albums)
+}
+
+pub async fn save_album(store: &dyn ObjectStore, album: &Album) -> Result<(), Error> {
+    let body = serde_json::to_vec(album).map_err(|err| Error::Fetch(format!("{}", err)))?;
+    store.put(&album.key(), body).await
+}
+
+pub async fn delete_album(store: &dyn ObjectStore, album: &Album) -> Result<(), Error> {
+    store.delete(&album.key(), None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::album::*;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::block_on;
+
+    fn image(key: &str, id: &str) -> S3ObjectInfo {
+        let mut image = S3ObjectInfo::new(key.to_string(), "2023-10-12T14:48:09.000Z".to_string(), "\"a\"".to_string(), 1);
+        image.set_id(Some(id.to_string()));
+        image
+    }
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(slug("Summer in Norway, 2023!"), "summer-in-norway-2023");
+        assert_eq!(slug("  "), "album");
+        let albums = vec![Album::new("Trip", &[])];
+        assert_eq!(Album::new("trip", &albums).slug, "trip-2");
+    }
+
+    #[test]
+    fn test_album_images() {
+        let gallery = vec![image("images/a.jpg", "a"), image("images/b.jpg", "b"), image("images/c.jpg", "c")];
+        let mut album = Album::new("Trip", &[]);
+        album.add("c");
+        album.add("a");
+        album.add("gone");
+        album.add("c");
+        assert_eq!(album.images(&gallery).iter().map(|i| i.key()).collect::<Vec<String>>(), vec!["images/c.jpg", "images/a.jpg"]);
+        assert_eq!(album.cover(&gallery).map(|i| i.key()), Some("images/c.jpg".to_string()));
+
+        album.cover_image = Some("a".to_string());
+        album.move_image("a", -5);
+        assert_eq!(album.image_ids, vec!["a", "c", "gone"]);
+        album.move_image("a", 1);
+        assert_eq!(album.image_ids, vec!["c", "a", "gone"]);
+        album.remove("a");
+        assert_eq!(album.cover_image, None);
+        assert_eq!(album.link("http://photos.example.com/"), "http://photos.example.com/#/album/trip");
+    }
+
+    #[test]
+    fn test_picker_albums() {
+        let albums = vec![Album::new("Beach", &[]), Album::new("Trip", &[])];
+        assert_eq!(picker_albums(&albums, Some(&albums[1]), true), Some(albums.clone()));
+        assert_eq!(picker_albums(&albums, None, false), Some(albums.clone()));
+        // a visitor opening a shared album link only sees that album
+        assert_eq!(picker_albums(&albums, Some(&albums[1]), false), None);
+        assert_eq!(picker_albums(&[], None, false), None);
+        assert_eq!(picker_albums(&[], None, true), Some(vec![]));
+    }
+
+    #[test]
+    fn test_saved_albums() {
+        let store = MemoryStore::new();
+        block_on(async {
+            let mut zoo = Album::new("Zoo", &[]);
+            zoo.add("a");
+            let beach = Album::new("beach", &[]);
+            save_album(&store, &zoo).await.unwrap();
+            save_album(&store, &beach).await.unwrap();
+            assert_eq!(list_albums(&store).await.unwrap(), vec![beach.clone(), zoo.clone()]);
+
+            delete_album(&store, &beach).await.unwrap();
+            assert_eq!(list_albums(&store).await.unwrap(), vec![zoo]);
+        });
+    }
+}
@@ -3,25 +3,46 @@ use leptos::*;
 use thiserror::Error;
 use serde::{Serialize, Deserialize};
 
-use gloo_net::http::{Request};
-use js_sys::{Date, Uint8Array};
+use gloo_net::http::{Request, Method, RequestBuilder, Response};
+use js_sys::{Array, Date, Uint8Array};
+use web_sys::{AbortSignal, Blob, BlobPropertyBag, FormData};
+use wasm_bindgen::JsValue;
 
 use crate::SiteSettings;
-use wasm_bindgen::JsValue;
-use crate::ListBucketResult;
 use crate::S3ObjectInfo;
-use crate::ImageInfo;
 
 use crate::api::Error::UploadError;
 use crate::api::Error::Fetch;
+use crate::api::Error::MissingCredentials;
+use crate::api::Error::Signing;
+use crate::api::Error::Status;
+use crate::api::Error::Cancelled;
+use crate::api::Error::RequestTimeTooSkewed;
+use crate::api::Error::ExpiredToken;
 
-use url::form_urlencoded::byte_serialize;
-use crate::local_config::get_current_config;
+use crate::local_config::{get_current_config, SiteConfig};
+use crate::object_store::{list_all, ObjectStore, WriteCondition};
+use crate::conflict::{read_versioned, Versioned};
+use crate::manifest::{image_id, update_manifest};
+use crate::caption::Caption;
 use crate::awssigv4::generate_headers;
-use crate::upload_to_s3;
-use crate::delete_object;
-use crate::get_image;
+use crate::awssigv4::generate_signed_headers;
+use crate::awssigv4::SigningCredentials;
+use crate::awssigv4::generate_presigned_url;
 use crate::local_config;
+use crate::guest_upload::GuestUploadGrant;
+use crate::s3_error::{error_from_response, retry_delay_ms};
+
+use std::cell::Cell;
+use gloo_timers::future::TimeoutFuture;
+
+// Attempts made for a request before a transient failure is returned.
+const MAX_ATTEMPTS: u32 = 4;
+
+thread_local! {
+    // S3's clock minus the device clock, learned from RequestTimeTooSkewed errors.
+    static CLOCK_SKEW_MS: Cell<f64> = const { Cell::new(0.0) };
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AWSCredentials {
@@ -38,162 +59,338 @@ impl AWSCredentials {
     }
 }
 
-pub fn bucket_name() -> String {
-    match get_current_config() {
-        Ok(config) => {
-            config.s3_bucket_name()
-        },
-        Err(err) => {
-            log::info!("Err {:?}", err);
-            "".to_string()
-        },
-    }
+/// The current time corrected for the difference between the device's clock and S3's.
+pub fn signing_date() -> Date {
+    Date::new(&JsValue::from_f64(Date::now() + CLOCK_SKEW_MS.with(|skew| skew.get())))
 }
 
-async fn send_list_request(url: String) -> Result<ListBucketResult> {
-    let request = Request::get(&url).headers(generate_headers(&Date::new_0()));
-    return match request.send().await {
+async fn send_request(request: Request, abort: Option<&AbortSignal>) -> Result<Response> {
+    match request.send().await {
         Ok(response) => {
-            let xml = response.text().await.expect("xml expected");
-            match quick_xml::de::from_str(&xml) {
-                Ok(result) => Ok(result),
-                Err(err) => {
-                    log::info!("Error on deserializing request: {}", err);
-                    Ok(ListBucketResult::new())
-                },
+            if response.ok() {
+                Ok(response)
+            } else {
+                let body = response.text().await.unwrap_or_default();
+                Err(error_from_response(response.status(), &body))
             }
         },
-        Err(net_error) => {
-            Err(Fetch(format!("{}", net_error).to_string()))
-        }
-    };
+        Err(net_error) => match abort {
+            Some(signal) if signal.aborted() => Err(Cancelled),
+            _ => Err(Fetch(format!("{}", net_error))),
+        },
+    }
 }
 
-pub async fn list_images() -> Result<Vec<S3ObjectInfo>> {
-    let mut contents: Vec<S3ObjectInfo> = vec![];
-    let config = get_current_config()?;
-    let bucket = config.s3_bucket_name();
-    let region = config.region;
-    let url = {format!("https://s3.{}.amazonaws.com/{}?list-type=2&prefix=images%2F&start-after=images%2F", region, bucket)};
-    let mut response = send_list_request(url).await?;
-    contents.append(&mut response.contents);
-    let mut truncated = response.is_truncated;
-    let mut next_continuation_token = response.next_continuation_token;
-    while truncated {
-        match next_continuation_token {
-            Some(ref continuation_token) => {
-                let url = format!("https://s3.{}.amazonaws.com/{}?list-type=2&continuation-token={}&prefix=images%2F&start-after=images%2F",
-                    region, bucket_name(), byte_serialize(continuation_token.as_bytes()).collect::<String>());
-                let mut r = send_list_request(url).await?;
-                truncated = r.is_truncated;
-                next_continuation_token = r.next_continuation_token;
-                contents.append(&mut r.contents);
+/// Whether a request may be sent again when its response is lost, as it changes nothing more the
+/// second time. A conditional request is not, as its own first attempt would fail its condition.
+pub fn is_idempotent(method: &str, headers: &[(String, String)]) -> bool {
+    let conditional = headers.iter().any(|(name, _)| {
+        let name = name.to_ascii_lowercase();
+        name.starts_with("if-") || name.starts_with("x-amz-copy-source-if-")
+    });
+    matches!(method, "GET" | "HEAD" | "PUT" | "DELETE" | "PROPFIND") && !conditional
+}
+
+/// Sends the request made by `build`, retrying transient failures with exponential backoff.
+/// The request is built again for each attempt, so it is signed with the corrected clock. A
+/// request which is not `idempotent` is not sent again when it may have reached the server.
+pub async fn send_with_retry(build: impl Fn() -> Result<Request>, idempotent: bool, abort: Option<&AbortSignal>) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let result = send_request(build()?, abort).await;
+        match result {
+            Err(err) if err.is_retryable(idempotent) && attempt + 1 < MAX_ATTEMPTS => {
+                if let RequestTimeTooSkewed(Some(server_time)) = err {
+                    CLOCK_SKEW_MS.with(|skew| skew.set(server_time as f64 - Date::now()));
+                }
+                attempt += 1;
+                TimeoutFuture::new(retry_delay_ms(attempt, js_sys::Math::random())).await;
             },
-            None => truncated = false,
-        } 
+            _ => return result,
+        }
     }
-    contents.sort_by(|a, b| b.last_modified().cmp(&a.last_modified()));     
-    Ok(contents)
 }
 
-pub fn region() -> String {
-    get_current_config().expect("config expected").region
+/// The site's images, most recently modified first.
+pub async fn list_images(store: &dyn ObjectStore) -> Result<Vec<S3ObjectInfo>> {
+    list_all(store, "images/").await
 }
 
-pub async fn set_public_site_settings(site_settings: SiteSettings) -> Result<SiteSettings> {
-    let s3_bucket_name = get_current_config().expect("config expected").s3_bucket_name();
-    let region = get_current_config().expect("config expected").region;
-    let sk = get_current_config().expect("config expected").secret_key.expect("sk");
-    let ak = get_current_config().expect("config expected").access_key.expect("ak");
+/// The site's settings with the ETag they were read with, for editing them.
+pub async fn read_public_site_settings(store: &dyn ObjectStore) -> Result<Versioned<SiteSettings>> {
+    let parse = |bytes: &[u8]| serde_json::from_slice::<SiteSettings>(bytes).unwrap_or_else(|_| SiteSettings::new());
+    read_versioned(store, "admin/settings.json", parse, SiteSettings::new()).await
+}
+
+/// Stores the site's settings while `condition` holds, returns their new ETag. Settings changed
+/// since they were read fail with `PreconditionFailed`.
+pub async fn set_public_site_settings(store: &dyn ObjectStore, site_settings: &SiteSettings, condition: &WriteCondition) -> Result<String> {
+    let source = serde_json::to_string(site_settings).expect("site setting expected");
+    store.put_if("admin/settings.json", source.into_bytes(), condition).await
+}
 
-    let source = serde_json::to_string(&site_settings).expect("site setting expected");
-    let result = upload_to_s3(ak, sk, region, s3_bucket_name, "admin/settings.json".to_string(), source.into()).await;
-    let converted = result.as_string().expect("upload result exists");
-    if converted == "error" {
-       Err(UploadError("".to_string()))
-    } else {
-        Ok(site_settings)
+fn signing_credentials_for(config: &SiteConfig) -> Result<SigningCredentials> {
+    if config.credentials_expired(time::OffsetDateTime::now_utc()) {
+        return Err(ExpiredToken);
     }
+    config.signing_credentials().ok_or(MissingCredentials)
 }
 
-pub fn aws_credentials() -> Option<AWSCredentials> {
-    let access_key = match get_current_config().expect("ak").access_key {
-        Some(ak) => {
-            Some(ak)
+fn signing_credentials() -> Result<SigningCredentials> {
+    signing_credentials_for(&get_current_config()?)
+}
+
+/// The URL of the current site's bucket, on AWS or the site's custom endpoint.
+pub fn bucket_url() -> String {
+    get_current_config().expect("config expected").bucket_url()
+}
+
+pub fn object_url(key: &str) -> String {
+    format!("{}/{}", bucket_url(), key)
+}
+
+/// A link to the object that works without credentials until it expires.
+pub fn presigned_object_url(method: Method, key: String, expires_in: u32) -> Result<String> {
+    let credentials = signing_credentials()?;
+    generate_presigned_url(&signing_date(), &credentials, method.as_str(), &object_url(&key), expires_in).ok_or(Signing)
+}
+
+/// The URL used to display an object. When the site has credentials the URL is presigned, so
+/// images in a private bucket can be shown. The signing time is rounded down to the hour so the
+/// URL stays the same, and cacheable, for the rest of that hour.
+pub fn object_read_url(key: String) -> String {
+    site_read_url(&get_current_config().expect("config expected"), &key)
+}
+
+/// As `object_read_url`, for one of `config`'s objects.
+pub fn site_read_url(config: &SiteConfig, key: &str) -> String {
+    match signing_credentials_for(config) {
+        Ok(credentials) => {
+            let hour = 3_600_000.0;
+            let signing_time = Date::new(&JsValue::from_f64((signing_date().get_time() / hour).floor() * hour));
+            generate_presigned_url(&signing_time, &credentials, "GET", &config.object_url(key), 7200)
+                .unwrap_or_else(|| config.object_url(key))
         },
-        None => None,
+        Err(_) => config.object_url(key),
+    }
+}
+
+/// Sends a request for `url` signed with the current site's credentials. Fails on a non-success status.
+pub async fn send_signed_url_request(method: Method, url: String, body: Option<Vec<u8>>, abort: Option<&AbortSignal>) -> Result<Response> {
+    send_signed_url_request_with_headers(method, url, &[], body, abort).await
+}
+
+/// As `send_signed_url_request`, additionally sending and signing `extra_headers`.
+pub async fn send_signed_url_request_with_headers(
+    method: Method,
+    url: String,
+    extra_headers: &[(String, String)],
+    body: Option<Vec<u8>>,
+    abort: Option<&AbortSignal>,
+) -> Result<Response> {
+    let config = get_current_config()?;
+    // the current site's requests are always signed
+    signing_credentials_for(&config)?;
+    send_site_request(&config, method, url, extra_headers, body, abort).await
+}
+
+/// Sends a request for one of `config`'s URLs, signed with its credentials. Reads are sent
+/// unsigned when the site has no credentials, as they are by a public site's visitors.
+/// Fails on a non-success status.
+pub async fn send_site_request(
+    config: &SiteConfig,
+    method: Method,
+    url: String,
+    extra_headers: &[(String, String)],
+    body: Option<Vec<u8>>,
+    abort: Option<&AbortSignal>,
+) -> Result<Response> {
+    let credentials = match signing_credentials_for(config) {
+        Ok(credentials) => Some(credentials),
+        Err(MissingCredentials) if matches!(method, Method::GET | Method::HEAD) => None,
+        Err(err) => return Err(err),
     };
-    let secret_key = match get_current_config().expect("sk").secret_key {
-        Some(sk) => {
-            Some(sk)
-        },
-        None => None,
+    let build = || {
+        let headers = match &credentials {
+            Some(credentials) => generate_signed_headers(
+                &signing_date(),
+                credentials,
+                method.as_str(),
+                &url,
+                extra_headers,
+                body.as_deref(),
+            ).ok_or(Signing)?,
+            None => generate_headers(&signing_date()),
+        };
+        let builder = RequestBuilder::new(&url).method(method.clone()).headers(headers).abort_signal(abort);
+        match &body {
+            Some(bytes) => {
+                let u = Uint8Array::new_with_length(bytes.len() as u32);
+                u.copy_from(bytes);
+                builder.body(u)
+            },
+            None => builder.build(),
+        }.map_err(|err| Fetch(format!("{}", err)))
     };
-    Some(AWSCredentials::new(access_key.expect("ak"), secret_key.expect("sk")))
-}
-
-pub async fn upload_image_1(source: JsValue, key: String) -> Result<String> {
-    let creds = aws_credentials().expect("credential expected");
-    let result = upload_to_s3(creds.access_key, creds.secret_key, region(), bucket_name(), key, source).await;
-    let converted = result.as_string().expect("upload result exists");
-    if converted == "error" {
-        Err(UploadError("".to_string()))
-    } else {
-        Ok("ok".to_string())
-    }
-}
-
-pub async fn upload_object(source: JsValue, _prefix: String, object_name: String) -> bool {
-    let s3_bucket_name = get_current_config().expect("config expected").s3_bucket_name();
-    let region = get_current_config().expect("config expected").region;
-    let secret_key = get_current_config().expect("config expected").secret_key.expect("sk");
-    let access_key = get_current_config().expect("config expected").access_key.expect("ak");
-    let result = upload_to_s3(access_key, secret_key, region, s3_bucket_name, object_name, source).await;
-    let converted = result.as_string().expect("upload result exists");
-    if converted == "error" {
-        false
-    } else {
-        true
-    }
-}
-
-pub async fn rename_image(old_filename: String, new_filename: String, set_error: RwSignal<Option<String>>) {
-    let current_image = get_image(old_filename.clone()).await;
-    match current_image {
-        Some(ci) => {
-            let u = Uint8Array::new_with_length(ci.len() as u32);
-            u.copy_from(&ci);
-            let _delete_result = delete_object(format!("images/{}", old_filename), set_error).await;
-            let _upload_result = upload_object(u.into(), "".to_string(), format!("images/{}", new_filename)).await;
-        },
-        None => ()
+    send_with_retry(build, is_idempotent(method.as_str(), extra_headers), abort).await
+}
+
+/// Uploads into a site's inbox with a guest's signed POST policy, no credentials needed.
+pub async fn post_guest_upload(grant: &GuestUploadGrant, filename: String, body: Vec<u8>) -> Result<()> {
+    let content_type = match image::guess_format(&body) {
+        Ok(format) => format.to_mime_type(),
+        Err(_) => "image/jpeg",
+    };
+    let form = FormData::new().map_err(|_| UploadError("unable to create form".to_string()))?;
+    let key = format!("{}{}", grant.key_prefix, filename);
+    let fields = [
+        ("key", key.as_str()),
+        ("Content-Type", content_type),
+        ("x-amz-algorithm", "AWS4-HMAC-SHA256"),
+        ("x-amz-credential", grant.credential.as_str()),
+        ("x-amz-date", grant.date.as_str()),
+        ("policy", grant.policy.as_str()),
+        ("x-amz-signature", grant.signature.as_str()),
+    ];
+    for (name, value) in fields {
+        form.append_with_str(name, value).map_err(|_| UploadError(format!("unable to add {}", name)))?;
+    }
+    if let Some(token) = &grant.security_token {
+        form.append_with_str("x-amz-security-token", token).map_err(|_| UploadError("unable to add the security token".to_string()))?;
     }
+    // the file must be the last field in the form
+    let u = Uint8Array::new_with_length(body.len() as u32);
+    u.copy_from(&body);
+    let parts = Array::of1(&u);
+    let options = BlobPropertyBag::new();
+    options.set_type(content_type);
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|_| UploadError("unable to create file".to_string()))?;
+    form.append_with_blob_and_filename("file", &blob, &filename)
+        .map_err(|_| UploadError("unable to add file".to_string()))?;
+
+    let build = || RequestBuilder::new(&grant.upload_url())
+        .method(Method::POST)
+        .body(form.clone())
+        .map_err(|err| Fetch(format!("{}", err)));
+    send_with_retry(build, false, None).await?;
+    Ok(())
 }
 
-pub async fn update_comment(comment_text: String, e_tag: String, set_error: RwSignal<Option<String>>) {
-    set_error.set(None);
-    let s3_bucket_name = get_current_config().expect("config expected").s3_bucket_name();
-    let region = get_current_config().expect("config expected").region;
-    let secret_key = get_current_config().expect("config expected").secret_key.expect("sk");
-    let access_key = get_current_config().expect("config expected").access_key.expect("ak");
-    let key = format!("comments/{}", e_tag);
-    let result = upload_to_s3(access_key, secret_key, region, s3_bucket_name, key, comment_text.into()).await;
-    let converted = result.as_string().expect("result exists");
-    if converted == "error" {
-        set_error.set(Some("error on update comment".to_string()));
+/// Uploads an image with its stable id, in parts when it is large, reporting the bytes sent so far.
+pub async fn upload_image_1(store: &dyn ObjectStore, source: Vec<u8>, key: String, id: &str, on_progress: &dyn Fn(usize, usize), abort: Option<AbortSignal>) -> Result<String> {
+    match store.upload(&key, source, id, on_progress, abort).await {
+        Ok(_) => Ok("ok".to_string()),
+        Err(Cancelled) => Err(Cancelled),
+        Err(err) => Err(UploadError(format!("{}", err))),
     }
 }
 
+/// Renames an image with a copy within the store. The original, and its caption, are only
+/// removed once the copy is confirmed, so a failed rename leaves the image where it was.
+pub async fn rename_image(store: &dyn ObjectStore, old_filename: &str, new_filename: &str, e_tag: &str) -> std::result::Result<(), String> {
+    if old_filename == new_filename {
+        return Ok(());
+    }
+    let from = format!("images/{}", old_filename);
+    let to = format!("images/{}", new_filename);
+    // the copy keeps the image's id, so its caption still belongs to it
+    let new_e_tag = store.copy(&from, &to, Some(e_tag)).await
+        .map_err(|err| format!("Unable to rename '{}', the image was not changed. {}", old_filename, err))?;
+    // the copy's Content-Disposition still names the original
+    let new_e_tag = match store.fix_headers(&to).await {
+        Ok(e_tag) => e_tag,
+        Err(err) => {
+            log::info!("unable to update the headers of {}: {}", to, err);
+            new_e_tag
+        },
+    };
+    let mut result = Ok(());
+    if let Err(err) = store.delete(&from, Some(e_tag)).await {
+        result = Err(format!("'{}' was copied to '{}' but the original could not be removed. {}", old_filename, new_filename, err));
+    }
+    if let Err(err) = update_manifest(store, |manifest| manifest.rename(&from, &to, &new_e_tag)).await {
+        result = Err(format!("'{}' was renamed but the gallery could not be updated. {}", old_filename, err));
+    }
+    result
+}
+
+/// The caption of the image at `key` with the ETag it was read with, for editing it.
+pub async fn read_caption(store: &dyn ObjectStore, key: &str) -> Result<Versioned<Caption>> {
+    let (id, _) = image_id(store, key).await?;
+    read_versioned(store, &format!("comments/{}", id), Caption::parse, Caption::default()).await
+}
+
+/// Stores the caption of the image at `key`, keyed by the image's id, while `condition` holds.
+/// Returns the caption's new ETag. A caption changed since it was read fails with
+/// `PreconditionFailed`.
+pub async fn update_comment(store: &dyn ObjectStore, caption: &Caption, key: &str, condition: &WriteCondition) -> Result<String> {
+    let (id, _) = image_id(store, key).await?;
+    let e_tag = store.put_if(&format!("comments/{}", id), caption.to_bytes(), condition).await?;
+    update_manifest(store, |manifest| manifest.set_caption(&id, caption)).await?;
+    Ok(e_tag)
+}
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Error, Serialize, Deserialize, Clone)]
 pub enum Error {
-    #[error("Failed to fetch")]
+    #[error("Failed to fetch: {0}")]
     Fetch(String),
     #[error("Upload Error")]
     UploadError(String),
     #[error("Local Config Error")]
     LocalConfigError,
+    #[error("Credentials Required")]
+    MissingCredentials,
+    #[error("Unable to sign request")]
+    Signing,
+    #[error("Request failed with status {0}")]
+    Status(u16, String),
+    #[error("Cancelled")]
+    Cancelled,
+    #[error("Access denied, check the bucket policy and the site's credentials: {0}")]
+    AccessDenied(String),
+    #[error("The bucket '{0}' does not exist")]
+    NoSuchBucket(String),
+    #[error("The object does not exist")]
+    NoSuchKey,
+    #[error("S3 is limiting the request rate, try again shortly")]
+    SlowDown,
+    // S3's time as unix milliseconds, when it was sent
+    #[error("The device's clock differs too much from S3's")]
+    RequestTimeTooSkewed(Option<i64>),
+    #[error("The session credentials have expired")]
+    ExpiredToken,
+    #[error("Browser storage error: {0}")]
+    LocalStorage(String),
+    #[error("The object has changed since it was read")]
+    PreconditionFailed,
+    #[error("The object does not match its checksum, it may be corrupt")]
+    ChecksumMismatch,
+    #[error("{code} ({status}): {message}")]
+    S3 { status: u16, code: String, message: String },
+}
+
+impl Error {
+    /// Failures which may succeed when the request is sent again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Fetch(_) | Error::SlowDown | RequestTimeTooSkewed(_) => true,
+            Error::S3 { status, .. } | Status(status, _) => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Whether a request which failed with this error may be sent again. A failed fetch may
+    /// have lost the response of a request which was applied, so it is only retried when the
+    /// request is idempotent, see `is_idempotent`.
+    pub fn is_retryable(&self, idempotent: bool) -> bool {
+        match self {
+            Fetch(_) => idempotent,
+            _ => self.is_transient(),
+        }
+    }
 }
 
 impl From<local_config::ConfigError> for Error {
@@ -202,3 +399,94 @@ impl From<local_config::ConfigError> for Error {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+    use crate::caption::Caption;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::{block_on, ObjectStore, WriteCondition};
+
+    #[test]
+    fn test_rename_image() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            let e_tag = store.head("images/a.jpg").await.unwrap().e_tag.unwrap();
+            let caption = Caption { title: "a caption".to_string(), ..Caption::default() };
+            update_comment(&store, &caption, "images/a.jpg", &WriteCondition::Absent).await.unwrap();
+
+            rename_image(&store, "a.jpg", "b.jpg", &e_tag).await.unwrap();
+            assert!(store.get("images/a.jpg").await.is_err());
+            assert_eq!(store.get("images/b.jpg").await.unwrap(), vec![1]);
+            // the copy keeps its id, so the caption still belongs to it
+            let id = store.head("images/b.jpg").await.unwrap().id.unwrap();
+            assert_eq!(store.get(&format!("comments/{}", id)).await.unwrap(), caption.to_bytes());
+        });
+    }
+
+    #[test]
+    fn test_rename_changed_image_is_refused() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            let e_tag = store.head("images/a.jpg").await.unwrap().e_tag.unwrap();
+            store.put("images/a.jpg", vec![2]).await.unwrap();
+
+            assert!(rename_image(&store, "a.jpg", "b.jpg", &e_tag).await.is_err());
+            assert_eq!(store.get("images/a.jpg").await.unwrap(), vec![2]);
+            assert!(store.get("images/b.jpg").await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_caption_conflict() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            let read = read_caption(&store, "images/a.jpg").await.unwrap();
+            assert_eq!(read.e_tag, None);
+            let mine = Caption { title: "mine".to_string(), ..Caption::default() };
+            let e_tag = update_comment(&store, &mine, "images/a.jpg", &read.condition()).await.unwrap();
+
+            // another editor saved first, the stale write is refused
+            let theirs = Caption { title: "theirs".to_string(), ..Caption::default() };
+            assert!(matches!(update_comment(&store, &theirs, "images/a.jpg", &read.condition()).await, Err(Error::PreconditionFailed)));
+            let remote = read_caption(&store, "images/a.jpg").await.unwrap();
+            assert_eq!(remote, Versioned { value: mine.clone(), e_tag: Some(e_tag) });
+            update_comment(&store, &theirs, "images/a.jpg", &remote.condition()).await.unwrap();
+            assert_eq!(read_caption(&store, "images/a.jpg").await.unwrap().value, theirs);
+        });
+    }
+
+    #[test]
+    fn test_settings_conflict() {
+        let store = MemoryStore::new();
+        block_on(async {
+            let read = read_public_site_settings(&store).await.unwrap();
+            let mut settings = read.value.clone();
+            settings.app_title = "Photos".to_string();
+            set_public_site_settings(&store, &settings, &read.condition()).await.unwrap();
+            assert!(matches!(set_public_site_settings(&store, &settings, &read.condition()).await, Err(Error::PreconditionFailed)));
+            assert_eq!(read_public_site_settings(&store).await.unwrap().value, settings);
+        });
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let header = |name: &str, value: &str| vec![(name.to_string(), value.to_string())];
+        assert!(is_idempotent("GET", &[]));
+        assert!(is_idempotent("PUT", &header("Content-Type", "image/jpeg")));
+        assert!(!is_idempotent("PUT", &header("If-None-Match", "*")));
+        assert!(!is_idempotent("PUT", &header("x-amz-copy-source-if-match", "\"abc\"")));
+        assert!(!is_idempotent("DELETE", &header("if-match", "\"abc\"")));
+        assert!(!is_idempotent("POST", &[]));
+        assert!(!is_idempotent("MOVE", &[]));
+
+        let lost = Error::Fetch("network error".to_string());
+        assert!(lost.is_retryable(true));
+        assert!(!lost.is_retryable(false));
+        assert!(Error::SlowDown.is_retryable(false));
+        assert!(!Error::PreconditionFailed.is_retryable(true));
+    }
+}
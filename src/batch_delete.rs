@@ -0,0 +1,134 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use gloo_net::http::Method;
+use quick_xml::escape::escape;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::api::{send_site_request, Error};
+use crate::local_config::SiteConfig;
+
+// The DeleteObjects API accepts at most 1000 keys per request.
+pub const MAX_KEYS_PER_REQUEST: usize = 1000;
+
+/// The result of deleting one key, `error` is None when the key was deleted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeleteOutcome {
+    pub key: String,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+enum DeleteResultEntry {
+    Deleted {
+        #[serde(rename(deserialize = "Key"))]
+        key: String,
+    },
+    Error {
+        #[serde(rename(deserialize = "Key"))]
+        key: String,
+        #[serde(rename(deserialize = "Code"))]
+        code: String,
+        #[serde(rename(deserialize = "Message"))]
+        message: String,
+    },
+}
+
+fn default_entries() -> Vec<DeleteResultEntry> {
+    vec![]
+}
+
+#[derive(Deserialize)]
+struct DeleteResult {
+    #[serde(rename(deserialize = "$value"), default = "default_entries")]
+    entries: Vec<DeleteResultEntry>,
+}
+
+fn delete_request_body(keys: &[String]) -> String {
+    let objects = keys.iter()
+        .map(|key| format!("<Object><Key>{}</Key></Object>", escape(key)))
+        .collect::<String>();
+    format!("<Delete><Quiet>false</Quiet>{}</Delete>", objects)
+}
+
+fn parse_delete_result(xml: &str) -> Result<Vec<DeleteOutcome>, Error> {
+    let result = quick_xml::de::from_str::<DeleteResult>(xml)
+        .map_err(|err| Error::Fetch(format!("unexpected response: {}", err)))?;
+    Ok(result.entries.into_iter().map(|entry| match entry {
+        DeleteResultEntry::Deleted { key } => DeleteOutcome { key, error: None },
+        DeleteResultEntry::Error { key, code, message } => DeleteOutcome { key, error: Some(format!("{}: {}", code, message)) },
+    }).collect())
+}
+
+async fn send_delete_request(config: &SiteConfig, keys: &[String]) -> Result<Vec<DeleteOutcome>, Error> {
+    let body = delete_request_body(keys).into_bytes();
+    // S3 requires a checksum of the request body for DeleteObjects
+    let headers = vec![
+        ("x-amz-sdk-checksum-algorithm".to_string(), "SHA256".to_string()),
+        ("x-amz-checksum-sha256".to_string(), STANDARD.encode(Sha256::digest(&body))),
+    ];
+    let url = format!("{}?delete", config.bucket_url());
+    let response = send_site_request(config, Method::POST, url, &headers, Some(body), None).await?;
+    let xml = response.text().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+    parse_delete_result(&xml)
+}
+
+/// Deletes `keys` from `config`'s bucket in batches, returning an outcome for every key.
+pub async fn delete_objects(config: &SiteConfig, keys: Vec<String>) -> Vec<DeleteOutcome> {
+    let mut outcomes: Vec<DeleteOutcome> = vec![];
+    for batch in keys.chunks(MAX_KEYS_PER_REQUEST) {
+        match send_delete_request(config, batch).await {
+            Ok(mut results) => for key in batch {
+                let outcome = match results.iter().position(|o| &o.key == key) {
+                    Some(idx) => results.swap_remove(idx),
+                    None => DeleteOutcome { key: key.clone(), error: Some("missing from the response".to_string()) },
+                };
+                outcomes.push(outcome);
+            },
+            Err(err) => outcomes.extend(batch.iter().map(|key| DeleteOutcome { key: key.clone(), error: Some(format!("{}", err)) })),
+        }
+    }
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch_delete::*;
+
+    #[test]
+    fn test_delete_request_body() {
+        let keys = vec!["images/a.jpg".to_string(), "images/b&c.jpg".to_string()];
+        assert_eq!(
+            delete_request_body(&keys),
+            "<Delete><Quiet>false</Quiet><Object><Key>images/a.jpg</Key></Object><Object><Key>images/b&amp;c.jpg</Key></Object></Delete>"
+        );
+    }
+
+    #[test]
+    fn test_parse_delete_result() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DeleteResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Deleted>
+    <Key>images/a.jpg</Key>
+  </Deleted>
+  <Error>
+    <Key>images/b.jpg</Key>
+    <Code>AccessDenied</Code>
+    <Message>Access Denied</Message>
+  </Error>
+  <Deleted>
+    <Key>comments/"abc"</Key>
+  </Deleted>
+</DeleteResult>"#;
+        assert_eq!(parse_delete_result(xml).unwrap(), vec![
+            DeleteOutcome { key: "images/a.jpg".to_string(), error: None },
+            DeleteOutcome { key: "images/b.jpg".to_string(), error: Some("AccessDenied: Access Denied".to_string()) },
+            DeleteOutcome { key: "comments/\"abc\"".to_string(), error: None },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_empty_delete_result() {
+        let xml = r#"<DeleteResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/"></DeleteResult>"#;
+        assert_eq!(parse_delete_result(xml).unwrap(), vec![]);
+    }
+}
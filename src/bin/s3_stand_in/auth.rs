@@ -0,0 +1,204 @@
+use secrecy::SecretString;
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+
+use crate::awssigv4::{_get_auth_header, presigned_url, SigningCredentials};
+use crate::http::{Request, S3Error};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+// S3 refuses requests signed more than 15 minutes from its own clock.
+const MAX_SKEW_MINUTES: i64 = 15;
+
+/// The one access key the stand-in accepts.
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub region: String,
+}
+
+impl Credentials {
+    fn signing_credentials(&self) -> SigningCredentials {
+        SigningCredentials::new(self.access_key.clone(), SecretString::new(self.secret_key.clone()), self.region.clone())
+    }
+}
+
+fn signature_mismatch() -> S3Error {
+    S3Error::new(403, "SignatureDoesNotMatch", "The request signature we calculated does not match the signature you provided.")
+}
+
+fn parse_amz_date(value: &str) -> Result<OffsetDateTime, S3Error> {
+    PrimitiveDateTime::parse(value, format_description!("[year][month][day]T[hour][minute][second]Z"))
+        .map(|date| date.assume_utc())
+        .map_err(|_| S3Error::access_denied("X-Amz-Date must be in the ISO8601 basic format"))
+}
+
+// Access key, date and region from a credential such as `AKID/20231012/us-east-1/s3/aws4_request`.
+fn check_credential(credential: &str, credentials: &Credentials) -> Result<(), S3Error> {
+    let parts = credential.split('/').collect::<Vec<&str>>();
+    match parts.as_slice() {
+        [access_key, _, _, _, _] if *access_key != credentials.access_key =>
+            Err(S3Error::new(403, "InvalidAccessKeyId", "The AWS Access Key Id you provided does not exist in our records.")),
+        [_, _, region, "s3", "aws4_request"] if *region != credentials.region =>
+            Err(S3Error::new(400, "AuthorizationHeaderMalformed", format!("the region '{}' is wrong; expecting '{}'", region, credentials.region))
+                .with_field("Region", &credentials.region)),
+        [_, _, _, "s3", "aws4_request"] => Ok(()),
+        _ => Err(S3Error::new(400, "AuthorizationHeaderMalformed", format!("malformed credential {}", credential))),
+    }
+}
+
+fn check_skew(date: OffsetDateTime, now: OffsetDateTime) -> Result<(), S3Error> {
+    match (date - now).abs() > Duration::minutes(MAX_SKEW_MINUTES) {
+        true => Err(S3Error::new(403, "RequestTimeTooSkewed", "The difference between the request time and the current time is too large.")
+            .with_field("ServerTime", now.format(&Rfc3339).unwrap_or_default())),
+        false => Ok(()),
+    }
+}
+
+fn signature(authorization: &str) -> Option<&str> {
+    authorization.split(',').find_map(|part| part.trim().strip_prefix("Signature="))
+}
+
+/// Checks the request's signature, from its Authorization header or a presigned URL. `url` is the URL the
+/// client signed and `host` the Host it signed, which differ from the request's when it came through a proxy.
+/// Returns false for an anonymous request.
+pub fn authenticate(request: &Request, url: &str, host: &str, credentials: &Credentials, now: OffsetDateTime) -> Result<bool, S3Error> {
+    match (request.header("Authorization"), request.param("X-Amz-Signature")) {
+        (Some(authorization), _) => check_header_signature(request, authorization, url, host, credentials, now).map(|_| true),
+        (None, Some(signature)) => check_presigned_url(request, &signature, url, credentials, now).map(|_| true),
+        (None, None) => Ok(false),
+    }
+}
+
+fn check_header_signature(request: &Request, authorization: &str, url: &str, host: &str, credentials: &Credentials, now: OffsetDateTime) -> Result<(), S3Error> {
+    let fields = match authorization.strip_prefix(ALGORITHM) {
+        Some(fields) => fields.split(',').map(|field| field.trim()).collect::<Vec<&str>>(),
+        None => return Err(S3Error::new(400, "InvalidArgument", "Unsupported Authorization Type")),
+    };
+    let field = |name: &str| fields.iter().find_map(|f| f.strip_prefix(name)).map(|value| value.to_string());
+    let (credential, signed_headers) = match (field("Credential="), field("SignedHeaders=")) {
+        (Some(credential), Some(signed_headers)) => (credential, signed_headers),
+        _ => return Err(S3Error::new(400, "AuthorizationHeaderMalformed", "The authorization header is malformed")),
+    };
+    check_credential(&credential, credentials)?;
+    let date = parse_amz_date(request.header("X-Amz-Date").unwrap_or(""))?;
+    check_skew(date, now)?;
+
+    let payload_hash = request.header("X-Amz-Content-Sha256").unwrap_or(UNSIGNED_PAYLOAD).to_string();
+    if payload_hash != UNSIGNED_PAYLOAD && payload_hash != hex::encode(Sha256::digest(&request.body)) {
+        return Err(S3Error::new(400, "XAmzContentSHA256Mismatch", "The provided 'x-amz-content-sha256' header does not match what was computed."));
+    }
+
+    let mut headers: Vec<(String, String)> = vec![];
+    for name in signed_headers.split(';') {
+        let value = match name {
+            "host" => Some(host),
+            _ => request.header(name),
+        };
+        match value {
+            Some(value) => headers.push((name.to_string(), value.to_string())),
+            None => return Err(signature_mismatch()),
+        }
+    }
+    let expected = _get_auth_header(&credentials.signing_credentials(), "s3", &request.method, url, &headers, &payload_hash, &date)
+        .ok_or_else(signature_mismatch)?;
+    match signature(&expected).is_some() && signature(&expected) == signature(authorization) {
+        true => Ok(()),
+        false => Err(signature_mismatch()),
+    }
+}
+
+// Presigned URLs are signed with the X-Amz-* parameters appended to the URL, see `presigned_url`.
+fn check_presigned_url(request: &Request, signature: &str, url: &str, credentials: &Credentials, now: OffsetDateTime) -> Result<(), S3Error> {
+    let param = |name: &str| request.param(name).ok_or_else(|| S3Error::access_denied(&format!("Query-string authentication requires {}", name)));
+    if param("X-Amz-Algorithm")? != ALGORITHM {
+        return Err(S3Error::new(400, "InvalidArgument", "Unsupported X-Amz-Algorithm"));
+    }
+    check_credential(&param("X-Amz-Credential")?, credentials)?;
+    let date = parse_amz_date(&param("X-Amz-Date")?)?;
+    let expires = param("X-Amz-Expires")?.parse::<u32>().map_err(|_| S3Error::access_denied("X-Amz-Expires must be a number"))?;
+    if now > date + Duration::seconds(expires as i64) {
+        return Err(S3Error::access_denied("Request has expired"));
+    }
+
+    let (base, query) = url.split_once('?').unwrap_or((url, ""));
+    let unsigned_query = query.split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("X-Amz-"))
+        .collect::<Vec<&str>>()
+        .join("&");
+    let unsigned_url = match unsigned_query.is_empty() {
+        true => base.to_string(),
+        false => format!("{}?{}", base, unsigned_query),
+    };
+    let expected = presigned_url(&credentials.signing_credentials(), &request.method, &unsigned_url, expires, &date)
+        .ok_or_else(signature_mismatch)?;
+    match expected.rsplit_once("X-Amz-Signature=") {
+        Some((_, expected_signature)) if expected_signature == signature => Ok(()),
+        _ => Err(signature_mismatch()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretString;
+    use time::macros::datetime;
+    use time::Duration;
+
+    use crate::auth::{authenticate, Credentials};
+    use crate::awssigv4::{presigned_url, signed_request_headers, SigningCredentials};
+    use crate::http::Request;
+
+    fn credentials() -> Credentials {
+        Credentials { access_key: "weblum".to_string(), secret_key: "weblum-secret".to_string(), region: "us-east-1".to_string() }
+    }
+
+    fn signing_credentials(secret_key: &str) -> SigningCredentials {
+        SigningCredentials::new("weblum".to_string(), SecretString::new(secret_key.to_string()), "us-east-1".to_string())
+    }
+
+    fn request(method: &str, url: &str, headers: Vec<(String, String)>, body: &[u8]) -> Request {
+        let target = url.trim_start_matches("http://127.0.0.1:9000").to_string();
+        let mut headers = headers;
+        headers.push(("Host".to_string(), "127.0.0.1:9000".to_string()));
+        Request { method: method.to_string(), target, headers, body: body.to_vec() }
+    }
+
+    #[test]
+    fn test_signed_request() {
+        let date = datetime!(2023-10-12 14:48:09 UTC);
+        let url = "http://127.0.0.1:9000/photos/images/a%20b.jpg";
+        let headers = signed_request_headers(&signing_credentials("weblum-secret"), "PUT", url, &[], Some(b"image"), &date).unwrap();
+        let put = request("PUT", url, headers.clone(), b"image");
+        assert!(authenticate(&put, url, "127.0.0.1:9000", &credentials(), date + Duration::minutes(1)).unwrap());
+
+        let altered = request("PUT", url, headers.clone(), b"other");
+        assert_eq!(authenticate(&altered, url, "127.0.0.1:9000", &credentials(), date).err().unwrap().code, "XAmzContentSHA256Mismatch");
+        assert_eq!(authenticate(&put, url, "127.0.0.1:8080", &credentials(), date).err().unwrap().code, "SignatureDoesNotMatch");
+        assert_eq!(authenticate(&put, url, "127.0.0.1:9000", &credentials(), date + Duration::hours(1)).err().unwrap().code, "RequestTimeTooSkewed");
+
+        let wrong_secret = signed_request_headers(&signing_credentials("guess"), "PUT", url, &[], Some(b"image"), &date).unwrap();
+        let forged = request("PUT", url, wrong_secret, b"image");
+        assert_eq!(authenticate(&forged, url, "127.0.0.1:9000", &credentials(), date).err().unwrap().code, "SignatureDoesNotMatch");
+    }
+
+    #[test]
+    fn test_presigned_url() {
+        let date = datetime!(2023-10-12 14:48:09 UTC);
+        let url = presigned_url(&signing_credentials("weblum-secret"), "GET", "http://127.0.0.1:9000/photos/images/a.jpg", 3600, &date).unwrap();
+        let get = request("GET", &url, vec![], b"");
+        assert!(authenticate(&get, &url, "127.0.0.1:9000", &credentials(), date + Duration::minutes(59)).unwrap());
+        assert_eq!(authenticate(&get, &url, "127.0.0.1:9000", &credentials(), date + Duration::minutes(61)).err().unwrap().code, "AccessDenied");
+        let other_key = url.replace("a.jpg", "b.jpg");
+        let get_other = request("GET", &other_key, vec![], b"");
+        assert_eq!(authenticate(&get_other, &other_key, "127.0.0.1:9000", &credentials(), date).err().unwrap().code, "SignatureDoesNotMatch");
+    }
+
+    #[test]
+    fn test_anonymous_request() {
+        let url = "http://127.0.0.1:9000/photos?list-type=2&prefix=images%2F";
+        let list = request("GET", url, vec![], b"");
+        assert!(!authenticate(&list, url, "127.0.0.1:9000", &credentials(), datetime!(2023-10-12 14:48:09 UTC)).unwrap());
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::{self, BufRead, Write};
+
+use quick_xml::escape::escape;
+
+// Request bodies larger than this are refused, S3 limits a single PUT to 5 GiB.
+const MAX_BODY: usize = 512 * 1024 * 1024;
+
+/// A request as read from the connection, `target` is the raw path and query.
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The first value of the header `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn path(&self) -> &str {
+        self.target.split_once('?').map(|(path, _)| path).unwrap_or(&self.target)
+    }
+
+    pub fn raw_query(&self) -> &str {
+        self.target.split_once('?').map(|(_, query)| query).unwrap_or("")
+    }
+
+    /// The decoded query parameters, in order. Parameters without a value, e.g. `?uploads`, have an empty one.
+    pub fn query(&self) -> Vec<(String, String)> {
+        self.raw_query().split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                (decode(name), decode(value))
+            })
+            .collect()
+    }
+
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.query().into_iter().find(|(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    pub fn has_param(&self, name: &str) -> bool {
+        self.param(name).is_some()
+    }
+}
+
+// '+' is a space in form encoded queries, S3 accepts either.
+fn decode(value: &str) -> String {
+    let spaced = value.replace('+', " ");
+    urlencoding::decode(&spaced).map(|decoded| decoded.into_owned()).unwrap_or(spaced)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads the next request on a connection, None when the client has closed it.
+pub fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut headers: Vec<(String, String)> = vec![];
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("connection closed in the headers"));
+        }
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+        match header.split_once(':') {
+            Some((name, value)) => headers.push((name.trim().to_string(), value.trim().to_string())),
+            None => return Err(invalid("malformed header")),
+        }
+    }
+
+    let mut request = Request { method, target, headers, body: vec![] };
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(invalid("chunked request bodies are not supported"));
+    }
+    let length = match request.header("Content-Length") {
+        Some(length) => length.parse::<usize>().map_err(|_| invalid("malformed Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Err(invalid("request body too large"));
+    }
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+    Ok(Some(request))
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self { status, headers: vec![], body: vec![] }
+    }
+
+    pub fn xml(status: u16, xml: String) -> Self {
+        Self::new(status)
+            .with_header("Content-Type", "application/xml")
+            .with_body(format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}", xml).into_bytes())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Writes the response, a response to HEAD keeps its Content-Length but is sent without the body.
+    pub fn write_to(&self, writer: &mut impl Write, head_only: bool) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !self.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Content-Length")) {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        if !head_only {
+            writer.write_all(&self.body)?;
+        }
+        writer.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        _ => "",
+    }
+}
+
+/// An S3 error, sent as `<Error><Code>..</Code><Message>..</Message></Error>`.
+#[derive(Debug)]
+pub struct S3Error {
+    pub status: u16,
+    pub code: &'static str,
+    pub message: String,
+    // additional elements, such as BucketName for NoSuchBucket
+    pub fields: Vec<(&'static str, String)>,
+}
+
+impl S3Error {
+    pub fn new(status: u16, code: &'static str, message: impl ToString) -> Self {
+        Self { status, code, message: message.to_string(), fields: vec![] }
+    }
+
+    pub fn with_field(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.fields.push((name, value.to_string()));
+        self
+    }
+
+    pub fn access_denied(message: &str) -> Self {
+        Self::new(403, "AccessDenied", message)
+    }
+
+    pub fn no_such_key(key: &str) -> Self {
+        Self::new(404, "NoSuchKey", "The specified key does not exist.").with_field("Key", key)
+    }
+
+    pub fn precondition_failed() -> Self {
+        Self::new(412, "PreconditionFailed", "At least one of the pre-conditions you specified did not hold")
+    }
+
+    pub fn not_implemented(message: &str) -> Self {
+        Self::new(501, "NotImplemented", message)
+    }
+
+    pub fn internal(err: impl ToString) -> Self {
+        Self::new(500, "InternalError", err)
+    }
+
+    pub fn response(&self) -> Response {
+        let fields = self.fields.iter()
+            .map(|(name, value)| format!("<{}>{}</{}>", name, escape(value), name))
+            .collect::<String>();
+        Response::xml(self.status, format!("<Error><Code>{}</Code><Message>{}</Message>{}</Error>", self.code, escape(&self.message), fields))
+    }
+}
+
+impl From<io::Error> for S3Error {
+    fn from(err: io::Error) -> Self {
+        S3Error::internal(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use crate::http::{read_request, Response, S3Error};
+
+    #[test]
+    fn test_read_request() {
+        let raw = "PUT /photos/images/a%20b.jpg?partNumber=2&uploadId=x%2By HTTP/1.1\r\nHost: 127.0.0.1:9000\r\nContent-Length: 4\r\n\r\nbodyGET / HTTP/1.1\r\n\r\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let request = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path(), "/photos/images/a%20b.jpg");
+        assert_eq!(request.param("uploadId"), Some("x+y".to_string()));
+        assert_eq!(request.header("host"), Some("127.0.0.1:9000"));
+        assert_eq!(request.body, b"body");
+        // the next request on the same connection
+        assert_eq!(read_request(&mut reader).unwrap().unwrap().method, "GET");
+        assert!(read_request(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_error_response() {
+        let response = S3Error::no_such_key("comments/\"abc\"").response();
+        assert_eq!(response.status, 404);
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.ends_with("<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message><Key>comments/&quot;abc&quot;</Key></Error>"));
+    }
+
+    #[test]
+    fn test_head_response_has_no_body() {
+        let mut written: Vec<u8> = vec![];
+        Response::new(200).with_body(b"abc".to_vec()).write_to(&mut written, true).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n");
+    }
+}
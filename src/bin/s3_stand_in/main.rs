@@ -0,0 +1,736 @@
+//! A stand-in for S3, serving a directory over the part of the S3 REST API Weblum uses, for development
+//! and integration tests without a bucket. Each directory under the root is a bucket, and each file in it an
+//! object. Writes must be signed with the stand-in's access key, reads are also allowed anonymously as for a
+//! public site.
+//!
+//! `cargo run --bin s3_stand_in -- --root s3-data --bucket photos --max-keys 2`
+//!
+//! prints a site config for the app. A small `--max-keys` splits listings into pages, exercising the app's
+//! pagination. See the README for running it behind `trunk serve`.
+
+mod auth;
+mod http;
+mod storage;
+
+// The app's signing code, which checks the requests it signed. Much of it is only used in the browser.
+#[allow(dead_code)]
+#[path = "../../awssigv4.rs"]
+mod awssigv4;
+
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use quick_xml::escape::escape;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::auth::{authenticate, Credentials};
+use crate::http::{read_request, Request, Response, S3Error};
+use crate::storage::{Metadata, ObjectInfo, Storage};
+
+// S3 lists at most 1000 keys per page.
+const MAX_KEYS: usize = 1000;
+
+const USAGE: &str = "Usage: s3_stand_in [--root DIR] [--address HOST:PORT] [--bucket NAME] [--access-key KEY] [--secret-key KEY]
+                   [--region REGION] [--max-keys N] [--public-url URL]
+
+  --root        directory holding the buckets, one directory each (default s3-data)
+  --address     address to listen on (default 127.0.0.1:9000)
+  --bucket      bucket created at start up and used in the printed site config (default photos)
+  --access-key  access key requests must be signed with (default weblum)
+  --secret-key  secret key requests must be signed with (default weblum-secret)
+  --region      region requests must be signed for (default us-east-1)
+  --max-keys    largest page of a listing (default 1000)
+  --public-url  URL the app reaches the stand-in at when it is behind a proxy, e.g. http://127.0.0.1:8080/s3";
+
+struct Options {
+    root: PathBuf,
+    address: String,
+    bucket: String,
+    credentials: Credentials,
+    max_keys: usize,
+    public_url: Option<Url>,
+}
+
+impl Options {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            root: PathBuf::from("s3-data"),
+            address: "127.0.0.1:9000".to_string(),
+            bucket: "photos".to_string(),
+            credentials: Credentials {
+                access_key: "weblum".to_string(),
+                secret_key: "weblum-secret".to_string(),
+                region: "us-east-1".to_string(),
+            },
+            max_keys: MAX_KEYS,
+            public_url: None,
+        };
+        let mut args = args;
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or(format!("{} needs a value", flag))?;
+            match flag.as_str() {
+                "--root" => options.root = PathBuf::from(value),
+                "--address" => options.address = value,
+                "--bucket" => options.bucket = value,
+                "--access-key" => options.credentials.access_key = value,
+                "--secret-key" => options.credentials.secret_key = value,
+                "--region" => options.credentials.region = value,
+                "--max-keys" => options.max_keys = value.parse().ok().filter(|n| (1..=MAX_KEYS).contains(n))
+                    .ok_or("--max-keys must be between 1 and 1000".to_string())?,
+                "--public-url" => options.public_url = Some(Url::parse(&value).map_err(|err| format!("--public-url: {}", err))?),
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The endpoint the app is configured with, the stand-in's own address unless it is behind a proxy.
+    fn endpoint(&self) -> String {
+        match &self.public_url {
+            Some(url) => url.as_str().trim_end_matches('/').to_string(),
+            None => format!("http://{}", self.address),
+        }
+    }
+
+    /// The site config for the app, as JSON and encoded as a config token.
+    fn site_config(&self) -> (String, String) {
+        let json = serde_json::json!({
+            "id": "s3-stand-in",
+            "b": self.bucket,
+            "r": self.credentials.region,
+            "e": self.endpoint(),
+            "a": self.credentials.access_key,
+            "s": self.credentials.secret_key,
+        }).to_string();
+        let token = hex::encode(&json);
+        (json, token)
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteObject {
+    #[serde(rename(deserialize = "Key"))]
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct Delete {
+    #[serde(rename(deserialize = "Object"), default)]
+    objects: Vec<DeleteObject>,
+}
+
+#[derive(Deserialize)]
+struct CompletedPart {
+    #[serde(rename(deserialize = "PartNumber"))]
+    part_number: u32,
+    #[serde(rename(deserialize = "ETag"))]
+    e_tag: String,
+}
+
+#[derive(Deserialize)]
+struct CompleteMultipartUpload {
+    #[serde(rename(deserialize = "Part"), default)]
+    parts: Vec<CompletedPart>,
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(body: &[u8]) -> Result<T, S3Error> {
+    let xml = String::from_utf8_lossy(body);
+    quick_xml::de::from_str(&xml).map_err(|_| S3Error::new(400, "MalformedXML", "The XML you provided was not well-formed or did not validate against our published schema"))
+}
+
+fn decode_key(raw: &str) -> Result<String, S3Error> {
+    urlencoding::decode(raw)
+        .map(|key| key.into_owned())
+        .map_err(|_| S3Error::new(400, "InvalidURI", "Couldn't parse the specified URI."))
+}
+
+fn content_type(key: &str) -> &'static str {
+    match key.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).as_deref() {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+fn object_headers(response: Response, info: &ObjectInfo) -> Response {
+    response
+        .with_header("ETag", &info.e_tag)
+        .with_header("Last-Modified", &info.last_modified_http())
+}
+
+// Headers S3 stores with the object and sends back when it is read.
+const STORED_HEADERS: [&str; 3] = ["content-type", "cache-control", "content-disposition"];
+
+// The request's `x-amz-meta-*` and stored headers, which S3 keeps with the object.
+fn request_metadata(request: &Request) -> Metadata {
+    request.headers.iter()
+        .map(|(name, value)| (name.to_lowercase(), value.clone()))
+        .filter(|(name, _)| name.starts_with("x-amz-meta-") || STORED_HEADERS.contains(&name.as_str()))
+        .collect()
+}
+
+// The `x-amz-checksum-sha256` sent with a write, S3 refuses a body which does not match it.
+fn checked_checksum(request: &Request) -> Result<Option<&str>, S3Error> {
+    match request.header("x-amz-checksum-sha256") {
+        Some(checksum) if checksum != STANDARD.encode(Sha256::digest(&request.body)) =>
+            Err(S3Error::new(400, "BadDigest", "The SHA256 you specified did not match the calculated checksum.")),
+        checksum => Ok(checksum),
+    }
+}
+
+// S3 sends back the checksum a write was verified with.
+fn with_checksum(response: Response, checksum: Option<&str>) -> Response {
+    match checksum {
+        Some(checksum) => response.with_header("x-amz-checksum-sha256", checksum),
+        None => response,
+    }
+}
+
+// The first and last byte of a `Range: bytes=<first>-<last>` header, within an object of `size` bytes.
+fn byte_range(range: &str, size: usize) -> Result<(usize, usize), S3Error> {
+    let invalid = || S3Error::new(416, "InvalidRange", "The requested range is not satisfiable");
+    let (first, last) = range.strip_prefix("bytes=").and_then(|range| range.split_once('-')).ok_or_else(invalid)?;
+    let first = first.parse::<usize>().map_err(|_| invalid())?;
+    let last = match last {
+        "" => size.saturating_sub(1),
+        last => last.parse::<usize>().map_err(|_| invalid())?.min(size.saturating_sub(1)),
+    };
+    match first < size && first <= last {
+        true => Ok((first, last)),
+        false => Err(invalid()),
+    }
+}
+
+fn contents_xml(info: &ObjectInfo) -> String {
+    format!(
+        "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+        escape(&info.key), info.last_modified_iso(), escape(&info.e_tag), info.size,
+    )
+}
+
+fn optional_element(name: &str, value: Option<&str>) -> String {
+    value.map(|v| format!("<{}>{}</{}>", name, escape(v), name)).unwrap_or_default()
+}
+
+struct Server {
+    storage: Storage,
+    credentials: Credentials,
+    max_keys: usize,
+    public_url: Option<Url>,
+}
+
+impl Server {
+    // The path the app's requests start with when it is behind a proxy, e.g. "/s3".
+    fn base_path(&self) -> &str {
+        self.public_url.as_ref().map(|url| url.path().trim_end_matches('/')).unwrap_or("")
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let response = match request.method.as_str() {
+            "OPTIONS" => Ok(preflight(request)),
+            _ => self.route(request),
+        };
+        let response = response.unwrap_or_else(|err| err.response());
+        response
+            .with_header("Access-Control-Allow-Origin", request.header("Origin").unwrap_or("*"))
+            .with_header("Access-Control-Expose-Headers", "ETag, Last-Modified, x-amz-meta-weblum-id, x-amz-meta-weblum-sha256, x-amz-checksum-sha256")
+            .with_header("Vary", "Origin")
+    }
+
+    fn route(&self, request: &Request) -> Result<Response, S3Error> {
+        // the URL and host the client signed, as seen by the app when it is behind a proxy
+        let (url, host) = match &self.public_url {
+            Some(public_url) => {
+                let host = match public_url.port() {
+                    Some(port) => format!("{}:{}", public_url.host_str().unwrap_or_default(), port),
+                    None => public_url.host_str().unwrap_or_default().to_string(),
+                };
+                (format!("{}://{}{}", public_url.scheme(), host, request.target), host)
+            },
+            None => {
+                let host = request.header("Host").unwrap_or_default().to_string();
+                (format!("http://{}{}", host, request.target), host)
+            },
+        };
+        let signed = authenticate(request, &url, &host, &self.credentials, OffsetDateTime::now_utc())?;
+
+        let path = request.path().strip_prefix(self.base_path()).unwrap_or(request.path());
+        let (bucket, raw_key) = path.trim_start_matches('/').split_once('/').unwrap_or((path.trim_start_matches('/'), ""));
+        let key = decode_key(raw_key)?;
+        let reading = request.method == "GET" || request.method == "HEAD";
+        if !signed && !reading {
+            return Err(S3Error::access_denied("Anonymous users can only read from the stand-in"));
+        }
+        if bucket.is_empty() {
+            return Err(S3Error::not_implemented("ListBuckets is not supported by the stand-in"));
+        }
+        match (request.method.as_str(), key.is_empty()) {
+            ("GET", true) if request.has_param("versions") => self.list_versions(request, bucket),
+            ("GET", true) => self.list_objects(request, bucket),
+            ("HEAD", true) => self.storage.bucket_dir(bucket).map(|_| Response::new(200)),
+            ("POST", true) if request.has_param("delete") => self.delete_objects(request, bucket),
+            ("GET", false) if request.has_param("uploadId") => self.list_parts(request, bucket, &key),
+            ("GET", false) | ("HEAD", false) => self.get_object(request, bucket, &key),
+            ("PUT", false) if request.has_param("uploadId") => self.upload_part(request, bucket, &key),
+            ("PUT", false) if request.header("x-amz-copy-source").is_some() => self.copy_object(request, bucket, &key),
+            ("PUT", false) => {
+                let checksum = checked_checksum(request)?;
+                self.storage.check_write(bucket, &key, request.header("If-Match"), request.header("If-None-Match"))?;
+                let info = self.storage.put(bucket, &key, &request.body, &request_metadata(request))?;
+                Ok(with_checksum(object_headers(Response::new(200), &info), checksum))
+            },
+            ("POST", false) if request.has_param("uploads") => {
+                let upload_id = self.storage.create_upload(bucket, &key, &request_metadata(request))?;
+                Ok(Response::xml(200, format!(
+                    "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    escape(bucket), escape(&key), upload_id,
+                )))
+            },
+            ("POST", false) if request.has_param("uploadId") => self.complete_upload(request, bucket, &key),
+            ("DELETE", false) => {
+                match request.param("uploadId") {
+                    Some(upload_id) => self.storage.abort_upload(bucket, &key, &upload_id)?,
+                    None => self.storage.delete(bucket, &key, request.header("If-Match"))?,
+                }
+                Ok(Response::new(204))
+            },
+            _ => Err(S3Error::not_implemented(&format!("{} {} is not supported by the stand-in", request.method, request.path()))),
+        }
+    }
+
+    /// ListObjectsV2, continuation tokens are the last key of the previous page.
+    fn list_objects(&self, request: &Request, bucket: &str) -> Result<Response, S3Error> {
+        if request.param("list-type").as_deref() != Some("2") {
+            return Err(S3Error::not_implemented("Only ListObjectsV2, list-type=2, is supported by the stand-in"));
+        }
+        let prefix = request.param("prefix").unwrap_or_default();
+        let start_after = request.param("start-after");
+        let token = request.param("continuation-token");
+        let max_keys = request.param("max-keys").and_then(|n| n.parse().ok()).unwrap_or(MAX_KEYS).min(self.max_keys);
+        let after = match (&start_after, &token) {
+            (Some(start_after), Some(token)) => Some(start_after.max(token).as_str()),
+            (Some(after), None) | (None, Some(after)) => Some(after.as_str()),
+            (None, None) => None,
+        };
+        let listing = self.storage.list(bucket, &prefix, after, max_keys)?;
+        let next_token = match listing.truncated {
+            true => listing.objects.last().map(|object| object.key.clone()),
+            false => None,
+        };
+        Ok(Response::xml(200, format!(
+            "<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Name>{}</Name><Prefix>{}</Prefix>{}{}<KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+            escape(bucket),
+            escape(&prefix),
+            optional_element("StartAfter", start_after.as_deref()),
+            optional_element("ContinuationToken", token.as_deref()),
+            listing.objects.len(),
+            max_keys,
+            listing.truncated,
+            listing.objects.iter().map(contents_xml).collect::<String>(),
+            optional_element("NextContinuationToken", next_token.as_deref()),
+        )))
+    }
+
+    /// ListObjectVersions as a bucket without versioning answers it, each object's only version has
+    /// the id "null".
+    fn list_versions(&self, request: &Request, bucket: &str) -> Result<Response, S3Error> {
+        let prefix = request.param("prefix").unwrap_or_default();
+        let after = request.param("key-marker");
+        let listing = self.storage.list(bucket, &prefix, after.as_deref(), self.max_keys)?;
+        let next_marker = match listing.truncated {
+            true => listing.objects.last().map(|object| object.key.clone()),
+            false => None,
+        };
+        Ok(Response::xml(200, format!(
+            "<ListVersionsResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\"><Name>{}</Name><Prefix>{}</Prefix><IsTruncated>{}</IsTruncated>{}{}{}</ListVersionsResult>",
+            escape(bucket),
+            escape(&prefix),
+            listing.truncated,
+            optional_element("NextKeyMarker", next_marker.as_deref()),
+            optional_element("NextVersionIdMarker", next_marker.as_ref().map(|_| "null")),
+            listing.objects.iter().map(|info| format!(
+                "<Version><Key>{}</Key><VersionId>null</VersionId><IsLatest>true</IsLatest><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Version>",
+                escape(&info.key), info.last_modified_iso(), escape(&info.e_tag), info.size,
+            )).collect::<String>(),
+        )))
+    }
+
+    fn get_object(&self, request: &Request, bucket: &str, key: &str) -> Result<Response, S3Error> {
+        // only the current version is kept, see `list_versions`
+        if request.param("versionId").is_some_and(|version_id| version_id != "null") {
+            return Err(S3Error::new(404, "NoSuchVersion", "The specified version does not exist.").with_field("Key", key));
+        }
+        let info = self.storage.head(bucket, key)?;
+        if request.header("If-None-Match") == Some(info.e_tag.as_str()) {
+            return Ok(object_headers(Response::new(304), &info));
+        }
+        let range = match (request.method.as_str(), request.header("Range")) {
+            ("GET", Some(range)) => Some(byte_range(range, info.size as usize)?),
+            _ => None,
+        };
+        let metadata = self.storage.metadata(bucket, key)?;
+        let mut response = object_headers(Response::new(range.map_or(200, |_| 206)), &info);
+        if !metadata.contains_key("content-type") {
+            response = response.with_header("Content-Type", content_type(key));
+        }
+        for (name, value) in metadata {
+            response = response.with_header(&name, &value);
+        }
+        match (request.method.as_str(), range) {
+            ("HEAD", _) => Ok(response.with_header("Content-Length", &info.size.to_string())),
+            (_, Some((first, last))) => Ok(response
+                .with_header("Content-Range", &format!("bytes {}-{}/{}", first, last, info.size))
+                .with_body(self.storage.get(bucket, key)?.1[first..=last].to_vec())),
+            _ => Ok(response.with_body(self.storage.get(bucket, key)?.1)),
+        }
+    }
+
+    fn copy_object(&self, request: &Request, bucket: &str, key: &str) -> Result<Response, S3Error> {
+        let source = decode_key(request.header("x-amz-copy-source").unwrap_or_default())?;
+        let (source_bucket, source_key) = source.trim_start_matches('/').split_once('/')
+            .ok_or(S3Error::new(400, "InvalidArgument", "Copy Source must mention the source bucket and key: sourcebucket/sourcekey"))?;
+        let metadata = match request.header("x-amz-metadata-directive") {
+            Some("REPLACE") => Some(request_metadata(request)),
+            _ => None,
+        };
+        let info = self.storage.copy((source_bucket, source_key), (bucket, key), request.header("x-amz-copy-source-if-match"), metadata.as_ref())?;
+        Ok(Response::xml(200, format!(
+            "<CopyObjectResult><LastModified>{}</LastModified><ETag>{}</ETag></CopyObjectResult>",
+            info.last_modified_iso(), escape(&info.e_tag),
+        )))
+    }
+
+    /// DeleteObjects, the outcome of each key is reported in the body.
+    fn delete_objects(&self, request: &Request, bucket: &str) -> Result<Response, S3Error> {
+        self.storage.bucket_dir(bucket)?;
+        let delete = parse_body::<Delete>(&request.body)?;
+        let entries = delete.objects.iter().map(|object| match self.storage.delete(bucket, &object.key, None) {
+            Ok(()) => format!("<Deleted><Key>{}</Key></Deleted>", escape(&object.key)),
+            Err(err) => format!("<Error><Key>{}</Key><Code>{}</Code><Message>{}</Message></Error>", escape(&object.key), err.code, escape(&err.message)),
+        }).collect::<String>();
+        Ok(Response::xml(200, format!("<DeleteResult>{}</DeleteResult>", entries)))
+    }
+
+    fn upload_part(&self, request: &Request, bucket: &str, key: &str) -> Result<Response, S3Error> {
+        let upload_id = request.param("uploadId").unwrap_or_default();
+        let part_number = request.param("partNumber").and_then(|n| n.parse::<u32>().ok())
+            .ok_or(S3Error::new(400, "InvalidArgument", "Part number must be an integer between 1 and 10000, inclusive"))?;
+        let checksum = checked_checksum(request)?;
+        let e_tag = self.storage.put_part(bucket, key, &upload_id, part_number, &request.body)?;
+        Ok(with_checksum(Response::new(200).with_header("ETag", &e_tag), checksum))
+    }
+
+    fn list_parts(&self, request: &Request, bucket: &str, key: &str) -> Result<Response, S3Error> {
+        let upload_id = request.param("uploadId").unwrap_or_default();
+        let marker = request.param("part-number-marker").and_then(|n| n.parse::<u32>().ok()).unwrap_or(0);
+        let parts = self.storage.list_parts(bucket, key, &upload_id, marker)?;
+        let parts_xml = parts.iter()
+            .map(|part| format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag><ChecksumSHA256>{}</ChecksumSHA256><Size>{}</Size></Part>",
+                part.part_number, escape(&part.e_tag), part.checksum, part.size,
+            ))
+            .collect::<String>();
+        Ok(Response::xml(200, format!(
+            "<ListPartsResult><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId><PartNumberMarker>{}</PartNumberMarker><IsTruncated>false</IsTruncated>{}</ListPartsResult>",
+            escape(bucket), escape(key), upload_id, marker, parts_xml,
+        )))
+    }
+
+    fn complete_upload(&self, request: &Request, bucket: &str, key: &str) -> Result<Response, S3Error> {
+        let upload_id = request.param("uploadId").unwrap_or_default();
+        let complete = parse_body::<CompleteMultipartUpload>(&request.body)?;
+        let parts = complete.parts.into_iter().map(|part| (part.part_number, part.e_tag)).collect::<Vec<(u32, String)>>();
+        let info = self.storage.complete_upload(bucket, key, &upload_id, &parts)?;
+        Ok(Response::xml(200, format!(
+            "<CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+            escape(bucket), escape(key), escape(&info.e_tag),
+        )))
+    }
+}
+
+// Browsers ask before sending signed or non-simple requests from the app's origin.
+fn preflight(request: &Request) -> Response {
+    Response::new(200)
+        .with_header("Access-Control-Allow-Methods", "GET, HEAD, PUT, POST, DELETE")
+        .with_header("Access-Control-Allow-Headers", request.header("Access-Control-Request-Headers").unwrap_or("*"))
+        .with_header("Access-Control-Max-Age", "3000")
+}
+
+fn serve(server: &Server, stream: TcpStream) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(stream) => stream,
+        Err(_) => return,
+    });
+    let mut writer = BufWriter::new(stream);
+    // connections are kept open for the browser's next request, until it closes them
+    loop {
+        let request = match read_request(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(err) => {
+                let _ = S3Error::new(400, "BadRequest", err).response().with_header("Connection", "close").write_to(&mut writer, false);
+                return;
+            },
+        };
+        let response = server.handle(&request);
+        println!("{} {} {}", request.method, request.target, response.status);
+        if response.write_to(&mut writer, request.method == "HEAD").is_err() {
+            return;
+        }
+    }
+}
+
+fn main() {
+    if std::env::args().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        },
+    };
+    let storage = Storage::new(options.root.clone()).expect("unable to create the root directory");
+    storage.create_bucket(&options.bucket).expect("unable to create the bucket");
+    let listener = TcpListener::bind(&options.address).expect("unable to listen on the address");
+
+    let (json, token) = options.site_config();
+    println!("Serving {} at {}", options.root.display(), options.endpoint());
+    println!("Site config: {}", json);
+    println!("Config token: {}", token);
+
+    let server = Arc::new(Server {
+        storage,
+        credentials: options.credentials,
+        max_keys: options.max_keys,
+        public_url: options.public_url,
+    });
+    for stream in listener.incoming().flatten() {
+        let server = server.clone();
+        thread::spawn(move || serve(&server, stream));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use secrecy::SecretString;
+    use sha2::{Digest, Sha256};
+    use time::OffsetDateTime;
+    use url::Url;
+
+    use crate::auth::Credentials;
+    use crate::awssigv4::{signed_request_headers, SigningCredentials};
+    use crate::http::{Request, Response};
+    use crate::storage::Storage;
+    use crate::{Options, Server};
+
+    fn server(name: &str, public_url: Option<&str>) -> Server {
+        let root = std::env::temp_dir().join(format!("weblum-s3-stand-in-server-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = Storage::new(PathBuf::from(&root)).unwrap();
+        storage.create_bucket("photos").unwrap();
+        Server {
+            storage,
+            credentials: Credentials { access_key: "weblum".to_string(), secret_key: "weblum-secret".to_string(), region: "us-east-1".to_string() },
+            max_keys: 2,
+            public_url: public_url.map(|url| Url::parse(url).unwrap()),
+        }
+    }
+
+    // A request signed as the app signs it, for `origin` and sent to the stand-in.
+    fn signed(method: &str, origin: &str, target: &str, extra_headers: &[(String, String)], body: &[u8]) -> Request {
+        let credentials = SigningCredentials::new("weblum".to_string(), SecretString::new("weblum-secret".to_string()), "us-east-1".to_string());
+        let url = format!("{}{}", origin, target);
+        let mut headers = signed_request_headers(&credentials, method, &url, extra_headers, Some(body), &OffsetDateTime::now_utc()).unwrap();
+        headers.push(("Host".to_string(), "127.0.0.1:9000".to_string()));
+        Request { method: method.to_string(), target: target.to_string(), headers, body: body.to_vec() }
+    }
+
+    fn anonymous(method: &str, target: &str) -> Request {
+        Request { method: method.to_string(), target: target.to_string(), headers: vec![("Host".to_string(), "127.0.0.1:9000".to_string())], body: vec![] }
+    }
+
+    fn body(response: &Response) -> String {
+        String::from_utf8(response.body.clone()).unwrap()
+    }
+
+    #[test]
+    fn test_put_and_list_pages() {
+        let server = server("list", None);
+        for name in ["a", "b", "c"] {
+            let put = signed("PUT", "http://127.0.0.1:9000", &format!("/photos/images/{}.jpg", name), &[], name.as_bytes());
+            assert_eq!(server.handle(&put).status, 200);
+        }
+        let first = server.handle(&anonymous("GET", "/photos?list-type=2&prefix=images%2F&start-after=images%2F"));
+        assert!(body(&first).contains("<KeyCount>2</KeyCount><MaxKeys>2</MaxKeys><IsTruncated>true</IsTruncated>"));
+        assert!(body(&first).contains("<NextContinuationToken>images/b.jpg</NextContinuationToken>"));
+        let second = server.handle(&anonymous("GET", "/photos?list-type=2&continuation-token=images%2Fb.jpg&prefix=images%2F&start-after=images%2F"));
+        assert!(body(&second).contains("<Key>images/c.jpg</Key>"));
+        assert!(body(&second).contains("<IsTruncated>false</IsTruncated>"));
+        assert!(!body(&second).contains("NextContinuationToken"));
+    }
+
+    #[test]
+    fn test_anonymous_writes_refused() {
+        let server = server("anonymous", None);
+        let response = server.handle(&anonymous("PUT", "/photos/images/a.jpg"));
+        assert_eq!(response.status, 403);
+        assert!(body(&response).contains("<Code>AccessDenied</Code>"));
+        assert_eq!(server.handle(&anonymous("GET", "/photos/images/a.jpg")).status, 404);
+        assert_eq!(server.handle(&anonymous("GET", "/albums?list-type=2")).status, 404);
+    }
+
+    #[test]
+    fn test_copy_and_delete() {
+        let server = server("copy", None);
+        server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/inbox/a%20b.jpg", &[], b"a"));
+        let headers = [
+            ("x-amz-copy-source".to_string(), "/photos/inbox/a%20b.jpg".to_string()),
+            ("x-amz-copy-source-if-match".to_string(), "\"other\"".to_string()),
+        ];
+        let refused = server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a%20b.jpg", &headers, b""));
+        assert_eq!(refused.status, 412);
+        let copied = server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a%20b.jpg", &headers[..1], b""));
+        assert!(body(&copied).contains("<CopyObjectResult>"));
+        let delete = server.handle(&signed("POST", "http://127.0.0.1:9000", "/photos?delete",
+            &[], b"<Delete><Quiet>false</Quiet><Object><Key>inbox/a b.jpg</Key></Object></Delete>"));
+        assert!(body(&delete).contains("<DeleteResult><Deleted><Key>inbox/a b.jpg</Key></Deleted></DeleteResult>"));
+        assert_eq!(server.handle(&anonymous("HEAD", "/photos/images/a%20b.jpg")).status, 200);
+        assert_eq!(server.handle(&anonymous("HEAD", "/photos/inbox/a%20b.jpg")).status, 404);
+    }
+
+    #[test]
+    fn test_list_versions() {
+        let server = server("versions", None);
+        for name in ["a", "b", "c"] {
+            server.handle(&signed("PUT", "http://127.0.0.1:9000", &format!("/photos/images/{}.jpg", name), &[], name.as_bytes()));
+        }
+        let first = server.handle(&anonymous("GET", "/photos?versions&prefix=images%2F"));
+        assert!(body(&first).contains("<IsTruncated>true</IsTruncated><NextKeyMarker>images/b.jpg</NextKeyMarker><NextVersionIdMarker>null</NextVersionIdMarker>"));
+        assert!(body(&first).contains("<Version><Key>images/a.jpg</Key><VersionId>null</VersionId><IsLatest>true</IsLatest>"));
+        let second = server.handle(&anonymous("GET", "/photos?versions&prefix=images%2F&key-marker=images%2Fb.jpg&version-id-marker=null"));
+        assert!(body(&second).contains("<Key>images/c.jpg</Key>"));
+        assert_eq!(server.handle(&anonymous("GET", "/photos/images/a.jpg?versionId=null")).body, b"a");
+        assert_eq!(server.handle(&anonymous("GET", "/photos/images/a.jpg?versionId=3HL4kqtJl")).status, 404);
+    }
+
+    #[test]
+    fn test_conditional_put() {
+        let server = server("conditional", None);
+        let create = [("If-None-Match".to_string(), "*".to_string())];
+        let created = server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/admin/settings.json", &create, b"a"));
+        assert_eq!(created.status, 200);
+        assert_eq!(server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/admin/settings.json", &create, b"b")).status, 412);
+        let e_tag = created.headers.iter().find(|(name, _)| name == "ETag").map(|(_, e_tag)| e_tag.clone()).unwrap();
+        let stale = [("If-Match".to_string(), "\"other\"".to_string())];
+        let refused = server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/admin/settings.json", &stale, b"c"));
+        assert!(body(&refused).contains("<Code>PreconditionFailed</Code>"));
+        let current = [("If-Match".to_string(), e_tag)];
+        assert_eq!(server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/admin/settings.json", &current, b"d")).status, 200);
+        assert_eq!(server.handle(&anonymous("GET", "/photos/admin/settings.json")).body, b"d");
+    }
+
+    #[test]
+    fn test_metadata() {
+        let server = server("metadata", None);
+        let id = [("x-amz-meta-weblum-id".to_string(), "a-id".to_string())];
+        server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a.jpg", &id, b"a"));
+        let head = server.handle(&anonymous("HEAD", "/photos/images/a.jpg"));
+        assert!(head.headers.contains(&("x-amz-meta-weblum-id".to_string(), "a-id".to_string())));
+
+        // a copy onto itself replacing the metadata, as the app gives an image an id
+        let replace = [
+            ("x-amz-copy-source".to_string(), "/photos/images/a.jpg".to_string()),
+            ("x-amz-metadata-directive".to_string(), "REPLACE".to_string()),
+            ("x-amz-meta-weblum-id".to_string(), "b-id".to_string()),
+        ];
+        server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a.jpg", &replace, b""));
+        let head = server.handle(&anonymous("HEAD", "/photos/images/a.jpg"));
+        assert!(head.headers.contains(&("x-amz-meta-weblum-id".to_string(), "b-id".to_string())));
+    }
+
+    #[test]
+    fn test_stored_headers() {
+        let server = server("headers", None);
+        let headers = [
+            ("Content-Type".to_string(), "image/jpeg".to_string()),
+            ("Cache-Control".to_string(), "public, max-age=86400".to_string()),
+        ];
+        server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a.bin", &headers, b"abcdef"));
+        let get = server.handle(&anonymous("GET", "/photos/images/a.bin"));
+        assert!(get.headers.contains(&("content-type".to_string(), "image/jpeg".to_string())));
+        assert!(get.headers.contains(&("cache-control".to_string(), "public, max-age=86400".to_string())));
+        assert!(!get.headers.iter().any(|(name, _)| name == "Content-Type"));
+
+        let mut first_bytes = anonymous("GET", "/photos/images/a.bin");
+        first_bytes.headers.push(("Range".to_string(), "bytes=0-63".to_string()));
+        let partial = server.handle(&first_bytes);
+        assert_eq!(partial.status, 206);
+        assert!(partial.headers.contains(&("Content-Range".to_string(), "bytes 0-5/6".to_string())));
+        assert_eq!(partial.body, b"abcdef");
+
+        let replace = [
+            ("x-amz-copy-source".to_string(), "/photos/images/a.bin".to_string()),
+            ("x-amz-metadata-directive".to_string(), "REPLACE".to_string()),
+            ("Content-Type".to_string(), "image/png".to_string()),
+        ];
+        server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a.bin", &replace, b""));
+        let head = server.handle(&anonymous("HEAD", "/photos/images/a.bin"));
+        assert!(head.headers.contains(&("content-type".to_string(), "image/png".to_string())));
+        assert!(!head.headers.iter().any(|(name, _)| name == "cache-control"));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let server = server("checksums", None);
+        let checksum = STANDARD.encode(Sha256::digest(b"abc"));
+        let headers = [
+            ("x-amz-checksum-sha256".to_string(), checksum.clone()),
+            ("x-amz-meta-weblum-sha256".to_string(), checksum.clone()),
+        ];
+        let put = server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a.jpg", &headers, b"abc"));
+        assert!(put.headers.contains(&("x-amz-checksum-sha256".to_string(), checksum.clone())));
+        let get = server.handle(&anonymous("GET", "/photos/images/a.jpg"));
+        assert!(get.headers.contains(&("x-amz-meta-weblum-sha256".to_string(), checksum)));
+
+        let changed = server.handle(&signed("PUT", "http://127.0.0.1:9000", "/photos/images/a.jpg", &headers, b"abd"));
+        assert!(body(&changed).contains("<Code>BadDigest</Code>"));
+        assert_eq!(server.handle(&anonymous("GET", "/photos/images/a.jpg")).body, b"abc");
+    }
+
+    #[test]
+    fn test_behind_proxy() {
+        let server = server("proxy", Some("http://127.0.0.1:8080/s3"));
+        let put = signed("PUT", "http://127.0.0.1:8080", "/s3/photos/images/a.jpg", &[], b"a");
+        assert_eq!(server.handle(&put).status, 200);
+        assert_eq!(server.handle(&anonymous("GET", "/s3/photos/images/a.jpg")).body, b"a");
+    }
+
+    #[test]
+    fn test_options() {
+        let args = ["--bucket", "albums", "--max-keys", "2", "--public-url", "http://127.0.0.1:8080/s3/"];
+        let options = Options::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(options.max_keys, 2);
+        assert_eq!(options.site_config().0,
+            r#"{"a":"weblum","b":"albums","e":"http://127.0.0.1:8080/s3","id":"s3-stand-in","r":"us-east-1","s":"weblum-secret"}"#);
+        assert!(Options::parse(["--max-keys", "0"].iter().map(|arg| arg.to_string())).is_err());
+        assert!(Options::parse(["--root"].iter().map(|arg| arg.to_string())).is_err());
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use time::macros::format_description;
+
+use crate::http::S3Error;
+
+// Holds in-progress multipart uploads, partly written objects and the objects' metadata, buckets can
+// not start with '.'.
+const UPLOADS_DIR: &str = ".uploads";
+const TEMP_DIR: &str = ".tmp";
+const METADATA_DIR: &str = ".metadata";
+
+/// An object's user metadata, by the lower case name of its `x-amz-meta-*` header.
+pub type Metadata = BTreeMap<String, String>;
+
+/// An object as listed, `last_modified` as S3 formats it in listings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub e_tag: String,
+    pub size: u64,
+    pub last_modified: OffsetDateTime,
+}
+
+impl ObjectInfo {
+    pub fn last_modified_iso(&self) -> String {
+        self.last_modified
+            .format(format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"))
+            .unwrap_or_default()
+    }
+
+    /// The HTTP date sent in the Last-Modified header.
+    pub fn last_modified_http(&self) -> String {
+        self.last_modified
+            .format(format_description!("[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"))
+            .unwrap_or_default()
+    }
+}
+
+/// A page of a listing, `truncated` when keys after the last one remain.
+pub struct Listing {
+    pub objects: Vec<ObjectInfo>,
+    pub truncated: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Part {
+    pub part_number: u32,
+    pub e_tag: String,
+    // the part's SHA-256, base64 encoded
+    pub checksum: String,
+    pub size: u64,
+}
+
+/// Buckets are the directories under `root`, each object a file at its key's path in the bucket.
+pub struct Storage {
+    root: PathBuf,
+    // ETags by path, recomputed when a file's modified time or length changes
+    e_tags: Mutex<HashMap<PathBuf, (SystemTime, u64, String)>>,
+    next_id: AtomicU64,
+}
+
+/// The ETag of a body, quoted as S3 sends it. S3 uses an MD5, any digest of the content will do.
+pub fn e_tag(body: &[u8]) -> String {
+    format!("\"{}\"", &hex::encode(Sha256::digest(body))[..32])
+}
+
+fn not_found(err: io::Error, key: &str) -> S3Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => S3Error::no_such_key(key),
+        _ => S3Error::internal(err),
+    }
+}
+
+fn no_such_upload() -> S3Error {
+    S3Error::new(404, "NoSuchUpload", "The specified multipart upload does not exist.")
+}
+
+fn check_match(info: &ObjectInfo, if_match: Option<&str>) -> Result<(), S3Error> {
+    match if_match {
+        Some(e_tag) if e_tag != "*" && e_tag != info.e_tag => Err(S3Error::precondition_failed()),
+        _ => Ok(()),
+    }
+}
+
+impl Storage {
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(root.join(UPLOADS_DIR))?;
+        fs::create_dir_all(root.join(TEMP_DIR))?;
+        fs::create_dir_all(root.join(METADATA_DIR))?;
+        Ok(Self { root, e_tags: Mutex::new(HashMap::new()), next_id: AtomicU64::new(0) })
+    }
+
+    pub fn create_bucket(&self, bucket: &str) -> io::Result<()> {
+        fs::create_dir_all(self.root.join(bucket))
+    }
+
+    pub fn bucket_dir(&self, bucket: &str) -> Result<PathBuf, S3Error> {
+        let dir = self.root.join(bucket);
+        match !bucket.is_empty() && !bucket.starts_with('.') && !bucket.contains(['/', '\\']) && dir.is_dir() {
+            true => Ok(dir),
+            false => Err(S3Error::new(404, "NoSuchBucket", "The specified bucket does not exist").with_field("BucketName", bucket)),
+        }
+    }
+
+    // Keys map to paths inside the bucket, so segments which would leave it are refused.
+    fn object_path(&self, bucket: &str, key: &str) -> Result<PathBuf, S3Error> {
+        let dir = self.bucket_dir(bucket)?;
+        match key.split('/').any(|segment| segment.is_empty() || segment == "." || segment == "..") {
+            true => Err(S3Error::new(400, "InvalidArgument", "The stand-in can not store this key").with_field("Key", key)),
+            false => Ok(dir.join(key)),
+        }
+    }
+
+    // Metadata is kept in a JSON file at the object's path under the metadata directory.
+    fn metadata_path(&self, bucket: &str, key: &str) -> Result<PathBuf, S3Error> {
+        self.object_path(bucket, key)?;
+        Ok(self.root.join(METADATA_DIR).join(bucket).join(key))
+    }
+
+    /// The metadata stored with the object, empty when it has none.
+    pub fn metadata(&self, bucket: &str, key: &str) -> Result<Metadata, S3Error> {
+        match fs::read(self.metadata_path(bucket, key)?) {
+            Ok(json) => serde_json::from_slice(&json).map_err(S3Error::internal),
+            Err(_) => Ok(Metadata::new()),
+        }
+    }
+
+    fn set_metadata(&self, bucket: &str, key: &str, metadata: &Metadata) -> Result<(), S3Error> {
+        let path = self.metadata_path(bucket, key)?;
+        match metadata.is_empty() {
+            true => match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(S3Error::internal(err)),
+                _ => Ok(()),
+            },
+            false => self.write(&path, &serde_json::to_vec(metadata).map_err(S3Error::internal)?),
+        }
+    }
+
+    fn unique_id(&self) -> String {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+        format!("{:x}{:x}", nanos, self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    // Written to a temporary file first, so a failed write never leaves a partial object.
+    fn write(&self, path: &Path, body: &[u8]) -> Result<(), S3Error> {
+        let temp = self.root.join(TEMP_DIR).join(self.unique_id());
+        fs::write(&temp, body)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    fn info(&self, path: &Path, key: &str) -> Result<ObjectInfo, S3Error> {
+        let metadata = fs::metadata(path).map_err(|err| not_found(err, key))?;
+        if !metadata.is_file() {
+            return Err(S3Error::no_such_key(key));
+        }
+        let modified = metadata.modified()?;
+        let mut e_tags = self.e_tags.lock().expect("e_tags lock");
+        let e_tag = match e_tags.get(path) {
+            Some((cached_modified, size, e_tag)) if *cached_modified == modified && *size == metadata.len() => e_tag.clone(),
+            _ => {
+                let e_tag = e_tag(&fs::read(path)?);
+                e_tags.insert(path.to_path_buf(), (modified, metadata.len(), e_tag.clone()));
+                e_tag
+            },
+        };
+        Ok(ObjectInfo { key: key.to_string(), e_tag, size: metadata.len(), last_modified: OffsetDateTime::from(modified) })
+    }
+
+    pub fn head(&self, bucket: &str, key: &str) -> Result<ObjectInfo, S3Error> {
+        self.info(&self.object_path(bucket, key)?, key)
+    }
+
+    pub fn get(&self, bucket: &str, key: &str) -> Result<(ObjectInfo, Vec<u8>), S3Error> {
+        let path = self.object_path(bucket, key)?;
+        let info = self.info(&path, key)?;
+        let body = fs::read(&path).map_err(|err| not_found(err, key))?;
+        Ok((info, body))
+    }
+
+    /// Stores `body` at `key`, replacing the metadata of an object already there.
+    pub fn put(&self, bucket: &str, key: &str, body: &[u8], metadata: &Metadata) -> Result<ObjectInfo, S3Error> {
+        let path = self.object_path(bucket, key)?;
+        self.write(&path, body)?;
+        self.set_metadata(bucket, key, metadata)?;
+        self.info(&path, key)
+    }
+
+    /// Fails as S3 does when a write's `If-Match` or `If-None-Match: *` does not hold for `key`, an
+    /// `If-Match` naming a missing object fails with NoSuchKey.
+    pub fn check_write(&self, bucket: &str, key: &str, if_match: Option<&str>, if_none_match: Option<&str>) -> Result<(), S3Error> {
+        match self.head(bucket, key) {
+            Ok(_) if if_none_match == Some("*") => Err(S3Error::precondition_failed()),
+            Ok(info) => check_match(&info, if_match),
+            Err(err) if err.code == "NoSuchKey" && if_match.is_none() => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Copies `from` to `to`, when `if_match` is given only while `from` still has that ETag. The copy
+    /// has `from`'s metadata unless `metadata` replaces it.
+    pub fn copy(&self, from: (&str, &str), to: (&str, &str), if_match: Option<&str>, metadata: Option<&Metadata>) -> Result<ObjectInfo, S3Error> {
+        let (info, body) = self.get(from.0, from.1)?;
+        check_match(&info, if_match)?;
+        let metadata = match metadata {
+            Some(metadata) => metadata.clone(),
+            None => self.metadata(from.0, from.1)?,
+        };
+        self.put(to.0, to.1, &body, &metadata)
+    }
+
+    /// Deleting a missing key succeeds, as with S3, unless a precondition was given.
+    pub fn delete(&self, bucket: &str, key: &str, if_match: Option<&str>) -> Result<(), S3Error> {
+        let path = self.object_path(bucket, key)?;
+        match self.info(&path, key) {
+            Ok(info) => check_match(&info, if_match)?,
+            Err(err) if err.code == "NoSuchKey" && if_match.is_none() => return Ok(()),
+            Err(err) => return Err(err),
+        }
+        fs::remove_file(&path)?;
+        self.set_metadata(bucket, key, &Metadata::new())?;
+        // empty directories would otherwise collect as keys are deleted
+        let bucket_dir = self.bucket_dir(bucket)?;
+        let mut dir = path.parent();
+        while let Some(parent) = dir {
+            if parent == bucket_dir || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+
+    /// Up to `max_keys` objects with keys starting with `prefix` and sorting after `after`, in key order.
+    pub fn list(&self, bucket: &str, prefix: &str, after: Option<&str>, max_keys: usize) -> Result<Listing, S3Error> {
+        let dir = self.bucket_dir(bucket)?;
+        let mut keys: Vec<String> = vec![];
+        collect_keys(&dir, "", &mut keys)?;
+        keys.sort();
+        let mut matching = keys.into_iter()
+            .filter(|key| key.starts_with(prefix) && after.map(|a| key.as_str() > a).unwrap_or(true));
+        let mut objects: Vec<ObjectInfo> = vec![];
+        for key in matching.by_ref().take(max_keys) {
+            objects.push(self.info(&dir.join(&key), &key)?);
+        }
+        Ok(Listing { objects, truncated: matching.next().is_some() })
+    }
+
+    /// Starts a multipart upload, the object gets `metadata` when it is completed.
+    pub fn create_upload(&self, bucket: &str, key: &str, metadata: &Metadata) -> Result<String, S3Error> {
+        self.object_path(bucket, key)?;
+        let upload_id = self.unique_id();
+        let dir = self.root.join(UPLOADS_DIR).join(&upload_id);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("key"), format!("{}/{}", bucket, key))?;
+        fs::write(dir.join("metadata"), serde_json::to_vec(metadata).map_err(S3Error::internal)?)?;
+        Ok(upload_id)
+    }
+
+    // The upload's directory, only for the object it was created for.
+    fn upload_dir(&self, bucket: &str, key: &str, upload_id: &str) -> Result<PathBuf, S3Error> {
+        if upload_id.is_empty() || !upload_id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(no_such_upload());
+        }
+        let dir = self.root.join(UPLOADS_DIR).join(upload_id);
+        match fs::read_to_string(dir.join("key")) {
+            Ok(object) if object == format!("{}/{}", bucket, key) => Ok(dir),
+            _ => Err(no_such_upload()),
+        }
+    }
+
+    pub fn put_part(&self, bucket: &str, key: &str, upload_id: &str, part_number: u32, body: &[u8]) -> Result<String, S3Error> {
+        let dir = self.upload_dir(bucket, key, upload_id)?;
+        if !(1..=10000).contains(&part_number) {
+            return Err(S3Error::new(400, "InvalidArgument", "Part number must be an integer between 1 and 10000, inclusive"));
+        }
+        self.write(&dir.join(format!("part-{}", part_number)), body)?;
+        Ok(e_tag(body))
+    }
+
+    /// The uploaded parts numbered after `marker`, in order.
+    pub fn list_parts(&self, bucket: &str, key: &str, upload_id: &str, marker: u32) -> Result<Vec<Part>, S3Error> {
+        let dir = self.upload_dir(bucket, key, upload_id)?;
+        let mut parts: Vec<Part> = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let part_number = match entry.file_name().to_str().and_then(|name| name.strip_prefix("part-")).and_then(|n| n.parse::<u32>().ok()) {
+                Some(part_number) if part_number > marker => part_number,
+                _ => continue,
+            };
+            let body = fs::read(entry.path())?;
+            parts.push(Part { part_number, e_tag: e_tag(&body), checksum: STANDARD.encode(Sha256::digest(&body)), size: body.len() as u64 });
+        }
+        parts.sort_by_key(|part| part.part_number);
+        Ok(parts)
+    }
+
+    /// Joins the listed parts, which must have been uploaded with the given ETags, into the object.
+    pub fn complete_upload(&self, bucket: &str, key: &str, upload_id: &str, parts: &[(u32, String)]) -> Result<ObjectInfo, S3Error> {
+        let dir = self.upload_dir(bucket, key, upload_id)?;
+        if parts.is_empty() || parts.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(S3Error::new(400, "InvalidPartOrder", "The list of parts was not in ascending order."));
+        }
+        let mut body: Vec<u8> = vec![];
+        for (part_number, part_e_tag) in parts {
+            let part = fs::read(dir.join(format!("part-{}", part_number)))
+                .map_err(|_| S3Error::new(400, "InvalidPart", format!("Part {} was not uploaded", part_number)))?;
+            if e_tag(&part) != *part_e_tag {
+                return Err(S3Error::new(400, "InvalidPart", format!("Part {} does not have the ETag {}", part_number, part_e_tag)));
+            }
+            body.extend_from_slice(&part);
+        }
+        let metadata = fs::read(dir.join("metadata")).ok()
+            .and_then(|json| serde_json::from_slice::<Metadata>(&json).ok())
+            .unwrap_or_default();
+        let info = self.put(bucket, key, &body, &metadata)?;
+        fs::remove_dir_all(&dir)?;
+        Ok(info)
+    }
+
+    pub fn abort_upload(&self, bucket: &str, key: &str, upload_id: &str) -> Result<(), S3Error> {
+        let dir = self.upload_dir(bucket, key, upload_id)?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
+
+fn collect_keys(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let key = format!("{}{}", prefix, name);
+        match entry.file_type()?.is_dir() {
+            true => collect_keys(&entry.path(), &format!("{}/", key), keys)?,
+            false => keys.push(key),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::storage::{e_tag, Metadata, Storage};
+
+    fn storage(name: &str) -> Storage {
+        let root = std::env::temp_dir().join(format!("weblum-s3-stand-in-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = Storage::new(PathBuf::from(&root)).unwrap();
+        storage.create_bucket("photos").unwrap();
+        storage
+    }
+
+    fn keys(storage: &Storage, prefix: &str, after: Option<&str>, max_keys: usize) -> (Vec<String>, bool) {
+        let listing = storage.list("photos", prefix, after, max_keys).unwrap();
+        (listing.objects.into_iter().map(|o| o.key).collect(), listing.truncated)
+    }
+
+    #[test]
+    fn test_list_pages() {
+        let storage = storage("list");
+        for key in ["images/c.jpg", "images/a.jpg", "comments/\"abc\"", "images/b.jpg"] {
+            storage.put("photos", key, key.as_bytes(), &Metadata::new()).unwrap();
+        }
+        assert_eq!(keys(&storage, "images/", None, 2), (vec!["images/a.jpg".to_string(), "images/b.jpg".to_string()], true));
+        assert_eq!(keys(&storage, "images/", Some("images/b.jpg"), 2), (vec!["images/c.jpg".to_string()], false));
+        assert_eq!(keys(&storage, "", None, 1000).0.len(), 4);
+        assert_eq!(storage.head("photos", "images/a.jpg").unwrap().e_tag, e_tag(b"images/a.jpg"));
+        assert_eq!(storage.list("albums", "", None, 1000).err().unwrap().code, "NoSuchBucket");
+    }
+
+    #[test]
+    fn test_copy_and_delete_preconditions() {
+        let storage = storage("copy");
+        let info = storage.put("photos", "inbox/a.jpg", b"a", &Metadata::new()).unwrap();
+        assert_eq!(storage.copy(("photos", "inbox/a.jpg"), ("photos", "images/a.jpg"), Some("\"other\""), None).err().unwrap().code, "PreconditionFailed");
+        assert_eq!(storage.copy(("photos", "inbox/a.jpg"), ("photos", "images/a.jpg"), Some(&info.e_tag), None).unwrap().e_tag, info.e_tag);
+        assert_eq!(storage.delete("photos", "inbox/a.jpg", Some("\"other\"")).err().unwrap().code, "PreconditionFailed");
+        storage.delete("photos", "inbox/a.jpg", Some(&info.e_tag)).unwrap();
+        assert!(storage.delete("photos", "inbox/a.jpg", None).is_ok());
+        assert_eq!(storage.delete("photos", "inbox/a.jpg", Some(&info.e_tag)).err().unwrap().code, "NoSuchKey");
+        assert_eq!(keys(&storage, "", None, 1000).0, vec!["images/a.jpg".to_string()]);
+        assert_eq!(storage.get("photos", "../photos/images/a.jpg").err().unwrap().code, "InvalidArgument");
+    }
+
+    #[test]
+    fn test_write_preconditions() {
+        let storage = storage("write");
+        assert!(storage.check_write("photos", "comments/a", None, Some("*")).is_ok());
+        assert_eq!(storage.check_write("photos", "comments/a", Some("\"other\""), None).err().unwrap().code, "NoSuchKey");
+        let info = storage.put("photos", "comments/a", b"a", &Metadata::new()).unwrap();
+        assert_eq!(storage.check_write("photos", "comments/a", None, Some("*")).err().unwrap().code, "PreconditionFailed");
+        assert_eq!(storage.check_write("photos", "comments/a", Some("\"other\""), None).err().unwrap().code, "PreconditionFailed");
+        assert!(storage.check_write("photos", "comments/a", Some(&info.e_tag), None).is_ok());
+        assert!(storage.check_write("photos", "comments/a", None, None).is_ok());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let storage = storage("metadata");
+        let metadata = Metadata::from([("x-amz-meta-weblum-id".to_string(), "a-id".to_string())]);
+        storage.put("photos", "images/a.jpg", b"a", &metadata).unwrap();
+        storage.copy(("photos", "images/a.jpg"), ("photos", "images/b.jpg"), None, None).unwrap();
+        assert_eq!(storage.metadata("photos", "images/b.jpg").unwrap(), metadata);
+        storage.copy(("photos", "images/a.jpg"), ("photos", "images/a.jpg"), None, Some(&Metadata::new())).unwrap();
+        assert!(storage.metadata("photos", "images/a.jpg").unwrap().is_empty());
+        storage.delete("photos", "images/b.jpg", None).unwrap();
+        storage.put("photos", "images/b.jpg", b"b", &Metadata::new()).unwrap();
+        assert!(storage.metadata("photos", "images/b.jpg").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_multipart_upload() {
+        let storage = storage("multipart");
+        let metadata = Metadata::from([("x-amz-meta-weblum-id".to_string(), "big-id".to_string())]);
+        let upload_id = storage.create_upload("photos", "images/big.jpg", &metadata).unwrap();
+        let second = storage.put_part("photos", "images/big.jpg", &upload_id, 2, b"world").unwrap();
+        let first = storage.put_part("photos", "images/big.jpg", &upload_id, 1, b"hello ").unwrap();
+        let parts = storage.list_parts("photos", "images/big.jpg", &upload_id, 0).unwrap();
+        assert_eq!(parts.iter().map(|p| (p.part_number, p.size)).collect::<Vec<(u32, u64)>>(), vec![(1, 6), (2, 5)]);
+        assert_eq!(storage.list_parts("photos", "images/big.jpg", &upload_id, 1).unwrap().len(), 1);
+        assert_eq!(storage.list_parts("photos", "images/other.jpg", &upload_id, 0).err().unwrap().code, "NoSuchUpload");
+        storage.complete_upload("photos", "images/big.jpg", &upload_id, &[(1, first), (2, second)]).unwrap();
+        assert_eq!(storage.get("photos", "images/big.jpg").unwrap().1, b"hello world");
+        assert_eq!(storage.metadata("photos", "images/big.jpg").unwrap(), metadata);
+        assert_eq!(storage.abort_upload("photos", "images/big.jpg", &upload_id).err().unwrap().code, "NoSuchUpload");
+    }
+}
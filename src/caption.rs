@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+
+use crate::conflict::merge_field;
+use crate::rich_text::{sanitize_html, text_html};
+
+// 1: the first caption document, captions were plain text before it
+// 2: the description is rich text, see `sanitize_html`
+const CAPTION_VERSION: u32 = 2;
+
+/// An image's caption as stored in `comments/<id>`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Caption {
+    // required, so a plain text caption is never mistaken for a document
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub title: String,
+    // HTML from the caption editor
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub alt_text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // shown instead of the date in the image's EXIF data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_taken: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    // hidden images are left out of the gallery for visitors
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+}
+
+impl Default for Caption {
+    fn default() -> Self {
+        Self {
+            version: CAPTION_VERSION,
+            title: String::new(),
+            description: String::new(),
+            alt_text: String::new(),
+            tags: vec![],
+            date_taken: None,
+            location: None,
+            license: None,
+            hidden: false,
+        }
+    }
+}
+
+impl Caption {
+    /// A stored caption. Captions stored before they were documents are plain text, which
+    /// becomes the description, as do the plain text descriptions of the first documents.
+    pub fn parse(bytes: &[u8]) -> Self {
+        match serde_json::from_slice::<Caption>(bytes) {
+            Ok(caption) if caption.version < 2 => Self { description: text_html(&caption.description), ..caption },
+            Ok(caption) => Self { description: sanitize_html(&caption.description), ..caption },
+            Err(_) => Self { description: text_html(&String::from_utf8_lossy(bytes)), ..Self::default() },
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&Self { version: CAPTION_VERSION, ..self.clone() }).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self { version: self.version, ..Self::default() }
+    }
+
+    /// The tags as they are edited, separated by commas.
+    pub fn tags_text(&self) -> String {
+        self.tags.join(", ")
+    }
+
+    pub fn set_tags_text(&mut self, text: &str) {
+        self.tags = text.split(',').map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect();
+    }
+
+    /// The caption edited from `base`, merged field by field with the changes made to `theirs`
+    /// since. Fields changed in both keep this caption's value.
+    pub fn merge(&self, base: &Caption, theirs: &Caption) -> Self {
+        Self {
+            version: CAPTION_VERSION,
+            title: merge_field(&base.title, &self.title, &theirs.title),
+            description: merge_field(&base.description, &self.description, &theirs.description),
+            alt_text: merge_field(&base.alt_text, &self.alt_text, &theirs.alt_text),
+            tags: merge_field(&base.tags, &self.tags, &theirs.tags),
+            date_taken: merge_field(&base.date_taken, &self.date_taken, &theirs.date_taken),
+            location: merge_field(&base.location, &self.location, &theirs.location),
+            license: merge_field(&base.license, &self.license, &theirs.license),
+            hidden: merge_field(&base.hidden, &self.hidden, &theirs.hidden),
+        }
+    }
+
+    /// The date taken, location and license, the details shown below the description.
+    pub fn details(&self) -> Vec<String> {
+        [&self.date_taken, &self.location, &self.license].into_iter().flatten().cloned().collect()
+    }
+}
+
+/// An optional field as edited in a text input, empty text clears it.
+pub fn optional_text(text: String) -> Option<String> {
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::caption::*;
+
+    #[test]
+    fn test_plain_text_caption() {
+        let caption = Caption::parse(b"a caption");
+        assert_eq!(caption, Caption { description: "<p>a caption</p>".to_string(), ..Caption::default() });
+        // plain text which happens to be JSON is still plain text
+        assert_eq!(Caption::parse(b"42").description, "<p>42</p>");
+        assert_eq!(Caption::parse(br#"{"title":"no version"}"#).description, "<p>{&quot;title&quot;:&quot;no version&quot;}</p>");
+        assert_eq!(Caption::parse(br#"{"version":1,"description":"<b>as typed</b>"}"#).description, "<p>&lt;b&gt;as typed&lt;/b&gt;</p>");
+        assert_eq!(Caption::parse(br#"{"version":2,"description":"<b onclick=\"x()\">bold</b>"}"#).description, "<b>bold</b>");
+        assert!(Caption::parse(b"").is_empty());
+    }
+
+    #[test]
+    fn test_caption_round_trip() {
+        let mut caption = Caption {
+            title: "Harbour".to_string(),
+            alt_text: "Boats in a harbour".to_string(),
+            location: optional_text(" Oslo ".to_string()),
+            license: optional_text("".to_string()),
+            hidden: true,
+            ..Caption::default()
+        };
+        caption.set_tags_text("boats, , sea ");
+        assert_eq!(caption.tags, vec!["boats", "sea"]);
+        assert_eq!(caption.tags_text(), "boats, sea");
+        assert_eq!(caption.details(), vec!["Oslo"]);
+
+        let bytes = caption.to_bytes();
+        assert_eq!(String::from_utf8(bytes.clone()).unwrap(),
+            r#"{"version":2,"title":"Harbour","alt_text":"Boats in a harbour","tags":["boats","sea"],"location":"Oslo","hidden":true}"#);
+        assert_eq!(Caption::parse(&bytes), caption);
+        assert!(!caption.is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = Caption { title: "Harbour".to_string(), tags: vec!["boats".to_string()], ..Caption::default() };
+        let mine = Caption { title: "The Harbour".to_string(), alt_text: "Boats".to_string(), ..base.clone() };
+        let theirs = Caption { title: "Oslo Harbour".to_string(), location: Some("Oslo".to_string()), hidden: true, ..base.clone() };
+        assert_eq!(mine.merge(&base, &theirs), Caption {
+            title: "The Harbour".to_string(),
+            alt_text: "Boats".to_string(),
+            location: Some("Oslo".to_string()),
+            hidden: true,
+            ..base.clone()
+        });
+        assert_eq!(base.merge(&base, &theirs), theirs);
+    }
+}
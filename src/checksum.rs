@@ -0,0 +1,96 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::api::Error;
+use crate::object_store::{list_all, ObjectStore};
+use crate::ImageInfo;
+
+/// The metadata header holding the checksum of an object's body when it was uploaded, so it can
+/// be verified when it is read. The bucket's CORS configuration must expose it.
+pub const CHECKSUM_HEADER: &str = "x-amz-meta-weblum-sha256";
+
+/// The checksum S3 verifies a write's body against, refusing it with BadDigest when they differ.
+/// S3 sends back the checksum it computed, the bucket's CORS configuration must expose it.
+pub const S3_CHECKSUM_HEADER: &str = "x-amz-checksum-sha256";
+
+/// The SHA-256 of `body`, base64 encoded as S3's checksums are.
+pub fn sha256(body: &[u8]) -> String {
+    STANDARD.encode(Sha256::digest(body))
+}
+
+/// Checks `checksum`, computed by the app, against the one `key` was stored or read with. Objects
+/// uploaded before checksums were recorded, and buckets which do not expose them, have none.
+pub fn verify(key: &str, checksum: &str, stored: Option<&str>) -> Result<(), Error> {
+    match stored {
+        Some(stored) if stored != checksum => {
+            log::error!("'{}' has the checksum {}, expected {}", key, checksum, stored);
+            Err(Error::ChecksumMismatch)
+        },
+        _ => Ok(()),
+    }
+}
+
+/// What `check_images` found, the images which no longer match the checksum they were uploaded
+/// with and those which could not be read, with why.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageCheck {
+    pub corrupt: Vec<String>,
+    pub unreadable: Vec<(String, String)>,
+}
+
+/// Reads every image, reporting the images read so far. An image which can not be read, e.g. one
+/// deleted since the listing, is recorded and the rest are still checked.
+pub async fn check_images(store: &dyn ObjectStore, on_progress: &dyn Fn(usize, usize)) -> Result<ImageCheck, Error> {
+    let keys = list_all(store, "images/").await?.into_iter().map(|object| object.key()).collect::<Vec<String>>();
+    let total = keys.len();
+    let mut check = ImageCheck::default();
+    for (checked, key) in keys.into_iter().enumerate() {
+        match store.get(&key).await {
+            Ok(_) => (),
+            Err(Error::ChecksumMismatch) => check.corrupt.push(key),
+            Err(err) => check.unreadable.push((key, err.to_string())),
+        }
+        on_progress(checked + 1, total);
+    }
+    Ok(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::*;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::block_on;
+
+    #[test]
+    fn test_verify() {
+        assert_eq!(sha256(b""), "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=");
+        let checksum = sha256(b"abc");
+        assert!(verify("images/a.jpg", &checksum, Some(&checksum)).is_ok());
+        assert!(verify("images/a.jpg", &checksum, None).is_ok());
+        assert!(matches!(verify("images/a.jpg", &checksum, Some(&sha256(b"abd"))), Err(Error::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn test_check_images() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            store.put("images/b.jpg", vec![2]).await.unwrap();
+            store.put("images/c.jpg", vec![3]).await.unwrap();
+            store.put("comments/a-id", vec![4]).await.unwrap();
+            let progress = std::cell::Cell::new((0, 0));
+            assert_eq!(check_images(&store, &|checked, total| progress.set((checked, total))).await.unwrap(), ImageCheck::default());
+            assert_eq!(progress.get(), (3, 3));
+
+            // a failed read is recorded and the images after it are still checked
+            store.fail_reads("images/c.jpg", Error::NoSuchKey);
+            store.fail_reads("images/a.jpg", Error::ChecksumMismatch);
+            assert_eq!(check_images(&store, &|checked, total| progress.set((checked, total))).await.unwrap(), ImageCheck {
+                corrupt: vec!["images/a.jpg".to_string()],
+                unreadable: vec![("images/c.jpg".to_string(), Error::NoSuchKey.to_string())],
+            });
+            assert_eq!(progress.get(), (3, 3));
+        });
+    }
+}
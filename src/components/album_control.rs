@@ -0,0 +1,292 @@
+use leptos::*;
+use leptonic::prelude::*;
+
+use crate::album::{delete_album, picker_albums, save_album, Album};
+use crate::api::Error;
+use crate::get_current_config;
+use crate::object_store::{use_display_url, use_object_store};
+use crate::AppState;
+use crate::ImageInfo;
+use crate::S3ObjectInfo;
+
+// the id of the image being shown
+fn current_image_id(app_state_signal: RwSignal<Option<AppState>>, list_image_resource: ReadSignal<Option<Result<Vec<S3ObjectInfo>, Error>>>) -> Option<String> {
+    let list = list_image_resource.get_untracked()?.ok()?;
+    let key = app_state_signal.get_untracked()?.current_image_name(list.clone());
+    list.into_iter().find(|image| image.key() == key)?.id()
+}
+
+fn public_site_url() -> String {
+    format!("http://{}/", get_current_config().map(|config| config.s3_bucket_name()).unwrap_or_default())
+}
+
+/// The album being shown, with its cover image.
+#[component]
+fn AlbumHeader(
+    album: Album,
+    image_list: Vec<S3ObjectInfo>,
+) -> impl IntoView {
+    let cover = album.cover(&image_list).map(|image| use_display_url(use_object_store(), image.key()));
+    view!{
+        <div style="display: flex; flex-direction: row; justify-content: center; align-items: center; padding: 10px 0 10px 0;">
+            {cover.map(|src| view!{ <img style="max-height: 80px; padding: 0 10px 0 10px;" src={src} alt={album.title.clone()} /> })}
+            <div>
+                <h4>{album.title.clone()}</h4>
+                <div>{album.description.clone()}</div>
+            </div>
+        </div>
+    }
+}
+
+/// Edits the site's albums, adding and arranging the image being shown.
+#[component]
+fn EditAlbumsControl(
+    albums: RwSignal<Vec<Album>>,
+    current_album: RwSignal<Option<Album>>,
+    app_state_signal: RwSignal<Option<AppState>>,
+    list_image_resource: ReadSignal<Option<Result<Vec<S3ObjectInfo>, Error>>>,
+    update_error: RwSignal<Option<String>>,
+) -> impl IntoView {
+    let (show_modal, set_show_modal) = create_signal(false);
+    let store = use_object_store();
+    let editing = create_rw_signal(None::<Album>);
+    let (new_title, set_new_title) = create_signal("".to_string());
+    let (title, set_title) = create_signal("".to_string());
+    let (description, set_description) = create_signal("".to_string());
+
+    create_effect(move |_| {
+        let album = editing.get();
+        set_title.set(album.as_ref().map(|album| album.title.clone()).unwrap_or_default());
+        set_description.set(album.map(|album| album.description).unwrap_or_default());
+    });
+
+    let save_action = create_action(move |album: &Album| {
+        let album = album.clone();
+        async move {
+            if let Err(err) = save_album(store.get_value().as_ref(), &album).await {
+                update_error.set(Some(format!("Unable to save the album. {}", err)));
+                return;
+            }
+            albums.update(|albums| {
+                albums.retain(|saved| saved.id != album.id);
+                albums.push(album.clone());
+                albums.sort_by_key(|album| album.title.to_lowercase());
+            });
+            if current_album.get_untracked().is_some_and(|current| current.id == album.id) {
+                current_album.set(Some(album.clone()));
+            }
+            editing.set(Some(album));
+        }
+    });
+
+    let delete_action = create_action(move |album: &Album| {
+        let album = album.clone();
+        async move {
+            if let Err(err) = delete_album(store.get_value().as_ref(), &album).await {
+                update_error.set(Some(format!("Unable to delete the album. {}", err)));
+                return;
+            }
+            albums.update(|albums| albums.retain(|saved| saved.id != album.id));
+            if current_album.get_untracked().is_some_and(|current| current.id == album.id) {
+                current_album.set(None);
+            }
+            editing.set(albums.get_untracked().first().cloned());
+        }
+    });
+
+    // applies `change` to the album being edited and saves it
+    let change = move |change: &dyn Fn(&mut Album)| {
+        if let Some(mut album) = editing.get_untracked() {
+            change(&mut album);
+            save_action.dispatch(album);
+        }
+    };
+    // applies `change` with the id of the image being shown
+    let change_image = move |change_with_id: fn(&mut Album, &str)| {
+        match current_image_id(app_state_signal, list_image_resource) {
+            Some(id) => change(&|album: &mut Album| change_with_id(album, &id)),
+            None => update_error.set(Some("The image has no id yet, reload the gallery and try again.".to_string())),
+        }
+    };
+
+    view!{
+        <div>
+            <Button
+                variant=ButtonVariant::Flat
+                on_click=move |_| {
+                    editing.set(current_album.get_untracked().or(albums.get_untracked().first().cloned()));
+                    set_show_modal.set(true);
+                }
+            >
+                "Edit Albums"
+            </Button>
+            <Modal show_when={show_modal}>
+                <ModalHeader><ModalTitle>"Albums"</ModalTitle></ModalHeader>
+                <ModalBody>
+                    <div style="width: 400px">
+                        <div>"New Album"</div>
+                        <div style="display: flex; flex-direction: row;">
+                            <TextInput get={new_title} set={set_new_title} placeholder="Title" />
+                            <Button
+                                disabled=Signal::derive(move || new_title.get().trim().is_empty())
+                                on_click=move |_| {
+                                    save_action.dispatch(Album::new(new_title.get_untracked().trim(), &albums.get_untracked()));
+                                    set_new_title.set("".to_string());
+                                }
+                            >
+                                "Create"
+                            </Button>
+                        </div>
+                        {move || match editing.get() {
+                            Some(album) => {
+                                let image_id = current_image_id(app_state_signal, list_image_resource);
+                                let in_album = image_id.as_ref().is_some_and(|id| album.contains(id));
+                                let link = album.link(&public_site_url());
+                                view!{
+                                    <div style="padding: 10px 0 10px 0;">
+                                        <div>"Album"</div>
+                                        <select
+                                            style="font-size: .8em;"
+                                            on:change=move |evt| {
+                                                let id = event_target_value(&evt);
+                                                editing.set(albums.get_untracked().into_iter().find(|album| album.id == id));
+                                            }
+                                        >
+                                            {albums.get_untracked().into_iter().map(|option| view!{
+                                                <option value={option.id.clone()} selected={option.id == album.id}>{option.title.clone()}</option>
+                                            }).collect_view()}
+                                        </select>
+                                        <div>"Title"</div>
+                                        <TextInput get={title} set={set_title} />
+                                        <div>"Description"</div>
+                                        <TextInput get={description} set={set_description} />
+                                        <div style="font-size: .8em; word-break: break-all; padding: 10px 0 10px 0;">{link.clone()}</div>
+                                        <div style="display: flex; flex-direction: row; flex-wrap: wrap;">
+                                            <Button
+                                                variant=ButtonVariant::Flat
+                                                on_click=move |_| change(&|album: &mut Album| {
+                                                    album.title = title.get_untracked();
+                                                    album.description = description.get_untracked();
+                                                })
+                                            >
+                                                "Save Details"
+                                            </Button>
+                                            <Button
+                                                variant=ButtonVariant::Flat
+                                                on_click=move |_| { let _ = leptos::window().navigator().clipboard().write_text(&link); }
+                                            >
+                                                "Copy Link"
+                                            </Button>
+                                            {match in_album {
+                                                false => view!{
+                                                    <Button variant=ButtonVariant::Flat on_click=move |_| change_image(Album::add)>
+                                                        "Add Current Image"
+                                                    </Button>
+                                                }.into_view(),
+                                                true => view!{
+                                                    <Button variant=ButtonVariant::Flat on_click=move |_| change_image(Album::remove)>
+                                                        "Remove Current Image"
+                                                    </Button>
+                                                    <Button variant=ButtonVariant::Flat on_click=move |_| change_image(|album, id| album.move_image(id, -1))>
+                                                        "Move Earlier"
+                                                    </Button>
+                                                    <Button variant=ButtonVariant::Flat on_click=move |_| change_image(|album, id| album.move_image(id, 1))>
+                                                        "Move Later"
+                                                    </Button>
+                                                    <Button variant=ButtonVariant::Flat on_click=move |_| change_image(|album, id| album.cover_image = Some(id.to_string()))>
+                                                        "Use as Cover"
+                                                    </Button>
+                                                }.into_view(),
+                                            }}
+                                        </div>
+                                        <Button
+                                            on_click=move |_| {
+                                                if let Some(album) = editing.get_untracked() {
+                                                    delete_action.dispatch(album);
+                                                }
+                                            }
+                                            color=ButtonColor::Danger
+                                        >
+                                            "Delete Album"
+                                        </Button>
+                                    </div>
+                                }.into_view()
+                            },
+                            None => "".into_view(),
+                        }}
+                    </div>
+                </ModalBody>
+                <ModalFooter>
+                    <ButtonWrapper>
+                        <Button
+                            on_click=move |_| set_show_modal.set(false)
+                            color=ButtonColor::Secondary
+                        >
+                            "Close"
+                        </Button>
+                    </ButtonWrapper>
+                </ModalFooter>
+            </Modal>
+        </div>
+    }
+}
+
+/// Picks the album shown, or all of the site's images. The hash route follows it, see `HashState`.
+/// Visitors shown an album only see its title and description, see `picker_albums`.
+#[component]
+pub fn AlbumPicker(
+    albums: RwSignal<Vec<Album>>,
+    current_album: RwSignal<Option<Album>>,
+    app_state_signal: RwSignal<Option<AppState>>,
+    list_image_resource: ReadSignal<Option<Result<Vec<S3ObjectInfo>, Error>>>,
+    update_error: RwSignal<Option<String>>,
+) -> impl IntoView {
+    let can_edit = get_current_config().map(|config| config.can_write()).unwrap_or(false);
+
+    view!{
+        <div>
+            <div style="display: flex; flex-direction: row; justify-content: center; align-items: center;">
+                {move || match picker_albums(&albums.get(), current_album.get().as_ref(), can_edit) {
+                    Some(choices) => view!{
+                        <div style="font-weight: bold; padding: 0 10px 0 10px;">"Album"</div>
+                        <select
+                            style="font-size: .8em;"
+                            on:change=move |evt| {
+                                let id = event_target_value(&evt);
+                                let album = albums.get_untracked().into_iter().find(|album| album.id == id);
+                                current_album.set(album);
+                            }
+                        >
+                            <option value="" selected={current_album.get().is_none()}>"All Images"</option>
+                            {choices.into_iter().map(|album| view!{
+                                <option
+                                    value={album.id.clone()}
+                                    selected={current_album.get().is_some_and(|current| current.id == album.id)}
+                                >
+                                    {album.title.clone()}
+                                </option>
+                            }).collect_view()}
+                        </select>
+                    }.into_view(),
+                    None => "".into_view(),
+                }}
+                {match can_edit {
+                    true => view!{
+                        <EditAlbumsControl
+                            albums={albums}
+                            current_album={current_album}
+                            app_state_signal={app_state_signal}
+                            list_image_resource={list_image_resource}
+                            update_error={update_error}
+                        />
+                    }.into_view(),
+                    false => "".into_view(),
+                }}
+            </div>
+            {move || match (current_album.get(), list_image_resource.get()) {
+                (Some(album), Some(Ok(image_list))) => view!{ <AlbumHeader album={album} image_list={image_list} /> }.into_view(),
+                _ => "".into_view(),
+            }}
+        </div>
+    }
+}
@@ -5,6 +5,10 @@ use crate::SiteSettings;
 use crate::SiteSelector;
 use crate::S3ObjectInfo;
 use crate::api::Error;
+use crate::caption::Caption;
+use crate::album::Album;
+use crate::AlbumPicker;
+use crate::versions::VersionPreview;
 
 #[component]
 pub fn Banner(
@@ -12,11 +16,14 @@ pub fn Banner(
     app_title: ReadSignal<String>,
     set_delete: WriteSignal<Option<String>>,
     rename: WriteSignal<String>,
-    caption: WriteSignal<Option<String>>,
+    caption: WriteSignal<Option<Caption>>,
     refresh_image_list: WriteSignal<bool>,
     public_settings: WriteSignal<SiteSettings>,
     update_error: RwSignal<Option<String>>,
     list_image_resource: ReadSignal<Option<Result<Vec<S3ObjectInfo>, Error>>>,
+    albums: RwSignal<Vec<Album>>,
+    current_album: RwSignal<Option<Album>>,
+    version_preview: RwSignal<Option<VersionPreview>>,
 ) 
   -> impl IntoView {
 
@@ -35,6 +42,13 @@ pub fn Banner(
             </div>
 
             <h3 class="banner">{move || app_title.get()}</h3>
+            <AlbumPicker
+                albums={albums}
+                current_album={current_album}
+                app_state_signal={app_state_signal}
+                list_image_resource={list_image_resource}
+                update_error={update_error}
+            />
             <div style="display:flex; flex-direction: row; justify-content: center">
                 {move || match list_image_resource.get() {
                     Some(image_list) => {
@@ -50,6 +64,7 @@ pub fn Banner(
                                         update_error={update_error}
                                         app_state_signal={app_state_signal}
                                         image_list={list}
+                                        version_preview={version_preview}
                                     />}.into_view()
                             },
                             Err(_) => {
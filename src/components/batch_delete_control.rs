@@ -0,0 +1,105 @@
+use leptos::*;
+use leptonic::prelude::*;
+use uuid::Uuid;
+
+use crate::api::Error;
+use crate::batch_delete::DeleteOutcome;
+use crate::get_current_config;
+use crate::object_store::use_object_store;
+use crate::trash::trash_images;
+use crate::ImageInfo;
+use crate::S3ObjectInfo;
+
+fn delete_report(outcomes: &[DeleteOutcome]) -> Toast {
+    let failures = outcomes.iter().filter(|o| o.error.is_some()).cloned().collect::<Vec<DeleteOutcome>>();
+    let trashed = outcomes.len() - failures.len();
+    let (variant, header) = match failures.is_empty() {
+        true => (ToastVariant::Success, format!("Moved {} images to the Trash", trashed)),
+        false => (ToastVariant::Error, format!("Moved {} of {} images to the Trash", trashed, outcomes.len())),
+    };
+    Toast {
+        id: Uuid::new_v4(),
+        created_at: time::OffsetDateTime::now_utc(),
+        variant,
+        header: header.into_view(),
+        body: failures.into_iter().map(|o| view!{
+            <div>{format!("{}: {}", o.key.replacen("images/", "", 1), o.error.unwrap_or_default())}</div>
+        }).collect_view(),
+        timeout: ToastTimeout::DefaultDelay,
+    }
+}
+
+/// Moves the images checked in the image list, along with their captions, to the trash.
+#[component]
+pub fn BatchDeleteControl(
+    list_image_resource: ReadSignal<Option<Result<Vec<S3ObjectInfo>, Error>>>,
+    selected: RwSignal<Vec<String>>,
+    refresh_image_list: WriteSignal<bool>,
+) -> impl IntoView {
+    let (show_modal, set_show_modal) = create_signal(false);
+    let toasts = expect_context::<Toasts>();
+    let store = use_object_store();
+
+    let can_delete = get_current_config()
+        .map(|config| config.can_write())
+        .unwrap_or(false);
+
+    let delete_action = create_action(move |_: &String| async move {
+        let keys = selected.get_untracked();
+        let images = match list_image_resource.get_untracked() {
+            Some(Ok(list)) => list.into_iter().map(|image| image.key()).filter(|key| keys.contains(key)).collect(),
+            _ => vec![],
+        };
+        let outcomes = trash_images(store.get_value().as_ref(), images, time::OffsetDateTime::now_utc()).await;
+        toasts.push(delete_report(&outcomes));
+        selected.set(vec![]);
+        refresh_image_list.set(true);
+    });
+
+    view!{
+        {move || match can_delete && !selected.get().is_empty() {
+            true => view!{
+                <div style="display: flex; flex-direction: row; align-items: center; padding: 0 0 10px 0;">
+                    <Button
+                        on_click=move |_| set_show_modal.set(true)
+                        color=ButtonColor::Danger
+                    >
+                        {format!("Delete {} Selected", selected.get().len())}
+                    </Button>
+                    <Button
+                        variant=ButtonVariant::Flat
+                        on_click=move |_| selected.set(vec![])
+                    >
+                        "Clear"
+                    </Button>
+                </div>
+            }.into_view(),
+            false => "".into_view(),
+        }}
+        <Modal show_when={show_modal}>
+            <ModalHeader><ModalTitle>"Delete Images"</ModalTitle></ModalHeader>
+            <ModalBody>
+                <div>{move || format!("Move the {} images and their captions to the Trash?", selected.get().len())}</div>
+            </ModalBody>
+            <ModalFooter>
+                <ButtonWrapper>
+                    <Button
+                        on_click=move |_| {
+                            delete_action.dispatch("batch delete".to_string());
+                            set_show_modal.set(false);
+                        }
+                        color=ButtonColor::Primary
+                    >
+                        "Delete"
+                    </Button>
+                    <Button
+                        on_click=move |_| set_show_modal.set(false)
+                        color=ButtonColor::Secondary
+                    >
+                        "Cancel"
+                    </Button>
+                </ButtonWrapper>
+            </ModalFooter>
+        </Modal>
+    }
+}
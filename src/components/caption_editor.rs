@@ -0,0 +1,110 @@
+use leptos::*;
+use leptonic::prelude::*;
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+
+use crate::rich_text::is_safe_link;
+
+// The Tiptap bundle's starter kit has lists, and js/tiptap-link.js adds links to it, but
+// leptos-tiptap has no messages for them, so they are changed on the editor it keeps for each
+// instance. A link is set on, or removed from, the whole link around the selection.
+#[wasm_bindgen(inline_js = "export function toggle_list(id, ordered) {
+    const instance = window._leptosTiptapEditors.get(id);
+    if (instance) {
+        const chain = instance.editor.chain().focus();
+        (ordered ? chain.toggleOrderedList() : chain.toggleBulletList()).run();
+    }
+}
+export function set_link(id, href) {
+    const instance = window._leptosTiptapEditors.get(id);
+    if (instance) {
+        const chain = instance.editor.chain().focus().extendMarkRange('link');
+        (href ? chain.setLink({ href }) : chain.unsetLink()).run();
+    }
+}")]
+extern "C" {
+    fn toggle_list(id: String, ordered: bool);
+    fn set_link(id: String, href: Option<String>);
+}
+
+fn button_class(active: bool) -> String {
+    format!("leptonic-tiptap-btn {}", if active { "active" } else { "" })
+}
+
+/// A rich text editor for a caption's description. The editor starts with `value` and sets
+/// `set_value` to its HTML as it is edited, which is sanitized when it is stored. The selected
+/// text is linked to the address entered, which must be a web or mail address.
+#[component]
+pub fn CaptionEditor(
+    #[prop(into)]
+    value: Signal<String>,
+    set_value: WriteSignal<String>,
+) -> impl IntoView {
+    let (msg, set_msg) = create_signal(TiptapInstanceMsg::Noop);
+    let (selection, set_selection) = create_signal(TiptapSelectionState::default());
+    let id = store_value(Uuid::new_v4().to_string());
+    let (href, set_href) = create_signal("".to_string());
+
+    view!{
+        <leptonic-tiptap-editor>
+            <leptonic-tiptap-menu>
+                <Button
+                    class=Signal::derive(move || button_class(selection.get().bold))
+                    size=ButtonSize::Small
+                    on_click=move |_| set_msg.set(TiptapInstanceMsg::Bold)
+                >
+                    "Bold"
+                </Button>
+                <Button
+                    class=Signal::derive(move || button_class(selection.get().italic))
+                    size=ButtonSize::Small
+                    on_click=move |_| set_msg.set(TiptapInstanceMsg::Italic)
+                >
+                    "Italic"
+                </Button>
+                <Button
+                    class=button_class(false)
+                    size=ButtonSize::Small
+                    on_click=move |_| toggle_list(id.get_value(), false)
+                >
+                    "Bulleted List"
+                </Button>
+                <Button
+                    class=button_class(false)
+                    size=ButtonSize::Small
+                    on_click=move |_| toggle_list(id.get_value(), true)
+                >
+                    "Numbered List"
+                </Button>
+                <TextInput get={href} set={set_href} placeholder="https://" />
+                <Button
+                    class=button_class(false)
+                    size=ButtonSize::Small
+                    disabled=Signal::derive(move || !is_safe_link(&href.get()))
+                    on_click=move |_| set_link(id.get_value(), Some(href.get_untracked().trim().to_string()))
+                >
+                    "Link"
+                </Button>
+                <Button
+                    class=button_class(false)
+                    size=ButtonSize::Small
+                    on_click=move |_| set_link(id.get_value(), None)
+                >
+                    "Unlink"
+                </Button>
+            </leptonic-tiptap-menu>
+            <TiptapInstance
+                id=id.get_value()
+                msg=msg
+                disabled=false
+                value=value
+                set_value=move |content| {
+                    if let TiptapContent::Html(html) = content {
+                        set_value.set(html);
+                    }
+                }
+                on_selection_change=move |state| set_selection.set(state)
+            />
+        </leptonic-tiptap-editor>
+    }
+}
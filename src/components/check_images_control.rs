@@ -0,0 +1,70 @@
+use leptos::*;
+use leptonic::prelude::*;
+use uuid::Uuid;
+
+use crate::checksum::{check_images, ImageCheck};
+use crate::object_store::use_object_store;
+
+/// Reads every image of the current site and lists those which no longer match the checksum they
+/// were uploaded with, so they can be uploaded again, and those which could not be read.
+#[component]
+pub fn CheckImagesControl() -> impl IntoView {
+    let toasts = expect_context::<Toasts>();
+    let store = use_object_store();
+    let (progress, set_progress) = create_signal(None::<(usize, usize)>);
+    let (check, set_check) = create_signal(ImageCheck::default());
+
+    let check_action = create_action(move |_: &String| async move {
+        set_progress.set(Some((0, 0)));
+        set_check.set(ImageCheck::default());
+        let on_progress = move |checked, total| set_progress.set(Some((checked, total)));
+        let (variant, header, body) = match check_images(store.get_value().as_ref(), &on_progress).await {
+            Ok(found) if found == ImageCheck::default() => (ToastVariant::Success, "Images checked", "Every image matches its checksum.".to_string()),
+            Ok(found) => {
+                let body = format!("{} images do not match their checksums, {} could not be read.", found.corrupt.len(), found.unreadable.len());
+                set_check.set(found);
+                (ToastVariant::Warn, "Corrupt images found", body)
+            },
+            Err(err) => (ToastVariant::Error, "Checking images failed", format!("Unable to check the images. {}", err)),
+        };
+        set_progress.set(None);
+        toasts.push(Toast {
+            id: Uuid::new_v4(),
+            created_at: time::OffsetDateTime::now_utc(),
+            variant,
+            header: header.into_view(),
+            body: body.into_view(),
+            timeout: ToastTimeout::DefaultDelay,
+        });
+    });
+
+    view!{
+        <div style="padding: 10px 0 10px 0;">
+            <div style="font-weight: bold;">"Check this site's images"</div>
+            <div>"Images which no longer match the checksum they were uploaded with are listed, upload them again."</div>
+            <div style="display: flex; flex-direction: row; align-items: center;">
+                <Button
+                    disabled = Signal::derive(move || progress.get().is_some())
+                    on_click = move |_| check_action.dispatch("check".to_string())
+                >
+                    "Check Images"
+                </Button>
+                <div>{move || progress.get().map(|(checked, total)| format!("{} of {}", checked, total))}</div>
+            </div>
+            {move || match check.get().corrupt.is_empty() {
+                true => ().into_view(),
+                false => view!{
+                    <div style="font-weight: bold;">"Corrupt"</div>
+                    {check.get().corrupt.into_iter().map(|key| view!{ <div>{key.replacen("images/", "", 1)}</div> }).collect_view()}
+                }.into_view(),
+            }}
+            {move || match check.get().unreadable.is_empty() {
+                true => ().into_view(),
+                false => view!{
+                    <div style="font-weight: bold;">"Unreadable"</div>
+                    {check.get().unreadable.into_iter().map(|(key, err)| view!{ <div>{format!("{}: {}", key.replacen("images/", "", 1), err)}</div> }).collect_view()}
+                }.into_view(),
+            }}
+        </div>
+    }
+}
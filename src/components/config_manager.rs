@@ -3,11 +3,48 @@ use leptonic::prelude::*;
 use crate::DeviceType;
 use crate::PurchaseControl;
 use crate::SiteSelector;
+use crate::PushLocalSiteControl;
+use crate::FixHeadersControl;
+use crate::CheckImagesControl;
 use uuid::Uuid;
 
 use crate::local_config::*;
 use crate::get_device_type;
 
+use time::{Duration, OffsetDateTime};
+use time::macros::format_description;
+
+// Warn this long before temporary credentials expire.
+pub const EXPIRY_WARNING_MINUTES: i64 = 60;
+
+fn expiration_display(config: &SiteConfig) -> String {
+    config.expiration
+        .and_then(|e| OffsetDateTime::from_unix_timestamp(e).ok())
+        .and_then(|e| e.format(format_description!("[year]-[month]-[day] [hour]:[minute] UTC")).ok())
+        .unwrap_or_default()
+}
+
+/// Warns when the current site's temporary credentials have expired or expire soon.
+#[component]
+pub fn CredentialsExpiryNotice() -> impl IntoView {
+    let now = OffsetDateTime::now_utc();
+    match get_current_config() {
+        Ok(config) if config.credentials_expired(now) => view!{
+            <div style="padding: 10px 0 10px 0;">
+                <Chip color=ChipColor::Danger>{format!("Credentials for {} expired at {}", config.s3_bucket_name(), expiration_display(&config))}</Chip>
+                <div>"Add a site config token with fresh credentials under \"Your Sites\" to keep editing."</div>
+            </div>
+        }.into_view(),
+        Ok(config) if config.credentials_expire_within(now, Duration::minutes(EXPIRY_WARNING_MINUTES)) => view!{
+            <div style="padding: 10px 0 10px 0;">
+                <Chip color=ChipColor::Warn>{format!("Credentials for {} expire at {}", config.s3_bucket_name(), expiration_display(&config))}</Chip>
+                <div>"Add a site config token with fresh credentials under \"Your Sites\" before then."</div>
+            </div>
+        }.into_view(),
+        _ => "".into_view(),
+    }
+}
+
 #[component]
 pub fn ConfigList(
     sites: ReadSignal<Vec<SiteConfig>>,
@@ -36,6 +73,7 @@ pub fn ConfigList(
                             <Th min_width=true>""</Th>
                             <Th min_width=true>"Bucket"</Th>
                             <Th min_width=true>"Access Key"</Th>
+                            <Th min_width=true>"Expires"</Th>
                         </Tr>
                     </Thead>
                         {move || sites.get().to_vec().into_iter()
@@ -63,8 +101,12 @@ pub fn ConfigList(
                                 "Copy"
                             </Button>
                         </Td>
-                        <Td>{move || site.get().s3_bucket_name()}</Td>
+                        <Td>{move || match site.get().is_local() {
+                            true => format!("{} (this browser)", site.get().s3_bucket_name()),
+                            false => site.get().s3_bucket_name(),
+                        }}</Td>
                         <Td>{move || site.get().access_key}</Td>
+                        <Td>{move || expiration_display(&site.get())}</Td>
                     </Tr>
                 }}).collect_view()}
             </Table>
@@ -78,13 +120,38 @@ pub fn AddConfig(config_added: WriteSignal<bool>) -> impl IntoView {
     let toasts = expect_context::<Toasts>();
     let (input, set_input) = create_signal( "".to_string());
     let (add, set_add) = create_signal(false);
+    let (site_kind, set_site_kind) = create_signal(SiteKind::S3);
 
     create_effect( move |_| {
-        if add.get() {
+        if add.get() && site_kind.get_untracked() == SiteKind::Local {
+            // a local site needs only a name, its images stay in this browser
+            match add_site_config(SiteConfig::local(input.get_untracked())) {
+                Ok(config) => {
+                    set_input.set("".to_string());
+                    config_added.set(true);
+                    let _s_ = set_current_site(config.id);
+                    let _ = leptos::window().location().reload();
+                },
+                Err(err) => {
+                    toasts.push(
+                        Toast {
+                            id: Uuid::new_v4(),
+                            created_at: time::OffsetDateTime::now_utc(),
+                            variant: ToastVariant::Error,
+                            header: "Add site failed.".into_view(),
+                            body: format!("Unable to add site. {}", err).into_view(),
+                            timeout:  ToastTimeout::DefaultDelay,
+                        }
+                    );
+                },
+            }
+            set_add.set(false);
+        } else if add.get() {
             if input.get().len() > 10 {
                 match SiteConfig::from_encoded(input.get_untracked()) {
                     Ok(new_site_config) => {
-                        match add_site_config(new_site_config) {
+                        // fresh temporary credentials replace those of an existing site
+                        match add_or_refresh_site_config(new_site_config) {
                             Ok(config) => {
                                 set_input.set("".to_string());
                                 config_added.set(true);
@@ -127,13 +194,28 @@ pub fn AddConfig(config_added: WriteSignal<bool>) -> impl IntoView {
 
     view!{
         <div>
+            <select style="font-size: .8em;"
+                on:change={move |evt| set_site_kind.set(match event_target_value(&evt).as_str() {
+                    "local" => SiteKind::Local,
+                    _ => SiteKind::S3,
+                })}
+            >
+                <option value="s3">"S3 Bucket or WebDAV"</option>
+                <option value="local">"Local, in this browser"</option>
+            </select>
             <TextInput
-                placeholder = "add your site config token here ..."
+                placeholder = Signal::derive(move || match site_kind.get() {
+                    SiteKind::Local => "name your local site ...".to_string(),
+                    _ => "add your site config token here ...".to_string(),
+                })
                 get={input}
                 set={set_input}
             />
             <Button
-                disabled = Signal::derive( move || {input.get().len() < 10})
+                disabled = Signal::derive( move || match site_kind.get() {
+                    SiteKind::Local => input.get().trim().is_empty(),
+                    _ => input.get().len() < 10,
+                })
                 on_click=move |_evt| {
                     set_add.set(!add.get_untracked());
                 }
@@ -222,6 +304,7 @@ pub fn ConfigManager()
                 DeviceType::Desktop => "width: 650px; min-width: 650px; min-height: 450px;",
             }}
         >
+            <CredentialsExpiryNotice />
             <Tabs>
                 <Tab name="tab-1" label="Purchase Site".into_view() >
                     <PurchaseControl />
@@ -229,6 +312,11 @@ pub fn ConfigManager()
                 <Tab name="tab-2" label="Your Sites".into_view() >
                     <SiteSelector set_site_updated = {set_site_updated} />
                     <EditConfig />
+                    {match get_current_config() {
+                        Ok(config) if config.is_local() => view!{ <PushLocalSiteControl /> }.into_view(),
+                        Ok(config) if config.signing_credentials().is_some() => view!{ <FixHeadersControl /> <CheckImagesControl /> }.into_view(),
+                        _ => "".into_view(),
+                    }}
                 </Tab>
             </Tabs>
         </div>
@@ -0,0 +1,49 @@
+use leptos::*;
+use leptonic::prelude::*;
+use uuid::Uuid;
+
+use crate::object_headers::fix_site_headers;
+use crate::object_store::use_object_store;
+
+/// Stores the current site's objects again with the `Content-Type`, `Cache-Control` and
+/// `Content-Disposition` uploads are now given, for objects uploaded before they were.
+#[component]
+pub fn FixHeadersControl() -> impl IntoView {
+    let toasts = expect_context::<Toasts>();
+    let store = use_object_store();
+    let (progress, set_progress) = create_signal(None::<(usize, usize)>);
+
+    let fix_action = create_action(move |_: &String| async move {
+        set_progress.set(Some((0, 0)));
+        let on_progress = move |fixed, total| set_progress.set(Some((fixed, total)));
+        let (variant, header, body) = match fix_site_headers(store.get_value().as_ref(), &on_progress).await {
+            Ok(fixed) => (ToastVariant::Success, "Headers fixed", format!("Updated the headers of {} objects.", fixed)),
+            Err(err) => (ToastVariant::Error, "Fixing headers failed", format!("Unable to update the headers. {}", err)),
+        };
+        set_progress.set(None);
+        toasts.push(Toast {
+            id: Uuid::new_v4(),
+            created_at: time::OffsetDateTime::now_utc(),
+            variant,
+            header: header.into_view(),
+            body: body.into_view(),
+            timeout: ToastTimeout::DefaultDelay,
+        });
+    });
+
+    view!{
+        <div style="padding: 10px 0 10px 0;">
+            <div style="font-weight: bold;">"Fix the headers of this site's images and captions"</div>
+            <div>"Images uploaded by earlier versions download instead of showing when opened from the bucket."</div>
+            <div style="display: flex; flex-direction: row; align-items: center;">
+                <Button
+                    disabled = Signal::derive(move || progress.get().is_some())
+                    on_click = move |_| fix_action.dispatch("fix".to_string())
+                >
+                    "Fix Headers"
+                </Button>
+                <div>{move || progress.get().map(|(fixed, total)| format!("{} of {}", fixed, total))}</div>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,271 @@
+use leptos::*;
+use leptonic::prelude::*;
+use leptos_icons::AiIcon::AiCloudUploadOutlined;
+
+use time::{Duration, OffsetDateTime};
+
+use crate::api::Error;
+use crate::guest_upload::{inbox_name, GuestUploadGrant, INBOX_PREFIX};
+use crate::S3ObjectInfo;
+use crate::ImageInfo;
+use crate::ConfigManager;
+use crate::FileEditAndPublishControl;
+
+use crate::get_current_config;
+use crate::object_store::{list_all, use_object_store};
+use crate::manifest::{now_iso, record_image};
+use crate::object_read_url;
+
+fn app_base_url() -> String {
+    let location = leptos::window().location();
+    format!(
+        "{}{}",
+        location.origin().unwrap_or_default(),
+        location.pathname().unwrap_or_default(),
+    )
+}
+
+#[component]
+fn GuestLinkCreator() -> impl IntoView {
+    let (hours, set_hours) = create_signal(72.0);
+    let (max_mb, set_max_mb) = create_signal(20.0);
+    let (link, set_link) = create_signal(None::<String>);
+
+    view!{
+        <div style="width: 400px">
+            <div>"Anyone with the link can upload images to the inbox until it expires."</div>
+            <div>"Link expires after (hours, at most 168)"</div>
+            <NumberInput get={hours} set={set_hours} min=1.0 max=168.0 />
+            <div>"Maximum image size (Mb)"</div>
+            <NumberInput get={max_mb} set={set_max_mb} min=1.0 max=100.0 />
+            <div style="padding: 10px 0 10px 0;">
+                <Button
+                    on_click=move |_| {
+                        let valid_for = Duration::seconds((hours.get_untracked().clamp(1.0, 168.0) * 3600.0) as i64);
+                        let max_bytes = (max_mb.get_untracked().clamp(1.0, 100.0) * 1_000_000.0) as u64;
+                        let grant = get_current_config().ok().and_then(|config| {
+                            GuestUploadGrant::new(&config, valid_for, max_bytes, OffsetDateTime::now_utc())
+                        });
+                        match grant {
+                            Some(g) => {
+                                let url = g.link(&app_base_url());
+                                let _ = leptos::window().navigator().clipboard().write_text(&url);
+                                set_link.set(Some(url));
+                            },
+                            None => set_link.set(None),
+                        }
+                    }
+                    color=ButtonColor::Primary
+                >
+                    "Create and Copy Link"
+                </Button>
+            </div>
+            {move || match link.get() {
+                Some(url) => view!{
+                    <div style="word-break: break-all; font-size: .8em;">{url}</div>
+                }.into_view(),
+                None => "".into_view(),
+            }}
+        </div>
+    }
+}
+
+#[component]
+fn GuestInbox(
+    refresh_image_list: WriteSignal<bool>,
+    update_error: RwSignal<Option<String>>,
+) -> impl IntoView {
+    let (inbox, set_inbox) = create_signal(None::<Result<Vec<S3ObjectInfo>, Error>>);
+    let store = use_object_store();
+
+    let load_inbox = create_action(move |_: &String| async move {
+        set_inbox.set(Some(list_all(store.get_value().as_ref(), INBOX_PREFIX).await));
+    });
+
+    let approve = create_action(move |key: &String| {
+        let key = key.clone();
+        async move {
+            let name = inbox_name(&key);
+            let image_key = format!("images/{}", name);
+            match store.get_value().move_object(&key, &image_key).await {
+                Ok(_) => {
+                    // a guest's upload is stored with its Content-Type only
+                    if let Err(err) = store.get_value().fix_headers(&image_key).await {
+                        log::info!("unable to update the headers of {}: {}", image_key, err);
+                    }
+                    if let Err(err) = record_image(store.get_value().as_ref(), &image_key, None, now_iso()).await {
+                        update_error.set(Some(format!("'{}' was approved but the gallery could not be updated. {}", name, err)));
+                    }
+                    refresh_image_list.set(true)
+                },
+                Err(err) => update_error.set(Some(format!("Unable to approve '{}'. {}", name, err))),
+            }
+            load_inbox.dispatch("approved".to_string());
+        }
+    });
+
+    let reject = create_action(move |key: &String| {
+        let key = key.clone();
+        async move {
+            if let Err(err) = store.get_value().delete(&key, None).await {
+                update_error.set(Some(format!("Unable to remove '{}'. {}", key, err)));
+            }
+            load_inbox.dispatch("rejected".to_string());
+        }
+    });
+
+    load_inbox.dispatch("inbox".to_string());
+
+    view!{
+        <div style="width: 400px">
+            <Button on_click=move |_| load_inbox.dispatch("refresh".to_string())>
+                "Refresh"
+            </Button>
+            {move || match inbox.get() {
+                Some(Ok(items)) if items.is_empty() => view!{<div>"The inbox is empty."</div>}.into_view(),
+                Some(Ok(items)) => items.into_iter().map(|item| {
+                    let key = item.key();
+                    let (approve_key, reject_key) = (key.clone(), key.clone());
+                    view!{
+                        <div style="display: flex; flex-direction: row; align-items: center; padding: 5px 0 5px 0;">
+                            <img style="max-width: 100px; max-height: 100px;" src={object_read_url(key.clone())} />
+                            <div style="padding: 0 10px 0 10px; flex-grow: 1;">{inbox_name(&key)}</div>
+                            <Button
+                                on_click=move |_| approve.dispatch(approve_key.clone())
+                                color=ButtonColor::Primary
+                            >
+                                "Approve"
+                            </Button>
+                            <Button
+                                on_click=move |_| reject.dispatch(reject_key.clone())
+                                color=ButtonColor::Secondary
+                            >
+                                "Reject"
+                            </Button>
+                        </div>
+                    }
+                }).collect_view(),
+                Some(Err(err)) => format!("Unable to load the inbox. {}", err).into_view(),
+                None => "Loading ...".into_view(),
+            }}
+        </div>
+    }
+}
+
+#[component]
+pub fn GuestUploadControl(
+    refresh_image_list: WriteSignal<bool>,
+    update_error: RwSignal<Option<String>>,
+) -> impl IntoView {
+    let (show_modal, set_show_modal) = create_signal(false);
+
+    let (can_manage, set_can_manage) = create_signal(false);
+    if let Ok(config) = get_current_config() {
+        set_can_manage.set(config.signing_credentials().is_some());
+    }
+
+    view!{
+        <div>
+            <div title="Guest Uploads">
+                <Button
+                    variant=ButtonVariant::Flat
+                    on_click=move |_ev| {
+                        set_show_modal.set(true);
+                    }
+                >
+                    <div>
+                        <Icon icon=leptos_icons::Icon::from(AiCloudUploadOutlined) />
+                        <div class="iconButtonText">"Guest Uploads"</div>
+                    </div>
+                </Button>
+            </div>
+
+            {move || match can_manage.get() {
+                true => view!{
+                    <Modal show_when = {show_modal}>
+                        <ModalHeader><ModalTitle>"Guest Uploads"</ModalTitle></ModalHeader>
+                        <ModalBody>
+                            <Tabs mount=Mount::WhenShown>
+                                <Tab name="guest-link" label="Create Link".into_view()>
+                                    <GuestLinkCreator />
+                                </Tab>
+                                <Tab name="guest-inbox" label="Inbox".into_view()>
+                                    <GuestInbox
+                                        refresh_image_list={refresh_image_list}
+                                        update_error={update_error}
+                                    />
+                                </Tab>
+                            </Tabs>
+                        </ModalBody>
+                        <ModalFooter>
+                            <ButtonWrapper>
+                                <Button
+                                    on_click=move |_| {
+                                        set_show_modal.set(false);
+                                    }
+                                    color=ButtonColor::Secondary
+                                >
+                                    "Close"
+                                </Button>
+                            </ButtonWrapper>
+                        </ModalFooter>
+                    </Modal>
+                },
+                false => view!{
+                    <Modal show_when={show_modal}>
+                        <ModalHeader>
+                            "Credentials Required for Guest Uploads"
+                        </ModalHeader>
+                        <ModalBody>
+                            <div style="padding: 10px;">"Set or purchase credentials below"</div>
+                            <ConfigManager />
+                            <div style="padding: 10px;">
+                                <Button on_click=move |_evt| set_show_modal.set(false)>
+                                    "Cancel"
+                                </Button>
+                            </div>
+                        </ModalBody>
+                    </Modal>
+                },
+            }}
+        </div>
+    }
+}
+
+// Page shown to guests opening an upload link.
+#[component]
+pub fn GuestUpload(
+    grant: GuestUploadGrant,
+) -> impl IntoView {
+    let (uploaded, set_uploaded) = create_signal(false);
+    let (_, set_show_modal) = create_signal(true);
+    let expired = grant.is_expired(OffsetDateTime::now_utc());
+    let bucket = grant.bucket.clone();
+
+    view!{
+        <div class="weblumapp">
+            <h3 class="banner">"Contribute Photos"</h3>
+            {match expired {
+                true => view!{
+                    <div>"This upload link has expired. Ask the site owner for a new one."</div>
+                }.into_view(),
+                false => view!{
+                    <div>
+                        <div style="padding: 10px 0 10px 0;">
+                            {format!("Images you upload are sent to {} and published once the owner approves them.", bucket)}
+                        </div>
+                        {move || match uploaded.get() {
+                            true => view!{<Chip color=ChipColor::Success>"Upload received"</Chip>}.into_view(),
+                            false => "".into_view(),
+                        }}
+                        <FileEditAndPublishControl
+                            update_list={set_uploaded}
+                            set_show_modal={set_show_modal}
+                            guest_grant={grant}
+                        />
+                    </div>
+                }.into_view(),
+            }}
+        </div>
+    }
+}
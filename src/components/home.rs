@@ -13,13 +13,17 @@ use crate::Banner;
 use crate::DeviceType;
 use crate::SiteSettings;
 use crate::api::Error;
+use crate::caption::Caption;
+use crate::album::Album;
+use crate::versions::VersionPreview;
 
 use crate::local_config::*;
 use crate::get_device_type;
+use crate::EXPIRY_WARNING_MINUTES;
 use crate::delete_object;
 use crate::rename_image;
-use crate::update_comment;
 use crate::update_app_state;
+use crate::object_store::use_object_store;
 
 
 
@@ -32,6 +36,8 @@ pub fn Home(
   public_settings: WriteSignal<SiteSettings>,
   current_image: ReadSignal<Option<String>>,
   set_current_image: WriteSignal<Option<String>>,
+  albums: RwSignal<Vec<Album>>,
+  current_album: RwSignal<Option<Album>>,
   ) -> impl IntoView {
     let (current_config, set_current_config) = create_signal(None::<SiteConfig>);
 
@@ -39,6 +45,25 @@ pub fn Home(
 
     let update_error = create_rw_signal(None::<String>);
 
+    let store = use_object_store();
+
+    if let Ok(config) = get_current_config() {
+        let now = time::OffsetDateTime::now_utc();
+        if config.credentials_expire_within(now, time::Duration::minutes(EXPIRY_WARNING_MINUTES)) {
+            update_error.set(Some("The site's temporary credentials expire soon. Add fresh credentials in Settings.".to_string()));
+        }
+    }
+    // keys of the images checked in the list
+    let selected_images = create_rw_signal(Vec::<String>::new());
+    // an earlier version shown in place of the current image, see `VersionHistoryControl`
+    let version_preview = create_rw_signal(None::<VersionPreview>);
+
+    // moving to another image ends the preview
+    create_effect(move |_| {
+        app_state_signal.track();
+        version_preview.set(None);
+    });
+
     let toasts = expect_context::<Toasts>();
 
     create_effect(move |_| {
@@ -81,7 +106,7 @@ pub fn Home(
 
     let (delete, set_delete) = create_signal(None::<String>);
 
-    let (caption, set_caption) = create_signal(None::<String>);
+    let (caption, set_caption) = create_signal(None::<Caption>);
 
     create_effect(move |_| {
         config_updated.get(); 
@@ -96,7 +121,7 @@ pub fn Home(
 
     let delete_action = create_action(move |_: &String| async move {
         let key = format!("images/{}", delete.get_untracked().expect("delete key expected"));
-        if delete_object(key, update_error).await {
+        if delete_object(store.get_value().as_ref(), key, update_error).await {
             set_refetch_list_signal.set(true)
         }
     });
@@ -114,7 +139,11 @@ pub fn Home(
                     Some(list_result) => {
                         match list_result {
                             Ok(list) => {
-                                rename_image(app_state.current_image_display_name(list), current_name.get_untracked(), update_error).await;
+                                let e_tag = app_state.e_tag(list.clone());
+                                let old_name = app_state.current_image_display_name(list);
+                                if let Err(err) = rename_image(store.get_value().as_ref(), &old_name, &current_name.get_untracked(), &e_tag).await {
+                                    update_error.set(Some(err));
+                                }
                                 set_refetch_list_signal.set(true);
                                 set_current_name.set("".to_string());
                             },
@@ -128,10 +157,8 @@ pub fn Home(
         }
     });
 
+    // the caption is stored by `EditCaptionControl`, which reports it here to be shown
     let update_caption_action = create_action(move |_: &String| async move {
-      let list = list_image_resource.get_untracked().expect("image list expected").expect("image list result expected");
-      let e_tag = app_state_signal.get_untracked().expect("app state expected").e_tag(list);
-      update_comment(caption.get_untracked().expect("caption expected"), e_tag, update_error).await;
       let mut app_state = app_state_signal.get_untracked().expect("app state expected");
       app_state.current_caption = Some(caption.get_untracked().expect("caption expected"));
       app_state_signal.set(Some(app_state));
@@ -176,7 +203,7 @@ pub fn Home(
                   Some(mut app_state) => {
                     if app_state.current_image_name(list.clone()) != i {
                       let new_state = app_state.set_current_image(i, list.clone());
-                      update_app_state(app_state_signal, new_state, list);
+                      update_app_state(store, app_state_signal, new_state, list);
                     }
                   }
                   None => ()
@@ -226,6 +253,9 @@ pub fn Home(
                                     refresh_image_list={set_refetch_list_signal}
                                     public_settings={public_settings}
                                     update_error={update_error}
+                                    albums={albums}
+                                    current_album={current_album}
+                                    version_preview={version_preview}
                                 />
                                 <div 
                                     style = {match get_device_type() {
@@ -237,11 +267,17 @@ pub fn Home(
                                     list_image_resource={list_image_resource}
                                     current_image={set_current_image}
                                     read_current_image={read_current_image}
+                                    selected={selected_images}
+                                    refresh_image_list={set_refetch_list_signal}
                                 />
   
                                 <ImageDisplay 
                                     app_state_signal={app_state_signal}
                                     list_image_resource={list_image_resource}
+                                    version_preview={version_preview}
+                                    caption={set_caption}
+                                    refresh_image_list={set_refetch_list_signal}
+                                    update_error={update_error}
                                 />
                             </div>
                         </div>
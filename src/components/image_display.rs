@@ -3,6 +3,8 @@ use leptos::*;
 use crate::AppState;
 use crate::DeviceType;
 use crate::S3ObjectInfo;
+use crate::caption::Caption;
+use crate::rich_text::sanitize_html;
 use crate::LoadingIndicator;
 
 use leptos_icons::BiIcon::BiDownloadSolid;
@@ -11,15 +13,22 @@ use crate::api::Error;
 use leptonic::prelude::*;
 
 use crate::update_app_state;
-use crate::local_config::get_current_config;
+use crate::object_store::use_object_store;
+use crate::object_store::{use_display_url, use_version_display_url};
+use crate::versions::VersionPreview;
+use crate::VersionPreviewBanner;
 
 use crate::get_device_type;
 
 #[component]
 fn ImageBody (
     image_key: String,
-    image_caption: Option<String>,
+    image_caption: Option<Caption>,
+    #[prop(optional)]
+    version_id: Option<String>,
 ) -> impl IntoView {
+    let src = use_version_display_url(use_object_store(), image_key, version_id);
+    let caption = image_caption.unwrap_or_default();
     view!{
         <div>
             <img 
@@ -28,19 +37,24 @@ fn ImageBody (
                         DeviceType::Desktop => "max-width: 900px",
                         DeviceType::Mobile => "max-width: 400px",                      
                     }}
-                    src={format!("https://s3.{}.amazonaws.com/{}/{}", 
-                        get_current_config().expect("region").region,
-                        get_current_config().expect("bucket").s3_bucket_name(),
-                        image_key
-                    )}
+                    src={src}
+                    alt={caption.alt_text.clone()}
             />
             <div>
-                {
-                    match image_caption {
-                        Some(caption) => caption.into_view(),
-                        None => "".into_view()
-                    }
-                }
+                {match caption.hidden {
+                    // only the site's owner is shown hidden images
+                    true => view!{ <div class="imageHidden">"Hidden from visitors"</div> }.into_view(),
+                    false => "".into_view(),
+                }}
+                {match caption.title.is_empty() {
+                    true => "".into_view(),
+                    false => view!{ <h4>{caption.title.clone()}</h4> }.into_view(),
+                }}
+                <div inner_html=sanitize_html(&caption.description)></div>
+                <div class="imageTags">
+                    {caption.tags.iter().map(|tag| view!{ <span class="imageTag">{tag.clone()}</span> }).collect_view()}
+                </div>
+                <div class="imageDate">{caption.details().join(" · ")}</div>
             </div>
         </div>
     }
@@ -54,10 +68,12 @@ fn ImageHeader(
 -> impl IntoView {
 
     let (image_list_signal, _) = create_signal(image_list.clone());
+    let store = use_object_store();
 
     match app_state_signal.get_untracked() {
         Some(_app_state) => {
             let (app_state, _) = create_signal(_app_state.clone());
+            let download_url = use_display_url(store, _app_state.current_image_name(image_list.clone()));
 
             return view!{
 
@@ -74,7 +90,7 @@ fn ImageHeader(
                         disabled = Signal::derive(move || {!app_state.get().has_previous_image()})
                         on_click=move |_evt| {
                             let new_app_state = app_state.get().previous_image();
-                            update_app_state(app_state_signal, new_app_state, image_list_signal.get());                 
+                            update_app_state(store, app_state_signal, new_app_state, image_list_signal.get());                 
                         }
                     >
                         "Previous"       
@@ -87,11 +103,7 @@ fn ImageHeader(
 
                         <a class="link"
                             target={"_blank"} 
-                            href={format!("https://s3.{}.amazonaws.com/{}/images/{}",
-                                get_current_config().expect("region").region,
-                                get_current_config().expect("region").s3_bucket_name(),
-                                _app_state.current_image_display_name(image_list.clone())
-                            )}>
+                            href={download_url}>
                             <span title="Download image">
                                 <Icon icon=leptos_icons::Icon::from(BiDownloadSolid) />
                             </span>
@@ -102,7 +114,7 @@ fn ImageHeader(
                         disabled = Signal::derive(move || {!app_state.get().has_next_image(image_list.clone())})
                         on_click={move |_evt| {
                             let new_app_state = app_state.get().next_image(image_list_signal.get());
-                            update_app_state(app_state_signal, new_app_state, image_list_signal.get());           
+                            update_app_state(store, app_state_signal, new_app_state, image_list_signal.get());           
                         }}
                     >
                         "Next"     
@@ -118,6 +130,10 @@ fn ImageHeader(
 pub fn ImageDisplay(
     app_state_signal: RwSignal<Option<AppState>>,
     list_image_resource: ReadSignal<Option<Result<Vec<S3ObjectInfo>, Error>>>,
+    version_preview: RwSignal<Option<VersionPreview>>,
+    caption: WriteSignal<Option<Caption>>,
+    refresh_image_list: WriteSignal<bool>,
+    update_error: RwSignal<Option<String>>,
 ) -> impl IntoView {
     {
         move || match list_image_resource.get() {
@@ -126,15 +142,38 @@ pub fn ImageDisplay(
                     Ok(image_list) => {
                         match app_state_signal.get() {
                             Some(app_state) => {
+                                let current_key = app_state.clone().current_image_name(image_list.clone());
+                                let body = match version_preview.get() {
+                                    // a deleted image or another image's version is shown without a caption
+                                    Some(VersionPreview::Image(version)) => view!{
+                                        <ImageBody
+                                            image_caption={app_state.current_caption().filter(|_| version.key == current_key)}
+                                            image_key={version.key}
+                                            version_id={version.version_id}
+                                        />
+                                    },
+                                    Some(VersionPreview::Caption { caption, .. }) => view!{
+                                        <ImageBody image_key={current_key} image_caption={Some(caption)} />
+                                    },
+                                    None => view!{
+                                        <ImageBody image_key={current_key} image_caption={app_state.current_caption()} />
+                                    },
+                                };
                                 return view!{<div>
                                     <ImageHeader
                                         app_state_signal = {app_state_signal} 
                                         image_list={image_list.clone()}
                                     />
-                                    <ImageBody 
-                                        image_key=app_state.clone().current_image_name(image_list.clone())
-                                        image_caption=app_state.clone().current_caption()
-                                    />
+                                    {version_preview.get().map(|shown| view!{
+                                        <VersionPreviewBanner
+                                            shown={shown}
+                                            version_preview={version_preview}
+                                            caption={caption}
+                                            refresh_image_list={refresh_image_list}
+                                            update_error={update_error}
+                                        />
+                                    })}
+                                    {body}
                                 </div>}.into_view();
                             },
                             None => "no app state".into_view(),
@@ -5,12 +5,25 @@ use leptos_icons::AiIcon::*;
 use crate::FileEditAndPublishControl;
 use crate::SettingsControl;
 use crate::EditPublicSiteSettingsControl;
+use crate::GuestUploadControl;
 use crate::SiteSettings;
 use crate::ConfigManager;
 use crate::AppState;
 use crate::S3ObjectInfo;
+use crate::api::{read_caption, update_comment, Error};
+use crate::caption::{optional_text, Caption};
+use crate::conflict::Versioned;
+use crate::object_store::{use_object_store, WriteCondition};
+use crate::rich_text::sanitize_html;
+use crate::CaptionEditor;
+use crate::VersionHistoryControl;
+use crate::TrashControl;
+use crate::versions::VersionPreview;
+
+use gloo_net::http::Method;
 
 use crate::get_current_config;
+use crate::presigned_object_url;
 
 #[component]
 pub fn UploadModalControl(
@@ -65,24 +78,140 @@ pub fn UploadModalControl(
 }
 
 
+/// A version of a caption, as shown when saving it conflicts with another edit.
+#[component]
+fn CaptionVersion(
+    label: &'static str,
+    caption: Caption,
+) -> impl IntoView {
+    view!{
+        <div style="flex: 1; padding: 0 10px 0 10px;">
+            <div style="font-weight: bold;">{label}</div>
+            <h4>{caption.title.clone()}</h4>
+            <div inner_html=sanitize_html(&caption.description)></div>
+            <div>{caption.alt_text.clone()}</div>
+            <div class="imageTags">
+                {caption.tags.iter().map(|tag| view!{ <span class="imageTag">{tag.clone()}</span> }).collect_view()}
+            </div>
+            <div class="imageDate">{caption.details().join(" · ")}</div>
+            {match caption.hidden {
+                true => view!{ <div class="imageHidden">"Hidden from visitors"</div> }.into_view(),
+                false => "".into_view(),
+            }}
+        </div>
+    }
+}
+
+/// Edits the caption of the image shown. The caption is read when the editor opens and saved only
+/// if it has not changed since, otherwise the stored caption is shown to be overwritten, merged
+/// with the edits, or kept.
 #[component]
 pub fn EditCaptionControl(
-    update_caption: WriteSignal<Option<String>>,
+    update_caption: WriteSignal<Option<Caption>>,
     app_state_signal: RwSignal<Option<AppState>>,
-
+    image_list: Vec<S3ObjectInfo>,
+    update_error: RwSignal<Option<String>>,
 ) -> impl IntoView {
+    let store = use_object_store();
     let (show_modal, set_show_modal) = create_signal(false);
-    let caption_default = match app_state_signal.get_untracked().expect("").current_caption{
-        Some(c) => c,
-        None => "".to_string(),
+    let app_state = app_state_signal.get_untracked().expect("");
+    let key = store_value(app_state.current_image_name(image_list));
+    let caption_default = app_state.current_caption.unwrap_or_default();
+
+    let (title, set_title) = create_signal(caption_default.title.clone());
+    let (description, set_description) = create_signal(caption_default.description.clone());
+    let (alt_text, set_alt_text) = create_signal(caption_default.alt_text.clone());
+    let (tags, set_tags) = create_signal(caption_default.tags_text());
+    let (date_taken, set_date_taken) = create_signal(caption_default.date_taken.clone().unwrap_or_default());
+    let (location, set_location) = create_signal(caption_default.location.clone().unwrap_or_default());
+    let (license, set_license) = create_signal(caption_default.license.clone().unwrap_or_default());
+    let (hidden, set_hidden) = create_signal(caption_default.hidden);
+
+    let caption = create_memo(move |_| {
+        let mut caption = Caption {
+            title: title.get(),
+            description: sanitize_html(&description.get()),
+            alt_text: alt_text.get(),
+            date_taken: optional_text(date_taken.get()),
+            location: optional_text(location.get()),
+            license: optional_text(license.get()),
+            hidden: hidden.get(),
+            ..caption_default.clone()
+        };
+        caption.set_tags_text(&tags.get());
+        caption
+    });
+
+    let set_fields = move |caption: &Caption| {
+        set_title.set(caption.title.clone());
+        set_description.set(caption.description.clone());
+        set_alt_text.set(caption.alt_text.clone());
+        set_tags.set(caption.tags_text());
+        set_date_taken.set(caption.date_taken.clone().unwrap_or_default());
+        set_location.set(caption.location.clone().unwrap_or_default());
+        set_license.set(caption.license.clone().unwrap_or_default());
+        set_hidden.set(caption.hidden);
     };
 
-    let (caption, set_caption) = create_signal(caption_default);
+    // the caption as it was read, which the edits are saved over
+    let base = create_rw_signal(None::<Versioned<Caption>>);
+    // the caption as stored by another edit since `base` was read
+    let conflict = create_rw_signal(None::<Versioned<Caption>>);
+    // the editor is shown again once the caption is read, as it only reads its value when shown
+    let loaded = create_memo(move |_| base.with(Option::is_some));
+
+    let load_action = create_action(move |_: &()| async move {
+        match read_caption(store.get_value().as_ref(), &key.get_value()).await {
+            Ok(read) => {
+                set_fields(&read.value);
+                base.set(Some(read));
+            },
+            Err(err) => {
+                update_error.set(Some(format!("Unable to read the caption. {}", err)));
+                set_show_modal.set(false);
+            },
+        }
+    });
+
+    let save_action = create_action(move |condition: &WriteCondition| {
+        let condition = condition.clone();
+        async move {
+            update_error.set(None);
+            let new_caption = caption.get_untracked();
+            match update_comment(store.get_value().as_ref(), &new_caption, &key.get_value(), &condition).await {
+                Ok(e_tag) => {
+                    base.set(Some(Versioned { value: new_caption.clone(), e_tag: Some(e_tag) }));
+                    conflict.set(None);
+                    set_show_modal.set(false);
+                    update_caption.set(Some(new_caption));
+                },
+                Err(Error::PreconditionFailed) => match read_caption(store.get_value().as_ref(), &key.get_value()).await {
+                    Ok(remote) => conflict.set(Some(remote)),
+                    Err(err) => update_error.set(Some(format!("The caption was changed elsewhere and could not be read. {}", err))),
+                },
+                Err(err) => {
+                    update_error.set(Some(format!("error on update comment: {}", err)));
+                    set_show_modal.set(false);
+                },
+            }
+        }
+    });
+
+    // resolves a conflict, continuing the edit from the stored caption
+    let resolve = move |fields: Option<Caption>| {
+        if let Some(remote) = conflict.get_untracked() {
+            if let Some(fields) = fields {
+                set_fields(&fields);
+            }
+            base.set(Some(remote));
+            conflict.set(None);
+        }
+    };
 
     let (can_edit, set_can_edit) = create_signal(false);
     match get_current_config() {
         Ok(config) => {
-            if config.access_key.is_some() && config.secret_key.is_some() {
+            if config.can_write() {
                 set_can_edit.set(true);
             }
         },
@@ -95,7 +224,12 @@ pub fn EditCaptionControl(
                 <Button
                     variant=ButtonVariant::Flat
                     on_click=move |_ev| {
+                        base.set(None);
+                        conflict.set(None);
                         set_show_modal.set(true);
+                        if can_edit.get_untracked() {
+                            load_action.dispatch(());
+                        }
                     }
                 >
                     <div>
@@ -111,36 +245,97 @@ pub fn EditCaptionControl(
                     <Modal show_when = {show_modal}>
                         <ModalHeader><ModalTitle>"Edit Caption"</ModalTitle></ModalHeader>
                         <ModalBody>
-                            <div>
-                                <div>"Edit Caption : "</div>
-                                <TextInput
-                                    get = {caption}
-                                    set = {set_caption}
-                                />
-                            </div>
+                            {move || match (loaded.get(), conflict.get()) {
+                                (false, _) => view!{ <div>"Loading..."</div> }.into_view(),
+                                (true, Some(remote)) => view!{
+                                    <div>
+                                        <div>"The caption was changed elsewhere since it was opened."</div>
+                                        <div style="display: flex; flex-direction: row;">
+                                            <CaptionVersion label="Your Caption" caption={caption.get_untracked()} />
+                                            <CaptionVersion label="Stored Caption" caption={remote.value} />
+                                        </div>
+                                    </div>
+                                }.into_view(),
+                                (true, None) => view!{
+                                    <div>
+                                        <div>"Title"</div>
+                                        <TextInput get={title} set={set_title} />
+                                        <div>"Description"</div>
+                                        <CaptionEditor value={description} set_value={set_description} />
+                                        <div>"Alt Text"</div>
+                                        <TextInput get={alt_text} set={set_alt_text} placeholder="Describes the image for screen readers" />
+                                        <div>"Tags"</div>
+                                        <TextInput get={tags} set={set_tags} placeholder="Separated by commas" />
+                                        <div>"Date Taken"</div>
+                                        <TextInput get={date_taken} set={set_date_taken} placeholder="Replaces the date from the image" />
+                                        <div>"Location"</div>
+                                        <TextInput get={location} set={set_location} />
+                                        <div>"License"</div>
+                                        <TextInput get={license} set={set_license} />
+                                        <div>
+                                            <input
+                                                type="checkbox"
+                                                prop:checked=move || hidden.get()
+                                                on:change=move |ev| set_hidden.set(event_target_checked(&ev))
+                                            />
+                                            " Hidden from visitors"
+                                        </div>
+                                    </div>
+                                }.into_view(),
+                            }}
                         </ModalBody>
                         <ModalFooter>
                             <ButtonWrapper>
-                                <Button
-                                    disabled=Signal::derive(move || {
-                                        match app_state_signal.get_untracked() {
-                                            Some(app_state) => {
-                                                match app_state.current_caption {
-                                                    Some(c) => c == caption.get(),
-                                                    None => false,
+                                {move || match conflict.get() {
+                                    Some(remote) => view!{
+                                        <Button
+                                            on_click=move |_| save_action.dispatch(remote.condition())
+                                            color=ButtonColor::Danger
+                                        >
+                                            "Overwrite"
+                                        </Button>
+                                        <Button
+                                            on_click=move |_| {
+                                                let merged = match (base.get_untracked(), conflict.get_untracked()) {
+                                                    (Some(base), Some(remote)) => caption.get_untracked().merge(&base.value, &remote.value),
+                                                    _ => caption.get_untracked(),
+                                                };
+                                                resolve(Some(merged));
+                                            }
+                                            color=ButtonColor::Primary
+                                        >
+                                            "Merge"
+                                        </Button>
+                                        <Button
+                                            on_click=move |_| {
+                                                if let Some(remote) = conflict.get_untracked() {
+                                                    update_caption.set(Some(remote.value.clone()));
+                                                    resolve(Some(remote.value));
                                                 }
+                                                set_show_modal.set(false);
                                             }
-                                            None => false,
-                                        }
-                                    })
-                                    on_click=move |_| {
-                                        update_caption.set(Some(caption.get_untracked()));
-                                        set_show_modal.set(false);
-                                    }
-                                    color=ButtonColor::Primary
-                                >
-                                    "Update"
-                                </Button>
+                                            color=ButtonColor::Secondary
+                                        >
+                                            "Keep Stored"
+                                        </Button>
+                                    }.into_view(),
+                                    None => view!{
+                                        <Button
+                                            disabled=Signal::derive(move || {
+                                                base.with(|base| base.as_ref().is_none_or(|base| base.value == caption.get()))
+                                                    || save_action.pending().get()
+                                            })
+                                            on_click=move |_| {
+                                                if let Some(base) = base.get_untracked() {
+                                                    save_action.dispatch(base.condition());
+                                                }
+                                            }
+                                            color=ButtonColor::Primary
+                                        >
+                                            "Update"
+                                        </Button>
+                                    }.into_view(),
+                                }}
                                 <Button 
                                     on_click=move |_| {
                                         set_show_modal.set(false);
@@ -187,7 +382,7 @@ pub fn RenameImageControl(
     let (can_rename, set_can_rename) = create_signal(false);
     match get_current_config() {
         Ok(config) => {
-            if config.access_key.is_some() && config.secret_key.is_some() {
+            if config.can_write() {
                 set_can_rename.set(true);
             }
         },
@@ -296,6 +491,110 @@ pub fn PublicLink (
     }
 }
 
+#[component]
+pub fn ShareImageLinkControl (
+    read_image_name: ReadSignal<String>,
+) -> impl IntoView {
+    let (show_modal, set_show_modal) = create_signal(false);
+    let (hours, set_hours) = create_signal(24.0);
+    let (link, set_link) = create_signal(None::<Result<String, Error>>);
+
+    let (can_share, set_can_share) = create_signal(false);
+    if let Ok(config) = get_current_config() {
+        set_can_share.set(config.signing_credentials().is_some());
+    }
+
+    view!{
+        <div>
+            <Button
+                variant=ButtonVariant::Flat
+                on_click=move |_ev| {
+                    set_link.set(None);
+                    set_show_modal.set(true);
+                }
+            >
+                <div title="Share Image Link">
+                    <Icon icon=leptos_icons::Icon::from(AiLinkOutlined) />
+                    <div class="iconButtonText">"Share Image"</div>
+                </div>
+            </Button>
+
+            {move || match can_share.get() {
+                true => view!{
+                    <Modal show_when = {show_modal}>
+                        <ModalHeader><ModalTitle>"Share Image"</ModalTitle></ModalHeader>
+                        <ModalBody>
+                            <div style="width: 400px">
+                                <div>{format!("Time limited link to '{}'", read_image_name.get_untracked())}</div>
+                                <div>"Link expires after (hours, at most 168)"</div>
+                                <NumberInput
+                                    get = {hours}
+                                    set = {set_hours}
+                                    min = 1.0
+                                    max = 168.0
+                                />
+                                {move || match link.get() {
+                                    Some(Ok(url)) => view!{
+                                        <div style="padding: 10px 0 10px 0; word-break: break-all; font-size: .8em;">
+                                            <a href={url.clone()} target="_blank">{url.clone()}</a>
+                                        </div>
+                                    }.into_view(),
+                                    Some(Err(err)) => view!{
+                                        <div>{format!("Unable to create link. {}", err)}</div>
+                                    }.into_view(),
+                                    None => "".into_view(),
+                                }}
+                            </div>
+                        </ModalBody>
+                        <ModalFooter>
+                            <ButtonWrapper>
+                                <Button
+                                    on_click=move |_| {
+                                        let expires_in = (hours.get_untracked().clamp(1.0, 168.0) * 3600.0) as u32;
+                                        let key = format!("images/{}", read_image_name.get_untracked());
+                                        let result = presigned_object_url(Method::GET, key, expires_in);
+                                        if let Ok(url) = &result {
+                                            let _ = leptos::window().navigator().clipboard().write_text(url);
+                                        }
+                                        set_link.set(Some(result));
+                                    }
+                                    color=ButtonColor::Primary
+                                >
+                                    "Create and Copy Link"
+                                </Button>
+                                <Button
+                                    on_click=move |_| {
+                                        set_show_modal.set(false);
+                                    }
+                                    color=ButtonColor::Secondary
+                                >
+                                    "Close"
+                                </Button>
+                            </ButtonWrapper>
+                        </ModalFooter>
+                    </Modal>
+                },
+                false => view!{
+                    <Modal show_when={show_modal}>
+                        <ModalHeader>
+                            "Credentials Required for Sharing"
+                        </ModalHeader>
+                        <ModalBody>
+                            <div style="padding: 10px;">"Set or purchase credentials below"</div>
+                            <ConfigManager />
+                            <div style="padding: 10px;">
+                                <Button on_click=move |_evt| set_show_modal.set(false)>
+                                    "Cancel"
+                                </Button>
+                            </div>
+                        </ModalBody>
+                    </Modal>
+                },
+            }}
+        </div>
+    }
+}
+
 #[component]
 pub fn DeleteImageControl (
     delete: WriteSignal<Option<String>>,
@@ -310,7 +609,7 @@ pub fn DeleteImageControl (
     let (can_delete, set_can_delete) = create_signal(false);
     match get_current_config() {
         Ok(config) => {
-            if config.access_key.is_some() && config.secret_key.is_some() {
+            if config.can_write() {
                 set_can_delete.set(true);
             }
         },
@@ -336,7 +635,7 @@ pub fn DeleteImageControl (
                     <Modal show_when = {show_modal}>
                         <ModalHeader><ModalTitle>"Delete Image"</ModalTitle></ModalHeader>
                         <ModalBody>
-                            <div>{format!("Are you sure you want to delete the image: '{}' ?", read_image_name.get_untracked())}</div>
+                            <div>{format!("Move the image '{}' and its caption to the Trash?", read_image_name.get_untracked())}</div>
                         </ModalBody>
                         <ModalFooter>
                             <ButtonWrapper>
@@ -404,13 +703,13 @@ pub fn UpdateErrorDisplay (
 pub fn ImageDisplayButtons (
     delete: WriteSignal<Option<String>>,
     rename: WriteSignal<String>,
-    caption: WriteSignal<Option<String>>,
+    caption: WriteSignal<Option<Caption>>,
     refresh_image_list: WriteSignal<bool>,
     public_settings: WriteSignal<SiteSettings>,
     update_error: RwSignal<Option<String>>,
     app_state_signal: RwSignal<Option<AppState>>,
     image_list: Vec<S3ObjectInfo>,
-
+    version_preview: RwSignal<Option<VersionPreview>>,
 ) -> impl IntoView {
     
     {move || match app_state_signal.get() {
@@ -433,12 +732,33 @@ pub fn ImageDisplayButtons (
                 <EditCaptionControl
                     app_state_signal={app_state_signal}
                     update_caption={caption}
+                    image_list={image_list.clone()}
+                    update_error={update_error}
+                />
+                <VersionHistoryControl
+                    app_state_signal={app_state_signal}
+                    image_list={image_list.clone()}
+                    version_preview={version_preview}
+                    caption={caption}
+                    refresh_image_list={refresh_image_list}
+                    update_error={update_error}
+                />
+                <TrashControl
+                    refresh_image_list={refresh_image_list}
+                    update_error={update_error}
                 />
                 <SettingsControl />
                 <EditPublicSiteSettingsControl
                     update_error={update_error}
                     settings_updated={public_settings}
                 />
+                <ShareImageLinkControl
+                    read_image_name={read_image_name}
+                />
+                <GuestUploadControl
+                    refresh_image_list={refresh_image_list}
+                    update_error={update_error}
+                />
                 <PublicLink />
             </div>
         }.into_view()},
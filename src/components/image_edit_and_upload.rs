@@ -13,11 +13,10 @@ use image::io::Reader;
 use image::imageops::FilterType;
 use image::GenericImageView;
 
-use js_sys::Uint8Array;
-
 use wasm_bindgen::JsValue;
 use wasm_bindgen::JsCast;
 
+use web_sys::AbortController;
 use web_sys::File;
 use web_sys::HtmlInputElement;
 use web_sys::EventTarget;
@@ -32,6 +31,11 @@ use img_parts::{ImageEXIF};
 use crate::parse_image_metadata;
 use crate::update_image_metadata_from_binary;
 use crate::upload_image_1;
+use crate::object_store::use_object_store;
+use crate::manifest::{now_iso, record_image, upload_id};
+use crate::api::Error;
+use crate::post_guest_upload;
+use crate::guest_upload::GuestUploadGrant;
 use crate::get_device_type;
 use crate::encode_binary;
 
@@ -165,9 +169,16 @@ impl SizeAttributes {
 #[derive(Clone)]
 pub enum UploadStatus {
     Idle,
-    InProgress,
+    InProgress { sent: usize, total: usize },
     Success,
     Failure,
+    Cancelled,
+}
+
+impl UploadStatus {
+    pub fn in_progress(&self) -> bool {
+        matches!(self, UploadStatus::InProgress { .. })
+    }
 }
 
 #[component]
@@ -402,9 +413,16 @@ pub fn ImageEditor(
                         {move ||
                             match upload_status.get() {
                                 UploadStatus::Idle => "No uploads.".to_string(),
-                                UploadStatus::InProgress =>  format!("uploading: {}", filename.get()),
+                                UploadStatus::InProgress { sent, total } => {
+                                    let percent = match total {
+                                        0 => 0,
+                                        t => sent * 100 / t,
+                                    };
+                                    format!("uploading {}%: {}", percent, filename.get())
+                                },
                                 UploadStatus::Success => format!("uploaded: {}", filename.get()),
                                 UploadStatus::Failure => format!("upload failed: {}", filename.get()),
+                                UploadStatus::Cancelled => format!("upload cancelled: {}", filename.get()),
                             }
                         }
                     </div>
@@ -490,7 +508,13 @@ pub fn ImageEditor(
 pub fn FileEditAndPublishControl (
     update_list: WriteSignal<bool>,
     set_show_modal: WriteSignal<bool>,
+    // when set, uploads go to the site's inbox using the guest's signed policy
+    #[prop(optional)]
+    guest_grant: Option<GuestUploadGrant>,
 ) -> impl IntoView {
+    let is_guest = guest_grant.is_some();
+    let (read_guest_grant, _) = create_signal(guest_grant);
+    let store = use_object_store();
 
     // signal for image data derived from the user selected File object.
     let image_metadata_option: Option<ImageMetadata> = None;
@@ -543,24 +567,47 @@ pub fn FileEditAndPublishControl (
     let (upload_status, set_upload_status) = create_signal(UploadStatus::Idle);
     // upload event
     let (start_upload, set_start_upload) = create_signal(None::<String>);
+    // aborts the upload in progress
+    let upload_controller = create_rw_signal(None::<AbortController>);
 
     let upload_image = create_action(move |_: &String| async move {
-        set_upload_status.set(UploadStatus::InProgress);
-        TimeoutFuture::new(1).await;
         let fb = read_file_binary.get_untracked();
-        let u = Uint8Array::new_with_length(fb.len() as u32);
-        u.copy_from(&fb);
+        set_upload_status.set(UploadStatus::InProgress { sent: 0, total: fb.len() });
+        TimeoutFuture::new(1).await;
 
         let image_name = match start_upload.get_untracked() {
             Some(n) => n,
             None => default_upload_filename.get_untracked()
         };
 
-        match upload_image_1(u.into(), format!("images/{}", image_name)).await {
+        let upload_result = match read_guest_grant.get_untracked() {
+            Some(grant) => post_guest_upload(&grant, image_name, fb).await,
+            None => {
+                let controller = AbortController::new().ok();
+                let signal = controller.as_ref().map(|c| c.signal());
+                upload_controller.set(controller);
+                let on_progress = move |sent, total| set_upload_status.set(UploadStatus::InProgress { sent, total });
+                let key = format!("images/{}", image_name);
+                let id = upload_id(store.get_value().as_ref(), &key).await;
+                let uploaded = upload_image_1(store.get_value().as_ref(), fb.clone(), key.clone(), &id, &on_progress, signal).await.map(|_| ());
+                if uploaded.is_ok() {
+                    if let Err(err) = record_image(store.get_value().as_ref(), &key, Some(fb), now_iso()).await {
+                        log::info!("the gallery could not be updated: {}", err);
+                    }
+                }
+                uploaded
+            },
+        };
+        upload_controller.set(None);
+
+        match upload_result {
             Ok(_) => {
                 set_upload_status.set(UploadStatus::Success);
                 update_list.set(true);
             },
+            Err(Error::Cancelled) => {
+                set_upload_status.set(UploadStatus::Cancelled);
+            },
             Err(_) => {
                 log::info!("Upload failed");
                 set_upload_status.set(UploadStatus::Failure);
@@ -606,9 +653,25 @@ pub fn FileEditAndPublishControl (
                 <div style="padding: 10px 0 0 0; display: flex; flex-direction: row; font-size: .8em;">
                     <UploadFileControl
                         disabled={disabled}
+                        allow_without_credentials={is_guest}
                         start_upload={set_start_upload}
                         default_upload_filename={default_upload_filename}
                     />
+                    {move || match upload_status.get().in_progress() && upload_controller.get().is_some() {
+                        true => view!{
+                            <Button
+                                on_click=move |_| {
+                                    if let Some(controller) = upload_controller.get_untracked() {
+                                        controller.abort();
+                                    }
+                                }
+                                color=ButtonColor::Secondary
+                            >
+                                "Cancel Upload"
+                            </Button>
+                        }.into_view(),
+                        false => "".into_view(),
+                    }}
                     <Button
                     on_click=move |_| {
                         set_show_modal.set(false);
@@ -7,8 +7,11 @@ pub fn ImageLink(
     contents: S3ObjectInfo,
     set_current_image: WriteSignal<Option<String>>,
     current: RwSignal<bool>,
+    selected: RwSignal<Vec<String>>,
 ) -> impl IntoView
 {
+    let key = contents.key();
+    let checked_key = key.clone();
     let mut mut_key_to = String::from(&contents.key());
     let name_only = mut_key_to.split_off(7);
     let mut date_display = String::from(&contents.last_modified());
@@ -16,6 +19,21 @@ pub fn ImageLink(
     let class_name = "link";
 
     view! {
+        <div style="display: flex; flex-direction: row; align-items: center;">
+        <input
+            type="checkbox"
+            title="Select"
+            prop:checked=move || selected.get().contains(&checked_key)
+            on:change=move |ev| {
+                let checked = event_target_checked(&ev);
+                selected.update(|keys| {
+                    keys.retain(|k| k != &key);
+                    if checked {
+                        keys.push(key.clone());
+                    }
+                });
+            }
+        />
         <div 
             class={class_name}
             on:click={move |_evt| {
@@ -36,5 +54,6 @@ pub fn ImageLink(
                     <div class="imageDate">{format!("{}", String::from(&date_display))}</div>
                 </div>
             </div>
+        </div>
     }
 }
\ No newline at end of file
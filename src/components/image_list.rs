@@ -17,6 +17,7 @@ pub fn List(
     list: ReadSignal<Vec<S3ObjectInfo>>,
     current_image: WriteSignal<Option<String>>,
     read_current_image: ReadSignal<String>,
+    selected: RwSignal<Vec<String>>,
 ) -> impl IntoView {
     let list_size = list.get_untracked().len().try_into().expect("image list expected");
     let mut default_updates: Vec<RwSignal<bool>> = Vec::with_capacity(list_size);
@@ -68,6 +69,7 @@ pub fn List(
                                         contents={contents.clone()}
                                         set_current_image={set_local_current_image}
                                         current={*updates_signal.get().get(index_for_key(list.get_untracked(), contents.key())).unwrap_or(&default_current)}
+                                        selected={selected}
                                     />
                                 </div>
 
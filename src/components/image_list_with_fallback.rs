@@ -4,6 +4,7 @@ use crate::S3ObjectInfo;
 use crate::DeviceType;
 use crate::api::Error;
 use crate::List;
+use crate::BatchDeleteControl;
 use crate::get_device_type;
 
 #[component]
@@ -11,9 +12,16 @@ pub fn ImageListWithFallback(
   list_image_resource: ReadSignal<Option<Result<Vec<S3ObjectInfo>, Error>>>,
   current_image: WriteSignal<Option<String>>,
   read_current_image: ReadSignal<String>,
+  selected: RwSignal<Vec<String>>,
+  refresh_image_list: WriteSignal<bool>,
 ) -> impl IntoView {
     view!{
     <div style="width: 25%;">
+    <BatchDeleteControl
+        list_image_resource={list_image_resource}
+        selected={selected}
+        refresh_image_list={refresh_image_list}
+    />
     {move || match list_image_resource.get() {
         Some(list_result) => {
             match list_result {
@@ -25,6 +33,7 @@ pub fn ImageListWithFallback(
                             list={list_signal}
                             current_image={current_image} 
                             read_current_image={read_current_image}
+                            selected={selected}
                         />
                     }.into_view(),
                     DeviceType::Mobile => view!{
@@ -35,7 +44,7 @@ pub fn ImageListWithFallback(
                         />
                     }.into_view(),
                 }},
-                Err(_) => "Error retrieving list".into_view(),
+                Err(err) => view!{<div>{format!("Error retrieving list. {}", err)}</div>}.into_view(),
             }
         },
         None => view!{<div title="Waiting" />}.into_view()
@@ -17,6 +17,15 @@ pub mod purchase_control;
 pub mod home;
 pub mod loading_indicator;
 pub mod image_list;
+pub mod guest_upload_control;
+pub mod batch_delete_control;
+pub mod push_local_site_control;
+pub mod caption_editor;
+pub mod album_control;
+pub mod version_history_control;
+pub mod trash_control;
+pub mod fix_headers_control;
+pub mod check_images_control;
 
 pub use self::{
     image_edit_and_upload::*, 
@@ -38,4 +47,13 @@ pub use self::{
     home::*,
     loading_indicator::*,
     image_list::*,
+    guest_upload_control::*,
+    batch_delete_control::*,
+    push_local_site_control::*,
+    caption_editor::*,
+    album_control::*,
+    version_history_control::*,
+    trash_control::*,
+    fix_headers_control::*,
+    check_images_control::*,
 };
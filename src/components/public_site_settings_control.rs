@@ -3,47 +3,91 @@ use leptonic::prelude::*;
 use leptos_icons::RiIcon::RiFileSettingsDocumentLine;
 use crate::SiteSettings;
 use crate::set_public_site_settings;
-use crate::get_public_site_settings;
+use crate::read_public_site_settings;
+use crate::api::Error;
+use crate::conflict::Versioned;
+use crate::object_store::{use_object_store, WriteCondition};
 
+/// Edits the site's public settings, which are saved only if they have not changed since they
+/// were read, see `EditCaptionControl`.
 #[component]
 pub fn EditPublicSiteSettingsControl(
     settings_updated: WriteSignal<SiteSettings>,
     update_error: RwSignal<Option<String>>,
 ) -> impl IntoView {
 
+    let store = use_object_store();
     let (show_modal, set_show_modal) = create_signal(false);
 
     let (page_title_input, set_page_title_input) = create_signal("".to_string());
     let (app_title_input, set_app_title_input) = create_signal("".to_string());
 
-    let (original_page_title, set_original_page_title) = create_signal("".to_string());
-    let (original_app_title, set_original_app_title) = create_signal("".to_string());
+    // the settings as they were read, which the edits are saved over
+    let base = create_rw_signal(None::<Versioned<SiteSettings>>);
+    // the settings as stored by another edit since `base` was read
+    let conflict = create_rw_signal(None::<Versioned<SiteSettings>>);
 
-    let fetch_public_site_settings = create_action(move |_: &String| async move {
-        let s = get_public_site_settings().await;
-        set_page_title_input.set(s.page_title.clone());
-        set_app_title_input.set(s.app_title.clone());
-        set_original_page_title.set(s.page_title.clone());
-        set_original_app_title.set(s.app_title.clone());
-    });
-
-    let update_public_site_settings = create_action(move |_| async move {
-        update_error.set(None);
-        let mut settings: SiteSettings = SiteSettings::new();
+    let input_settings = move || {
+        let mut settings = SiteSettings::new();
         settings.page_title = page_title_input.get_untracked();
         settings.app_title = app_title_input.get_untracked();
-        match set_public_site_settings(settings.clone()).await {
-            Ok(_settings) => {
-                settings_updated.set(settings);
+        settings
+    };
+    let set_inputs = move |settings: &SiteSettings| {
+        set_page_title_input.set(settings.page_title.clone());
+        set_app_title_input.set(settings.app_title.clone());
+    };
+
+    let fetch_public_site_settings = create_action(move |_: &String| async move {
+        match read_public_site_settings(store.get_value().as_ref()).await {
+            Ok(read) => {
+                set_inputs(&read.value);
+                base.set(Some(read));
             },
-            Err(_) => {
-                update_error.set(Some("error updating public settings".to_string()));
+            Err(err) => {
+                update_error.set(Some(format!("Unable to read the public settings. {}", err)));
+                set_show_modal.set(false);
             },
-        };
+        }
     });
 
+    let update_public_site_settings = create_action(move |condition: &WriteCondition| {
+        let condition = condition.clone();
+        async move {
+            update_error.set(None);
+            let settings = input_settings();
+            match set_public_site_settings(store.get_value().as_ref(), &settings, &condition).await {
+                Ok(e_tag) => {
+                    base.set(Some(Versioned { value: settings.clone(), e_tag: Some(e_tag) }));
+                    conflict.set(None);
+                    set_show_modal.set(false);
+                    settings_updated.set(settings);
+                },
+                Err(Error::PreconditionFailed) => match read_public_site_settings(store.get_value().as_ref()).await {
+                    Ok(remote) => conflict.set(Some(remote)),
+                    Err(err) => update_error.set(Some(format!("The public settings were changed elsewhere and could not be read. {}", err))),
+                },
+                Err(_) => {
+                    update_error.set(Some("error updating public settings".to_string()));
+                    set_show_modal.set(false);
+                },
+            };
+        }
+    });
+
+    // resolves a conflict with `settings`, continuing the edit from the stored settings
+    let resolve = move |settings: SiteSettings| {
+        if let Some(remote) = conflict.get_untracked() {
+            set_inputs(&settings);
+            base.set(Some(remote));
+            conflict.set(None);
+        }
+    };
+
     create_effect(move |_| {
         if show_modal.get() {
+            base.set(None);
+            conflict.set(None);
             fetch_public_site_settings.dispatch("".to_string());
         }
     });
@@ -68,35 +112,93 @@ pub fn EditPublicSiteSettingsControl(
             <Modal show_when = {show_modal}>
                 <ModalHeader><ModalTitle>"Update Public Site Settings"</ModalTitle></ModalHeader>
                 <ModalBody>
-                    <div>
-                        <div>"Page Title"</div>
-                        <TextInput
-                            get = {page_title_input}
-                            set = {set_page_title_input}
-                        />
-                    <div>"App Title"</div>
-                        <TextInput
-                            get = {app_title_input}
-                            set = {set_app_title_input}
-                        />
-                    </div>
+                    {move || match conflict.get() {
+                        Some(remote) => view!{
+                            <div>
+                                <div>"The public settings were changed elsewhere since they were opened."</div>
+                                <div style="display: flex; flex-direction: row;">
+                                    <div style="flex: 1; padding: 0 10px 0 10px;">
+                                        <div style="font-weight: bold;">"Your Settings"</div>
+                                        <div>"Page Title: "{page_title_input.get_untracked()}</div>
+                                        <div>"App Title: "{app_title_input.get_untracked()}</div>
+                                    </div>
+                                    <div style="flex: 1; padding: 0 10px 0 10px;">
+                                        <div style="font-weight: bold;">"Stored Settings"</div>
+                                        <div>"Page Title: "{remote.value.page_title.clone()}</div>
+                                        <div>"App Title: "{remote.value.app_title.clone()}</div>
+                                    </div>
+                                </div>
+                            </div>
+                        }.into_view(),
+                        None => view!{
+                            <div>
+                                <div>"Page Title"</div>
+                                <TextInput
+                                    get = {page_title_input}
+                                    set = {set_page_title_input}
+                                />
+                            <div>"App Title"</div>
+                                <TextInput
+                                    get = {app_title_input}
+                                    set = {set_app_title_input}
+                                />
+                            </div>
+                        }.into_view(),
+                    }}
                 </ModalBody>
                 <ModalFooter>
                     <ButtonWrapper>
-                        <Button
-                            disabled = Signal::derive( move || {
-                                original_page_title.get() == page_title_input.get() &&
-                                original_app_title.get() == app_title_input.get()
-                            })
-                            on_click=move |_| {
-                                log::info!("Save settings");
-                                update_public_site_settings.dispatch("");
-                                set_show_modal.set(false);
-                            }
-                            color=ButtonColor::Primary
-                        >
-                            "Save Settings"
-                        </Button>
+                        {move || match conflict.get() {
+                            Some(remote) => view!{
+                                <Button
+                                    on_click=move |_| update_public_site_settings.dispatch(remote.condition())
+                                    color=ButtonColor::Danger
+                                >
+                                    "Overwrite"
+                                </Button>
+                                <Button
+                                    on_click=move |_| {
+                                        if let (Some(base), Some(remote)) = (base.get_untracked(), conflict.get_untracked()) {
+                                            resolve(input_settings().merge(&base.value, &remote.value));
+                                        }
+                                    }
+                                    color=ButtonColor::Primary
+                                >
+                                    "Merge"
+                                </Button>
+                                <Button
+                                    on_click=move |_| {
+                                        if let Some(remote) = conflict.get_untracked() {
+                                            settings_updated.set(remote.value.clone());
+                                            resolve(remote.value);
+                                        }
+                                        set_show_modal.set(false);
+                                    }
+                                    color=ButtonColor::Secondary
+                                >
+                                    "Keep Stored"
+                                </Button>
+                            }.into_view(),
+                            None => view!{
+                                <Button
+                                    disabled = Signal::derive( move || {
+                                        base.with(|base| base.as_ref().is_none_or(|base| {
+                                            base.value.page_title == page_title_input.get() &&
+                                            base.value.app_title == app_title_input.get()
+                                        })) || update_public_site_settings.pending().get()
+                                    })
+                                    on_click=move |_| {
+                                        log::info!("Save settings");
+                                        if let Some(base) = base.get_untracked() {
+                                            update_public_site_settings.dispatch(base.condition());
+                                        }
+                                    }
+                                    color=ButtonColor::Primary
+                                >
+                                    "Save Settings"
+                                </Button>
+                            }.into_view(),
+                        }}
                         <Button
                             on_click=move |_| {
                                 log::info!("cancel");
@@ -0,0 +1,75 @@
+use leptos::*;
+use leptonic::prelude::*;
+use uuid::Uuid;
+
+use crate::local_config::*;
+use crate::object_store::{copy_site, site_store, use_object_store};
+
+/// Copies the current local site, images, captions and settings, into one of the bucket sites.
+#[component]
+pub fn PushLocalSiteControl() -> impl IntoView {
+    let toasts = expect_context::<Toasts>();
+    let store = use_object_store();
+
+    let targets = get_sites()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|site| !site.is_local() && site.can_write())
+        .collect::<Vec<SiteConfig>>();
+    let (target_id, set_target_id) = create_signal(targets.first().map(|site| site.id.clone()));
+    let (read_targets, _) = create_signal(targets);
+    let (progress, set_progress) = create_signal(None::<(usize, usize)>);
+
+    let push_action = create_action(move |_: &String| async move {
+        let target = match read_targets.get_untracked().into_iter().find(|site| Some(&site.id) == target_id.get_untracked().as_ref()) {
+            Some(site) => site,
+            None => return,
+        };
+        set_progress.set(Some((0, 0)));
+        let bucket = target.s3_bucket_name();
+        let target_store = site_store(Some(target));
+        let on_progress = move |copied, total| set_progress.set(Some((copied, total)));
+        let (variant, header, body) = match copy_site(store.get_value().as_ref(), target_store.as_ref(), &on_progress).await {
+            Ok(copied) => (ToastVariant::Success, "Site pushed", format!("Copied {} objects to {}.", copied, bucket)),
+            Err(err) => (ToastVariant::Error, "Push failed", format!("Unable to push to {}. {}", bucket, err)),
+        };
+        set_progress.set(None);
+        toasts.push(Toast {
+            id: Uuid::new_v4(),
+            created_at: time::OffsetDateTime::now_utc(),
+            variant,
+            header: header.into_view(),
+            body: body.into_view(),
+            timeout: ToastTimeout::DefaultDelay,
+        });
+    });
+
+    view!{
+        <div style="padding: 10px 0 10px 0;">
+            <div style="font-weight: bold;">"Push this local site to a bucket"</div>
+            {move || match read_targets.get().is_empty() {
+                true => view!{
+                    <div>"Add a site config token with credentials for a bucket, then push to it from here."</div>
+                }.into_view(),
+                false => view!{
+                    <div style="display: flex; flex-direction: row; align-items: center;">
+                        <select style="font-size: .8em;"
+                            on:change=move |evt| set_target_id.set(Some(event_target_value(&evt)))
+                        >
+                            {read_targets.get().into_iter().map(|site| view!{
+                                <option value={site.id.clone()}>{site.s3_bucket_name()}</option>
+                            }).collect_view()}
+                        </select>
+                        <Button
+                            disabled = Signal::derive(move || progress.get().is_some())
+                            on_click = move |_| push_action.dispatch("push".to_string())
+                        >
+                            "Push"
+                        </Button>
+                        <div>{move || progress.get().map(|(copied, total)| format!("{} of {}", copied, total))}</div>
+                    </div>
+                }.into_view(),
+            }}
+        </div>
+    }
+}
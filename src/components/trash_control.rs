@@ -0,0 +1,146 @@
+use leptos::*;
+use leptonic::prelude::*;
+use leptos_icons::AiIcon::AiRestOutlined;
+
+use crate::api::Error;
+use crate::get_current_config;
+use crate::manifest::now_iso;
+use crate::object_store::{use_display_url, use_object_store};
+use crate::trash::{list_trash, purge, purge_older_than, restore_trashed, TrashedImage};
+
+// the age, in days, of the images "Empty" purges unless another is entered
+const DEFAULT_PURGE_DAYS: &str = "30";
+
+/// Lists the deleted images kept in the trash, each of which can be restored to the gallery or
+/// purged. Images deleted more than a number of days ago are purged together.
+#[component]
+pub fn TrashControl(
+    refresh_image_list: WriteSignal<bool>,
+    update_error: RwSignal<Option<String>>,
+) -> impl IntoView {
+    let (show_modal, set_show_modal) = create_signal(false);
+    let (trash, set_trash) = create_signal(None::<Result<Vec<TrashedImage>, Error>>);
+    let (days, set_days) = create_signal(DEFAULT_PURGE_DAYS.to_string());
+    let store = use_object_store();
+    let can_write = get_current_config().map(|config| config.can_write()).unwrap_or(false);
+
+    let load_trash = create_action(move |_: &String| async move {
+        set_trash.set(Some(list_trash(store.get_value().as_ref()).await));
+    });
+
+    let restore = create_action(move |image: &TrashedImage| {
+        let image = image.clone();
+        async move {
+            match restore_trashed(store.get_value().as_ref(), &image, now_iso()).await {
+                Ok(_) => refresh_image_list.set(true),
+                Err(err) => update_error.set(Some(format!("Unable to restore '{}'. {}", image.name(), err))),
+            }
+            load_trash.dispatch("restored".to_string());
+        }
+    });
+
+    let purge_image = create_action(move |image: &TrashedImage| {
+        let image = image.clone();
+        async move {
+            if let Err(err) = purge(store.get_value().as_ref(), &image).await {
+                update_error.set(Some(format!("Unable to purge '{}'. {}", image.name(), err)));
+            }
+            load_trash.dispatch("purged".to_string());
+        }
+    });
+
+    let empty = create_action(move |days: &i64| {
+        let days = *days;
+        async move {
+            if let Err(err) = purge_older_than(store.get_value().as_ref(), days, time::OffsetDateTime::now_utc()).await {
+                update_error.set(Some(format!("Unable to empty the trash. {}", err)));
+            }
+            load_trash.dispatch("emptied".to_string());
+        }
+    });
+
+    match can_write {
+        true => view!{
+            <div>
+                <div title="Trash">
+                    <Button
+                        variant=ButtonVariant::Flat
+                        on_click=move |_ev| {
+                            load_trash.dispatch("trash".to_string());
+                            set_show_modal.set(true);
+                        }
+                    >
+                        <div>
+                            <Icon icon=leptos_icons::Icon::from(AiRestOutlined) />
+                            <div class="iconButtonText">"Trash"</div>
+                        </div>
+                    </Button>
+                </div>
+                <Modal show_when={show_modal}>
+                    <ModalHeader><ModalTitle>"Trash"</ModalTitle></ModalHeader>
+                    <ModalBody>
+                        <div style="width: 400px">
+                            <div style="display: flex; flex-direction: row; align-items: center; padding: 0 0 10px 0;">
+                                <div style="padding: 0 10px 0 0;">"Empty images deleted more than"</div>
+                                <div style="width: 60px;"><TextInput get={days} set={set_days} /></div>
+                                <div style="padding: 0 10px 0 10px;">"days ago"</div>
+                                <Button
+                                    disabled=Signal::derive(move || days.get().trim().parse::<i64>().is_err())
+                                    on_click=move |_| {
+                                        if let Ok(days) = days.get_untracked().trim().parse::<i64>() {
+                                            empty.dispatch(days);
+                                        }
+                                    }
+                                    color=ButtonColor::Danger
+                                >
+                                    "Empty"
+                                </Button>
+                            </div>
+                            {move || match trash.get() {
+                                Some(Ok(items)) if items.is_empty() => view!{<div>"The trash is empty."</div>}.into_view(),
+                                Some(Ok(items)) => items.into_iter().map(|image| {
+                                    let src = use_display_url(store, image.trash_key());
+                                    let (restore_image, purged_image) = (image.clone(), image.clone());
+                                    view!{
+                                        <div style="display: flex; flex-direction: row; align-items: center; padding: 5px 0 5px 0;">
+                                            <img style="max-width: 100px; max-height: 100px;" src={src} />
+                                            <div style="padding: 0 10px 0 10px; flex-grow: 1;">
+                                                <div>{image.name()}</div>
+                                                <div class="imageDate">{format!("Deleted {}", image.deleted_label())}</div>
+                                            </div>
+                                            <Button
+                                                on_click=move |_| restore.dispatch(restore_image.clone())
+                                                color=ButtonColor::Primary
+                                            >
+                                                "Restore"
+                                            </Button>
+                                            <Button
+                                                on_click=move |_| purge_image.dispatch(purged_image.clone())
+                                                color=ButtonColor::Danger
+                                            >
+                                                "Purge"
+                                            </Button>
+                                        </div>
+                                    }
+                                }).collect_view(),
+                                Some(Err(err)) => format!("Unable to list the trash. {}", err).into_view(),
+                                None => "Loading ...".into_view(),
+                            }}
+                        </div>
+                    </ModalBody>
+                    <ModalFooter>
+                        <ButtonWrapper>
+                            <Button
+                                on_click=move |_| set_show_modal.set(false)
+                                color=ButtonColor::Secondary
+                            >
+                                "Close"
+                            </Button>
+                        </ButtonWrapper>
+                    </ModalFooter>
+                </Modal>
+            </div>
+        }.into_view(),
+        false => "".into_view(),
+    }
+}
@@ -8,15 +8,16 @@ pub fn UploadFileControl(
     start_upload: WriteSignal<Option<String>>,
     default_upload_filename: ReadSignal<String>,
     disabled: ReadSignal<bool>,
-
+    // guests upload with a signed policy instead of the site's credentials
+    #[prop(optional)]
+    allow_without_credentials: bool,
 ) -> impl IntoView {
     let (show_modal, set_show_modal) = create_signal(false);
     let (filename, set_filename) = create_signal(default_upload_filename.get_untracked());
 
     let current_config = get_current_config().expect("config expected");
 
-    let current_config_can_upload = current_config.access_key.is_some()
-        && current_config.secret_key.is_some();
+    let current_config_can_upload = allow_without_credentials || current_config.can_write();
 
     create_effect(move |_| {
         //log::info!("upload effect");
@@ -0,0 +1,247 @@
+use leptos::*;
+use leptonic::prelude::*;
+use leptos_icons::AiIcon::AiHistoryOutlined;
+
+use crate::api::Error;
+use crate::caption::Caption;
+use crate::manifest::{image_id, now_iso};
+use crate::object_store::{use_object_store, ObjectStore, ObjectVersion};
+use crate::versions::{deleted_images, history, preview, restore, version_label, VersionPreview};
+use crate::AppState;
+use crate::S3ObjectInfo;
+use crate::get_current_config;
+
+// The versions listed in a tab of the history.
+#[derive(Clone)]
+enum VersionSource {
+    Image(String),
+    // the caption of the image at the key
+    Caption(String),
+    DeletedImages,
+}
+
+impl VersionSource {
+    async fn load(&self, store: &dyn ObjectStore) -> Result<Vec<ObjectVersion>, Error> {
+        match self {
+            Self::Image(key) => history(store, key).await,
+            Self::Caption(key) => {
+                let (id, _) = image_id(store, key).await?;
+                history(store, &format!("comments/{}", id)).await
+            },
+            Self::DeletedImages => deleted_images(store).await,
+        }
+    }
+}
+
+// Restores a version and reloads the gallery with it, a restored caption is shown with its image.
+fn create_restore_action(
+    version_preview: RwSignal<Option<VersionPreview>>,
+    caption: WriteSignal<Option<Caption>>,
+    refresh_image_list: WriteSignal<bool>,
+    update_error: RwSignal<Option<String>>,
+) -> Action<ObjectVersion, ()> {
+    let store = use_object_store();
+    create_action(move |version: &ObjectVersion| {
+        let version = version.clone();
+        async move {
+            update_error.set(None);
+            match restore(store.get_value().as_ref(), &version, now_iso()).await {
+                Ok(restored_caption) => {
+                    version_preview.set(None);
+                    if restored_caption.is_some() {
+                        caption.set(restored_caption);
+                    }
+                    refresh_image_list.set(true);
+                },
+                Err(err) => update_error.set(Some(format!("Unable to restore the version of '{}'. {}", version.key, err))),
+            }
+        }
+    })
+}
+
+/// The versions from `source`, each of which can be previewed or restored.
+#[component]
+fn VersionList(
+    source: VersionSource,
+    preview_action: Action<ObjectVersion, ()>,
+    restore_action: Action<ObjectVersion, ()>,
+) -> impl IntoView {
+    let store = use_object_store();
+    let (versions, set_versions) = create_signal(None::<Result<Vec<ObjectVersion>, Error>>);
+    let show_keys = matches!(source, VersionSource::DeletedImages);
+
+    let load_versions = create_action(move |source: &VersionSource| {
+        let source = source.clone();
+        async move {
+            set_versions.set(Some(source.load(store.get_value().as_ref()).await));
+        }
+    });
+
+    load_versions.dispatch(source);
+
+    view!{
+        <div style="width: 400px">
+            {move || match versions.get() {
+                Some(Ok(items)) if items.is_empty() => view!{<div>"No versions are kept, check that the bucket has versioning enabled."</div>}.into_view(),
+                Some(Ok(items)) => items.into_iter().map(|version| {
+                    let label = match show_keys {
+                        true => format!("{} {}", version.key.replacen("images/", "", 1), version_label(&version)),
+                        false => version_label(&version),
+                    };
+                    let is_latest = version.is_latest;
+                    let (preview_version, restore_version) = (version.clone(), version);
+                    view!{
+                        <div style="display: flex; flex-direction: row; align-items: center; padding: 5px 0 5px 0;">
+                            <div style="padding: 0 10px 0 0; flex-grow: 1;">{label}</div>
+                            <Button
+                                on_click=move |_| preview_action.dispatch(preview_version.clone())
+                                color=ButtonColor::Secondary
+                            >
+                                "Preview"
+                            </Button>
+                            <Button
+                                disabled=is_latest
+                                on_click=move |_| restore_action.dispatch(restore_version.clone())
+                                color=ButtonColor::Primary
+                            >
+                                "Restore"
+                            </Button>
+                        </div>
+                    }
+                }).collect_view(),
+                Some(Err(err)) => format!("Unable to list the versions. {}", err).into_view(),
+                None => "Loading ...".into_view(),
+            }}
+        </div>
+    }
+}
+
+/// Lists the kept versions of the image shown, of its caption and of deleted images, for sites
+/// whose bucket has versioning enabled. A version is previewed in place of the image shown.
+#[component]
+pub fn VersionHistoryControl(
+    app_state_signal: RwSignal<Option<AppState>>,
+    image_list: Vec<S3ObjectInfo>,
+    version_preview: RwSignal<Option<VersionPreview>>,
+    caption: WriteSignal<Option<Caption>>,
+    refresh_image_list: WriteSignal<bool>,
+    update_error: RwSignal<Option<String>>,
+) -> impl IntoView {
+    let store = use_object_store();
+    let (show_modal, set_show_modal) = create_signal(false);
+    let key = store_value(app_state_signal.get_untracked().expect("app state expected").current_image_name(image_list));
+    let can_write = get_current_config().map(|config| config.can_write()).unwrap_or(false);
+
+    let preview_action = create_action(move |version: &ObjectVersion| {
+        let version = version.clone();
+        async move {
+            match preview(store.get_value().as_ref(), version).await {
+                Ok(shown) => {
+                    version_preview.set(Some(shown));
+                    set_show_modal.set(false);
+                },
+                Err(err) => update_error.set(Some(format!("Unable to preview the version. {}", err))),
+            }
+        }
+    });
+    let restore_action = create_restore_action(version_preview, caption, refresh_image_list, update_error);
+    let restore_and_close = create_action(move |version: &ObjectVersion| {
+        restore_action.dispatch(version.clone());
+        set_show_modal.set(false);
+        async {}
+    });
+
+    match can_write {
+        true => view!{
+            <div>
+                <div title="History">
+                    <Button
+                        variant=ButtonVariant::Flat
+                        on_click=move |_ev| set_show_modal.set(true)
+                    >
+                        <div>
+                            <Icon icon=leptos_icons::Icon::from(AiHistoryOutlined) />
+                            <div class="iconButtonText">"History"</div>
+                        </div>
+                    </Button>
+                </div>
+                <Modal show_when={show_modal}>
+                    <ModalHeader><ModalTitle>"History"</ModalTitle></ModalHeader>
+                    <ModalBody>
+                        <Tabs mount=Mount::WhenShown>
+                            <Tab name="image-versions" label="Image".into_view()>
+                                <VersionList
+                                    source={VersionSource::Image(key.get_value())}
+                                    preview_action={preview_action}
+                                    restore_action={restore_and_close}
+                                />
+                            </Tab>
+                            <Tab name="caption-versions" label="Caption".into_view()>
+                                <VersionList
+                                    source={VersionSource::Caption(key.get_value())}
+                                    preview_action={preview_action}
+                                    restore_action={restore_and_close}
+                                />
+                            </Tab>
+                            <Tab name="deleted-images" label="Deleted Images".into_view()>
+                                <VersionList
+                                    source={VersionSource::DeletedImages}
+                                    preview_action={preview_action}
+                                    restore_action={restore_and_close}
+                                />
+                            </Tab>
+                        </Tabs>
+                    </ModalBody>
+                    <ModalFooter>
+                        <ButtonWrapper>
+                            <Button
+                                on_click=move |_| set_show_modal.set(false)
+                                color=ButtonColor::Secondary
+                            >
+                                "Close"
+                            </Button>
+                        </ButtonWrapper>
+                    </ModalFooter>
+                </Modal>
+            </div>
+        }.into_view(),
+        false => "".into_view(),
+    }
+}
+
+/// Shown above a previewed version, see `VersionHistoryControl`, to restore it or end the preview.
+#[component]
+pub fn VersionPreviewBanner(
+    shown: VersionPreview,
+    version_preview: RwSignal<Option<VersionPreview>>,
+    caption: WriteSignal<Option<Caption>>,
+    refresh_image_list: WriteSignal<bool>,
+    update_error: RwSignal<Option<String>>,
+) -> impl IntoView {
+    let restore_action = create_restore_action(version_preview, caption, refresh_image_list, update_error);
+    let version = shown.version().clone();
+    let description = match &shown {
+        VersionPreview::Image(_) => format!("Previewing '{}' as stored {}", version.key.replacen("images/", "", 1), version_label(&version)),
+        VersionPreview::Caption { .. } => format!("Previewing the caption as stored {}", version_label(&version)),
+    };
+    let is_latest = version.is_latest;
+
+    view!{
+        <div class="imageHidden" style="display: flex; flex-direction: row; align-items: center; padding: 5px 0 5px 0;">
+            <div style="padding: 0 10px 0 0; flex-grow: 1;">{description}</div>
+            <Button
+                disabled=is_latest
+                on_click=move |_| restore_action.dispatch(version.clone())
+                color=ButtonColor::Primary
+            >
+                "Restore This Version"
+            </Button>
+            <Button
+                on_click=move |_| version_preview.set(None)
+                color=ButtonColor::Secondary
+            >
+                "Back to Current"
+            </Button>
+        </div>
+    }
+}
@@ -0,0 +1,65 @@
+use crate::api::Error;
+use crate::object_store::{ObjectStore, WriteCondition};
+
+/// A document as it was read, with the ETag a write over it is conditional on. Documents not
+/// stored yet have no ETag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Versioned<T> {
+    pub value: T,
+    pub e_tag: Option<String>,
+}
+
+impl<T> Versioned<T> {
+    /// The condition for replacing the document as it was read, see `ObjectStore::put_if`.
+    pub fn condition(&self) -> WriteCondition {
+        WriteCondition::for_e_tag(self.e_tag.clone())
+    }
+}
+
+/// The document at `key` read with `parse`, `missing` when there is none.
+pub async fn read_versioned<T>(store: &dyn ObjectStore, key: &str, parse: impl Fn(&[u8]) -> T, missing: T) -> Result<Versioned<T>, Error> {
+    match store.get_with_e_tag(key).await {
+        Ok((bytes, e_tag)) => Ok(Versioned { value: parse(&bytes), e_tag: Some(e_tag) }),
+        Err(Error::NoSuchKey) => Ok(Versioned { value: missing, e_tag: None }),
+        Err(err) => Err(err),
+    }
+}
+
+/// A field edited from `base` to `mine`, merged with the field as it was changed to `theirs`
+/// since. When both changed it, `mine` is kept.
+pub fn merge_field<T: Clone + PartialEq>(base: &T, mine: &T, theirs: &T) -> T {
+    match mine == base {
+        true => theirs.clone(),
+        false => mine.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conflict::*;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::block_on;
+
+    #[test]
+    fn test_merge_field() {
+        assert_eq!(merge_field(&"a", &"a", &"b"), "b");
+        assert_eq!(merge_field(&"a", &"c", &"a"), "c");
+        assert_eq!(merge_field(&"a", &"c", &"b"), "c");
+    }
+
+    #[test]
+    fn test_read_versioned() {
+        let store = MemoryStore::new();
+        block_on(async {
+            let missing = read_versioned(&store, "admin/settings.json", |bytes| bytes.len(), 0).await.unwrap();
+            assert_eq!(missing, Versioned { value: 0, e_tag: None });
+            assert_eq!(missing.condition(), WriteCondition::Absent);
+
+            let e_tag = store.put_if("admin/settings.json", vec![1, 2], &missing.condition()).await.unwrap();
+            let stored = read_versioned(&store, "admin/settings.json", |bytes| bytes.len(), 0).await.unwrap();
+            assert_eq!(stored, Versioned { value: 2, e_tag: Some(e_tag.clone()) });
+            assert_eq!(stored.condition(), WriteCondition::Matches(e_tag));
+            assert!(matches!(store.put_if("admin/settings.json", vec![3], &missing.condition()).await, Err(Error::PreconditionFailed)));
+        });
+    }
+}
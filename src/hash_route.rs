@@ -1,7 +1,122 @@
-use serde::{Deserialize, Serialize};
+/// The app's own routes in the page's hash start with '/', e.g. `#/album/trip/image/harbour.jpg`.
+/// A hash holding a site's config keeps it, with the route after a second '#'.
+const ROUTE_PREFIX: &str = "/";
+const ALBUM_SEGMENT: &str = "album/";
+const IMAGE_SEGMENT: &str = "image/";
 
-#[derive(Debug, Serialize, Deserialize)]
-struct HashState {
+/// What the page's hash route links to, the album shown and the image shown in it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HashState {
+    // an album's slug
+    pub album: Option<String>,
+    // an image's name, its key without "images/"
     pub current_image: Option<String>,
 }
 
+impl HashState {
+    /// The state of a route, e.g. `/album/trip/image/harbour.jpg`. Anything else in it is ignored.
+    pub fn parse(route: &str) -> Self {
+        let mut state = Self::default();
+        let mut rest = route.strip_prefix(ROUTE_PREFIX).unwrap_or(route);
+        if let Some(album_route) = rest.strip_prefix(ALBUM_SEGMENT) {
+            let (slug, after) = album_route.split_once('/').unwrap_or((album_route, ""));
+            state.album = Some(slug.to_string()).filter(|slug| !slug.is_empty());
+            rest = after;
+        }
+        if let Some(name) = rest.strip_prefix(IMAGE_SEGMENT) {
+            state.current_image = urlencoding::decode(name).ok().map(|name| name.into_owned()).filter(|name| !name.is_empty());
+        }
+        state
+    }
+
+    /// The route of the state, empty when it links to nothing. Image names are encoded, so the
+    /// '/' of a name in a folder stays in the name.
+    pub fn route(&self) -> String {
+        let mut route = String::new();
+        if let Some(slug) = &self.album {
+            route.push_str(&format!("{}{}{}", ROUTE_PREFIX, ALBUM_SEGMENT, slug));
+        }
+        if let Some(name) = &self.current_image {
+            route.push_str(&format!("{}{}{}", ROUTE_PREFIX, IMAGE_SEGMENT, urlencoding::encode(name)));
+        }
+        route
+    }
+}
+
+/// Splits a hash, without its leading '#', into the site config or guest grant it holds and the
+/// app's route.
+pub fn split_hash(hash: &str) -> (&str, Option<&str>) {
+    if hash.starts_with(ROUTE_PREFIX) {
+        return ("", Some(hash));
+    }
+    match hash.split_once(&format!("#{}", ROUTE_PREFIX)) {
+        Some((config, _)) => (config, Some(&hash[config.len() + 1..])),
+        None => (hash, None),
+    }
+}
+
+fn current_hash() -> String {
+    let hash = leptos::window().location().hash().unwrap_or_default();
+    hash.strip_prefix('#').unwrap_or(&hash).to_string()
+}
+
+/// The state of the page's hash route.
+pub fn hash_state() -> HashState {
+    match split_hash(&current_hash()) {
+        (_, Some(route)) => HashState::parse(route),
+        (_, None) => HashState::default(),
+    }
+}
+
+/// Links the page to `state`, keeping a site config in the hash. Each change adds to the browser's
+/// history, so back and forward move between images, except when the page had no route yet.
+pub fn push_hash_state(state: &HashState) {
+    let hash = current_hash();
+    let (config, route) = split_hash(&hash);
+    let new_route = state.route();
+    if route.unwrap_or_default() == new_route {
+        return;
+    }
+    let new_hash = match (config.is_empty(), new_route.is_empty()) {
+        (true, _) => new_route.clone(),
+        (false, true) => config.to_string(),
+        (false, false) => format!("{}#{}", config, new_route),
+    };
+    let location = leptos::window().location();
+    let _ = match route {
+        Some(_) => location.set_hash(&new_hash),
+        None => location.replace(&format!("#{}", new_hash)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash_route::*;
+
+    #[test]
+    fn test_routes() {
+        let state = HashState { album: Some("trip".to_string()), current_image: Some("2023/red door.jpg".to_string()) };
+        assert_eq!(state.route(), "/album/trip/image/2023%2Fred%20door.jpg");
+        assert_eq!(HashState::parse(&state.route()), state);
+
+        let image = HashState { album: None, current_image: Some("a.jpg".to_string()) };
+        assert_eq!(image.route(), "/image/a.jpg");
+        assert_eq!(HashState::parse("/image/a.jpg"), image);
+        assert_eq!(HashState::parse("/album/trip").album, Some("trip".to_string()));
+        assert_eq!(HashState::default().route(), "");
+        assert_eq!(HashState::parse("/unknown"), HashState::default());
+    }
+
+    #[test]
+    fn test_split_hash() {
+        assert_eq!(split_hash(""), ("", None));
+        assert_eq!(split_hash("/image/a.jpg"), ("", Some("/image/a.jpg")));
+        assert_eq!(split_hash("7b2262223a2270686f746f73227d"), ("7b2262223a2270686f746f73227d", None));
+        assert_eq!(split_hash("7b2262223a2270686f746f73227d#/image/a.jpg"), ("7b2262223a2270686f746f73227d", Some("/image/a.jpg")));
+        // a config's endpoint has '/'s, but not after a '#'
+        let config = r#"{"b":"photos","e":"http://localhost:9000"}"#;
+        assert_eq!(split_hash(config), (config, None));
+        assert_eq!(split_hash(&format!("{}#/album/trip", config)), (config, Some("/album/trip")));
+        assert_eq!(split_hash("guest=7b22"), ("guest=7b22", None));
+    }
+}
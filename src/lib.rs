@@ -1,20 +1,23 @@
 use leptos::*;
 
 use serde::{Deserialize, Serialize};
-use wasm_bindgen::JsValue;
-use js_sys::Date;
-use gloo_net::http::Request;
 
 use base64::*;
 use base64::alphabet::Alphabet;
 use base64::alphabet::ParseAlphabetError;
 
-use wasm_bindgen::prelude::wasm_bindgen;
 use crate::api::*;
 use crate::local_config::get_current_config;
 use crate::file_metadata_reader::*;
-use crate::api::list_images;
-use crate::awssigv4::*;
+use crate::manifest::{caption_name, load_gallery};
+use crate::caption::Caption;
+use crate::conflict::merge_field;
+use crate::object_store::{provide_object_store, site_store, use_object_store, ObjectStore};
+use std::rc::Rc;
+use crate::guest_upload::GuestUploadGrant;
+use crate::album::{list_albums, Album};
+use crate::hash_route::{hash_state, push_hash_state, HashState};
+use crate::trash::trash_image;
 
 mod api;
 mod hash_route;
@@ -22,6 +25,27 @@ mod components;
 mod awssigv4;
 mod file_metadata_reader;
 mod local_config;
+mod guest_upload;
+mod multipart_upload;
+mod batch_delete;
+mod s3_error;
+mod object_store;
+mod s3_store;
+mod local_store;
+mod webdav_store;
+mod manifest;
+mod caption;
+mod rich_text;
+mod album;
+mod conflict;
+mod versions;
+mod trash;
+mod object_headers;
+mod checksum;
+#[cfg(test)]
+mod memory_store;
+#[cfg(test)]
+mod webdav_stand_in;
 
 use self::{components::*};
 
@@ -32,7 +56,7 @@ pub enum DeviceType {
     Mobile,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct SiteSettings {
     #[serde(rename(deserialize = "appTitle"))]
     #[serde(rename(serialize = "appTitle"))]
@@ -49,6 +73,14 @@ impl SiteSettings {
             page_title: "".to_string(),
         }
     }
+
+    /// The settings edited from `base`, merged with the changes made to `theirs` since.
+    pub fn merge(&self, base: &SiteSettings, theirs: &SiteSettings) -> Self {
+        Self {
+            app_title: merge_field(&base.app_title, &self.app_title, &theirs.app_title),
+            page_title: merge_field(&base.page_title, &self.page_title, &theirs.page_title),
+        }
+    }
 }
 
 /**
@@ -75,66 +107,22 @@ pub fn decode_string(data: String) -> Option<String> {
 }
 
 
-#[wasm_bindgen]
-extern "C" {
-    #[wasm_bindgen(js_name = uploadToS3)]
-    pub async fn upload_to_s3(ak: String, sk: String, region: String, bucket_name: String, key: String, source: JsValue) -> JsValue;
-}
-
-#[wasm_bindgen]
-extern "C" {
-    #[wasm_bindgen(js_name = deleteObject)]
-    pub async fn extern_delete_object(ak: String, sk: String, region: String, bucket_name: String, prefix: String, filename: String) -> JsValue;
-}
-
-pub async fn get_public_site_settings() -> SiteSettings {
-    match get_current_config() {
-        Ok(config) => {
-            let url = format!("https://s3.{}.amazonaws.com/{}/admin/settings.json", config.region, config.s3_bucket_name());
-            let request = Request::get(&url).headers(generate_headers(&Date::new_0()));
-            match request.send().await {
-                Ok(response) => {
-                    if response.ok() {
-                        let text_result = response.text().await;
-                        match text_result {
-                            Ok(s) => {
-                                match serde_json::from_str::<SiteSettings>(&s) {
-                                    Ok(settings) => settings,
-                                    Err(_) => SiteSettings::new(),
-                                }
-                            },
-                            Err(_) => SiteSettings::new(),
-                        }
-                    } else {
-                        SiteSettings::new()
-                    }
-                },
-                Err(_) => SiteSettings::new(),
-            }
-        },
+pub async fn get_public_site_settings(store: &dyn ObjectStore) -> SiteSettings {
+    match store.get("admin/settings.json").await {
+        Ok(bytes) => serde_json::from_slice::<SiteSettings>(&bytes).unwrap_or_else(|_| SiteSettings::new()),
         Err(_) => SiteSettings::new(),
     }
 }
 
-pub async fn delete_object(filename: String, set_error: RwSignal<Option<String>> ) -> bool {
+/// Moves the image at `filename` to the trash, see `trash_image`.
+pub async fn delete_object(store: &dyn ObjectStore, filename: String, set_error: RwSignal<Option<String>> ) -> bool {
     set_error.set(None);
-    match get_current_config() {
-        Ok(current_config) => {
-            let s3_bucket_name = current_config.s3_bucket_name();
-            let access_key = current_config.access_key.expect("access key");
-            let secret_key = current_config.secret_key.expect("secret key");
-            let region = current_config.region;
-
-            let delete_result = extern_delete_object(access_key, secret_key, region, s3_bucket_name, "".to_string(), filename).await;
-            let converted = delete_result.as_string().expect("delete result exists");
-            if converted == "error" {
-                set_error.set(Some("Error while attempting to delete object".to_string()));
-                false
-            } else {
-                true
-            }
+    match trash_image(store, &filename, time::OffsetDateTime::now_utc()).await {
+        Ok(_) => true,
+        Err(err) => {
+            set_error.set(Some(format!("Error while attempting to delete object: {}", err)));
+            false
         },
-        Err(_) => false,
     }
 }
 
@@ -157,7 +145,7 @@ pub struct AppState {
     current_image_index: usize,
     _current_image: String,
     _e_tag: String,
-    current_caption: Option<String>,
+    current_caption: Option<Caption>,
 }
 
 impl AppState {
@@ -170,7 +158,7 @@ impl AppState {
         }
     }
 
-    pub fn current_caption(&self) -> Option<String> {
+    pub fn current_caption(&self) -> Option<Caption> {
         self.current_caption.clone()
     }
 
@@ -195,7 +183,7 @@ impl AppState {
         }
     }
 
-    pub fn set_caption(&mut self, new_caption: String) {
+    pub fn set_caption(&mut self, new_caption: Caption) {
         self.current_caption = Some(new_caption);
     }
 
@@ -230,14 +218,14 @@ impl AppState {
     }
 
     pub fn next_image(&mut self, image_list: Vec<S3ObjectInfo>) -> Self {
-        if self.current_image_index < (image_list.len() - 1) {
+        if self.current_image_index + 1 < image_list.len() {
             self.current_image_index = self.current_image_index + 1
         }
         self.clone()
     }
 
     pub fn has_next_image(&self, image_list: Vec<S3ObjectInfo>) -> bool {
-        self.current_image_index < (image_list.len() - 1)
+        self.current_image_index + 1 < image_list.len()
     }
 }
 
@@ -270,11 +258,11 @@ pub trait ImageInfo {
     fn key(&self) -> String;
     fn last_modified(&self) -> String;
     fn e_tag(&self) -> String;
-    fn get_text(&self) -> Option<String>;
-    fn set_text(&mut self, new_text: Option<String>);
+    fn get_caption(&self) -> Option<Caption>;
+    fn set_caption(&mut self, new_caption: Option<Caption>);
 }
 
-#[derive(Clone, Deserialize, Serialize, Ord, Eq, PartialOrd, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, Ord, Eq, PartialOrd, PartialEq)]
 pub struct S3ObjectInfo {
     #[serde(rename(deserialize = "Key"))]
     _key: String,
@@ -286,7 +274,36 @@ pub struct S3ObjectInfo {
     _size: i64,
     #[serde(rename(deserialize = "StorageClass"))]
     _storage_class: String,
-    _text: Option<String>,
+    // boxed, as most listed objects have no caption
+    _caption: Option<Box<Caption>>,
+    _id: Option<String>,
+}
+
+impl S3ObjectInfo {
+    pub fn new(key: String, last_modified: String, e_tag: String, size: i64) -> Self {
+        Self {
+            _key: key,
+            _last_modified: last_modified,
+            _e_tag: e_tag,
+            _size: size,
+            _storage_class: "STANDARD".to_string(),
+            _caption: None,
+            _id: None,
+        }
+    }
+
+    pub fn size(&self) -> i64 {
+        self._size
+    }
+
+    /// The image's stable id, known when it was loaded from the manifest.
+    pub fn id(&self) -> Option<String> {
+        self._id.clone()
+    }
+
+    pub fn set_id(&mut self, id: Option<String>) {
+        self._id = id
+    }
 }
 
 impl ImageInfo for S3ObjectInfo {
@@ -302,79 +319,65 @@ impl ImageInfo for S3ObjectInfo {
         self._e_tag.clone()
     }
 
-    fn get_text(&self) -> Option<String> {
-        self._text.clone()
+    fn get_caption(&self) -> Option<Caption> {
+        self._caption.as_deref().cloned()
     }
 
-    fn set_text(&mut self, new_text: Option<String>) {
-        self._text = new_text
+    fn set_caption(&mut self, new_caption: Option<Caption>) {
+        self._caption = new_caption.map(Box::new)
     }
 
 }
 
-pub async fn get_image(filename: String) -> Option<Vec<u8>> {
-    let region = get_current_config().expect("").region;
-    let bucket = get_current_config().expect("").s3_bucket_name();
-    let url = format!("https://s3.{}.amazonaws.com/{}/images/{}", region, bucket, filename);
-    let request = Request::get(&url).headers(generate_headers(&Date::new_0()));
-    match request.send().await {
-        Ok(response) => {
-            if response.ok() {
-                return match response.binary().await {
-                    Ok(s) => Some(s),
-                    _ => None,
-                }
-            } else {
-                log::info!("Request for image failed.");
-                None
-            }
-        },
-        Err(err) => {
-            log::info!("Error on get image. {}", err);
-            return None
-        }
-    } 
-}
-
-pub async fn get_comment(id: String) -> Option<String> {
-    let region = get_current_config().expect("").region;
-    let bucket = get_current_config().expect("").s3_bucket_name();
-    let url = format!("https://s3.{}.amazonaws.com/{}/comments/{}", region, bucket, id);
-    let request = Request::get(&url).headers(generate_headers(&Date::new_0()));
-    let response = request.send().await.expect("response");
-    if response.ok() {
-        let text_result = response.text().await;
-        match text_result {
-            Ok(s) => Some(s),
-            _ => Some("".to_string()),
-        }
-    } else {
-        Some("".to_string())
+/// The caption stored under `id`, an image's stable id or, before it had one, its ETag.
+/// An image without a caption has an empty one.
+pub async fn get_caption(store: &dyn ObjectStore, id: String) -> Caption {
+    match store.get(&format!("comments/{}", id)).await {
+        Ok(bytes) => Caption::parse(&bytes),
+        Err(_) => Caption::default(),
     }
 }
 
-fn get_update_caption_action(app_state: RwSignal<Option<AppState>>, image_list: Vec<S3ObjectInfo>) -> Action<(), ()> {
+fn get_update_caption_action(store: StoredValue<Rc<dyn ObjectStore>>, app_state: RwSignal<Option<AppState>>, image_list: Vec<S3ObjectInfo>) -> Action<(), ()> {
     let (read_image_list, _) = create_signal(image_list);
     create_action(move |_| async move {
-        let ut = app_state.get_untracked();
-        match get_comment(ut.expect("").e_tag(read_image_list.get_untracked())).await {
-            Some(contents) => {
-                let mut current_state= app_state.get_untracked().expect("app state expected");
-                current_state.set_caption(contents);
-                app_state.set(Some(current_state));
+        let ut = app_state.get_untracked().expect("");
+        let list = read_image_list.get_untracked();
+        // images loaded from the manifest come with their captions
+        let image = list.get(ut.current_image_index).cloned();
+        let caption = match image.as_ref().and_then(|image| image.get_caption()) {
+            Some(caption) => Some(caption),
+            None => match image {
+                Some(image) => Some(get_caption(store.get_value().as_ref(), caption_name(store.get_value().as_ref(), &image).await).await),
+                None => None,
             },
-            _ => (),
+        };
+        if let Some(contents) = caption {
+            let mut current_state= app_state.get_untracked().expect("app state expected");
+            current_state.set_caption(contents);
+            app_state.set(Some(current_state));
         }
     })
 }
 
-pub fn update_app_state(app_state_signal: RwSignal<Option<AppState>>, new_app_state: AppState, image_list: Vec<S3ObjectInfo>) {
+pub fn update_app_state(store: StoredValue<Rc<dyn ObjectStore>>, app_state_signal: RwSignal<Option<AppState>>, new_app_state: AppState, image_list: Vec<S3ObjectInfo>) {
     app_state_signal.set(Some(new_app_state));
-    get_update_caption_action(app_state_signal, image_list).dispatch(());
+    get_update_caption_action(store, app_state_signal, image_list).dispatch(());
 }
 
 #[component]
 pub fn App() -> impl IntoView {
+    provide_object_store(site_store(get_current_config().ok()));
+
+    // guest upload links only get the editor, never the site's configuration
+    if let Some(grant) = GuestUploadGrant::from_hash_route() {
+        return view!{
+            <GuestUpload grant={grant} />
+        }.into_view();
+    }
+
+    let store = use_object_store();
+
     let app_state_signal = create_rw_signal(None::<AppState>);
     let (refetch_list_signal, set_refetch_list_signal) = create_signal(false);
     let (app_title, set_app_title) = create_signal("".to_string());
@@ -382,7 +385,7 @@ pub fn App() -> impl IntoView {
     let (public_site_settings, set_public_site_settings) = create_signal(SiteSettings::new());
 
     let fetch_public_site_settings = create_action(move |_: &String| async move {
-        let s = get_public_site_settings().await;
+        let s = get_public_site_settings(store.get_value().as_ref()).await;
         match leptos::window().document() {
             Some(doc) => {
                 doc.set_title(&s.page_title);
@@ -394,26 +397,57 @@ pub fn App() -> impl IntoView {
     
     let image_list_default: Option<Result<Vec<S3ObjectInfo>, api::Error>> = None;
     
+    // every image of the site, and the images shown, those of the current album
+    let (gallery, set_gallery) = create_signal(image_list_default.clone());
     let (image_list, set_image_list) = create_signal(image_list_default);
 
+    let albums = create_rw_signal(Vec::<Album>::new());
+    let current_album = create_rw_signal(None::<Album>);
+
     let fetch_images = create_action(move |_: &String| async move {
-        let fetch_result = list_images().await;
-        set_image_list.set(Some(fetch_result.clone()));
-        match fetch_result {
-            Ok(result) => {
-                match app_state_signal.get_untracked() {
-                    Some(app_state) => {
-                        update_app_state(app_state_signal, app_state, result.clone());
-                    },
-                    None => {
-                        let app_state = AppState::empty();
-                        update_app_state(app_state_signal, app_state, result.clone());
-
-                    }
-                }
+        let can_write = get_current_config().map(|config| config.can_write()).unwrap_or(false);
+        let fetch_result = load_gallery(store.get_value().as_ref(), can_write).await;
+        let album_list = match list_albums(store.get_value().as_ref()).await {
+            Ok(list) => list,
+            Err(err) => {
+                log::info!("unable to list albums: {}", err);
+                vec![]
             },
-            Err(_) => (),
+        };
+        // the album shown stays, or on the first load is the one the hash route links to
+        let album = match current_album.get_untracked() {
+            Some(current) => album_list.iter().find(|album| album.id == current.id).cloned(),
+            None => hash_state().album.and_then(|slug| album_list.iter().find(|album| album.slug == slug).cloned()),
+        };
+        batch(move || {
+            albums.set(album_list);
+            current_album.set(album);
+            set_gallery.set(Some(fetch_result));
+        });
+    });
+
+    // album listener
+    create_effect(move |previous_album: Option<Option<String>>| {
+        let album = current_album.get();
+        let album_id = album.as_ref().map(|album| album.id.clone());
+        let shown = gallery.get().map(|result| result.map(|images| match &album {
+            Some(album) => album.images(&images),
+            None => images,
+        }));
+        set_image_list.set(shown.clone());
+        if let Some(Ok(list)) = shown {
+            // a different album, or the first load, starts at the image the hash route links to,
+            // or else at its first image
+            let app_state = match app_state_signal.get_untracked() {
+                Some(app_state) if previous_album.as_ref() == Some(&album_id) => app_state,
+                _ => match hash_state().current_image {
+                    Some(name) => AppState::empty().set_current_image(format!("images/{}", name), list.clone()),
+                    None => AppState::empty(),
+                },
+            };
+            update_app_state(store, app_state_signal, app_state, list);
         }
+        album_id
     });
 
     create_effect(move |_| match refetch_list_signal.get() {
@@ -421,13 +455,29 @@ pub fn App() -> impl IntoView {
         false => (),
     });
 
-    // app state signal listener
+    // app state signal listener, the hash route links to the image shown
     create_effect(move |_| {
-        match  app_state_signal.get() {
-            Some(_) => {
-                //update_hash_route(Some(a)),
-            },
-            None => ()
+        if let (Some(app_state), Some(Ok(list))) = (app_state_signal.get(), image_list.get_untracked()) {
+            push_hash_state(&HashState {
+                album: current_album.get_untracked().map(|album| album.slug),
+                current_image: list.get(app_state.current_image_index).map(|image| image.key().replacen("images/", "", 1)),
+            });
+        }
+    });
+
+    // back and forward, or an edited address, show the album and image the hash route links to
+    let _ = window_event_listener_untyped("hashchange", move |_| {
+        let state = hash_state();
+        let album = state.album.and_then(|slug| albums.get_untracked().into_iter().find(|album| album.slug == slug));
+        if album.as_ref().map(|album| &album.id) != current_album.get_untracked().as_ref().map(|album| &album.id) {
+            current_album.set(album);
+            return;
+        }
+        if let (Some(name), Some(Ok(list)), Some(mut app_state)) = (state.current_image, image_list.get_untracked(), app_state_signal.get_untracked()) {
+            if app_state.current_image_name(list.clone()) != format!("images/{}", name) {
+                let new_state = app_state.set_current_image(format!("images/{}", name), list.clone());
+                update_app_state(store, app_state_signal, new_state, list);
+            }
         }
     });
 
@@ -451,8 +501,10 @@ pub fn App() -> impl IntoView {
                 app_state_signal = app_state_signal.into()
                 current_image = {current_image}
                 set_current_image = {set_current_image}
+                albums = {albums}
+                current_album = {current_album}
             />
-    }
+    }.into_view()
 
 }
 
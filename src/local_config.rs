@@ -7,6 +7,12 @@ use hex::FromHexError;
 use std::num::TryFromIntError;
 use gloo_storage::errors::StorageError;
 use wasm_bindgen::JsValue;
+use secrecy::SecretString;
+use time::{Duration, OffsetDateTime};
+
+use crate::awssigv4::SigningCredentials;
+use crate::hash_route::split_hash;
+use crate::object_headers::DEFAULT_IMAGE_CACHE_CONTROL;
 
 const SITE_CONFIG: &str = "WM_SITE_CONFIG";
 const CURRENT_SITE: &str = "WM_CURRENT_SITE";
@@ -71,6 +77,44 @@ impl From<TryFromIntError> for ConfigError {
     }
 }
 
+/// How the bucket appears in request URLs. Path style, `https://<endpoint>/<bucket>/<key>`, works with
+/// AWS and most S3 compatible services. Virtual hosted, `https://<bucket>.<endpoint>/<key>`, is needed by
+/// services which do not support path style.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum AddressingStyle {
+    #[default]
+    #[serde(rename = "path")]
+    Path,
+    #[serde(rename = "virtual")]
+    VirtualHosted,
+}
+
+impl AddressingStyle {
+    fn is_path(&self) -> bool {
+        *self == AddressingStyle::Path
+    }
+}
+
+/// Where a site's images are kept. A local site lives in this browser's storage, so it can be
+/// tried without a bucket and pushed to one later. A WebDAV site is a folder on a WebDAV share,
+/// its endpoint is the folder's URL and its access and secret keys are the user name and password.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum SiteKind {
+    #[default]
+    #[serde(rename = "s3")]
+    S3,
+    #[serde(rename = "local")]
+    Local,
+    #[serde(rename = "webdav")]
+    WebDav,
+}
+
+impl SiteKind {
+    fn is_s3(&self) -> bool {
+        *self == SiteKind::S3
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SiteConfig {
     pub id: String,
@@ -87,6 +131,34 @@ pub struct SiteConfig {
     #[serde(rename(deserialize = "r"))]
     #[serde(rename(serialize = "r"))]
     pub region: String,
+    // temporary credentials issued by STS carry a session token and expire
+    #[serde(rename(deserialize = "t"))]
+    #[serde(rename(serialize = "t"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    // unix timestamp in seconds
+    #[serde(rename(deserialize = "x"))]
+    #[serde(rename(serialize = "x"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<i64>,
+    // an S3 compatible service such as MinIO or R2, e.g. "http://localhost:9000". AWS when not set.
+    #[serde(rename(deserialize = "e"))]
+    #[serde(rename(serialize = "e"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(rename(deserialize = "v"))]
+    #[serde(rename(serialize = "v"))]
+    #[serde(default, skip_serializing_if = "AddressingStyle::is_path")]
+    pub addressing_style: AddressingStyle,
+    #[serde(rename(deserialize = "k"))]
+    #[serde(rename(serialize = "k"))]
+    #[serde(default, skip_serializing_if = "SiteKind::is_s3")]
+    pub kind: SiteKind,
+    // the `Cache-Control` images are stored with, `DEFAULT_IMAGE_CACHE_CONTROL` when not set
+    #[serde(rename(deserialize = "c"))]
+    #[serde(rename(serialize = "c"))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<String>,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
     pub read_only: bool,
@@ -118,10 +190,101 @@ impl SiteConfig {
             access_key: ak,
             secret_key: sk,
             region,
+            session_token: None,
+            expiration: None,
+            endpoint: None,
+            addressing_style: AddressingStyle::Path,
+            kind: SiteKind::S3,
+            cache_control: None,
             read_only: true,
         }
     }
 
+    /// A site kept in this browser, `name` stands in for the bucket name.
+    pub fn local(name: String) -> Self {
+        Self::new(name, None, None, "".to_string()).with_kind(SiteKind::Local)
+    }
+
+    pub fn with_kind(mut self, kind: SiteKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.kind == SiteKind::Local
+    }
+
+    /// True when the site's images can be changed, a local site always can.
+    pub fn can_write(&self) -> bool {
+        self.is_local() || (self.access_key.is_some() && self.secret_key.is_some())
+    }
+
+    /// Marks the credentials as temporary, `expiration` is a unix timestamp in seconds.
+    pub fn with_session(mut self, session_token: Option<String>, expiration: Option<i64>) -> Self {
+        self.session_token = session_token;
+        self.expiration = expiration;
+        self
+    }
+
+    pub fn with_cache_control(mut self, cache_control: Option<String>) -> Self {
+        self.cache_control = cache_control;
+        self
+    }
+
+    /// The `Cache-Control` images are stored with, see `object_headers`.
+    pub fn image_cache_control(&self) -> &str {
+        self.cache_control.as_deref().unwrap_or(DEFAULT_IMAGE_CACHE_CONTROL)
+    }
+
+    pub fn with_endpoint(mut self, endpoint: Option<String>, addressing_style: AddressingStyle) -> Self {
+        self.endpoint = endpoint;
+        self.addressing_style = addressing_style;
+        self
+    }
+
+    /// The URL of the bucket, without a trailing '/'. All request URLs are built from this.
+    pub fn bucket_url(&self) -> String {
+        let endpoint = match &self.endpoint {
+            Some(e) => e.trim_end_matches('/').to_string(),
+            None => format!("https://s3.{}.amazonaws.com", self.region),
+        };
+        match self.addressing_style {
+            AddressingStyle::Path => format!("{}/{}", endpoint, self.s3_bucket_name()),
+            AddressingStyle::VirtualHosted => match endpoint.split_once("://") {
+                Some((scheme, host)) => format!("{}://{}.{}", scheme, self.s3_bucket_name(), host),
+                None => format!("https://{}.{}", self.s3_bucket_name(), endpoint),
+            },
+        }
+    }
+
+    pub fn object_url(&self, key: &str) -> String {
+        format!("{}/{}", self.bucket_url(), key)
+    }
+
+    /// Credentials for signing requests, None for a read only site or one not on S3.
+    pub fn signing_credentials(&self) -> Option<SigningCredentials> {
+        if !self.kind.is_s3() {
+            return None;
+        }
+        Some(SigningCredentials::new(
+            self.access_key.clone()?,
+            SecretString::new(self.secret_key.clone()?),
+            self.region.clone(),
+        ).with_session_token(self.session_token.clone()))
+    }
+
+    /// True when temporary credentials expire before `now + window`.
+    pub fn credentials_expire_within(&self, now: OffsetDateTime, window: Duration) -> bool {
+        match self.expiration {
+            Some(expiration) => (now + window).unix_timestamp() >= expiration,
+            None => false,
+        }
+    }
+
+    pub fn credentials_expired(&self, now: OffsetDateTime) -> bool {
+        self.credentials_expire_within(now, Duration::ZERO)
+    }
+
     pub fn s3_bucket_name(&self) -> String {
         self._s3_bucket_name.to_lowercase()
     }
@@ -135,13 +298,21 @@ impl SiteConfig {
     ) -> Result<Self, ConfigError> {
         match serde_json::from_str::<SiteConfig>(&encoded) {
             Ok(sc) => {
-                return Ok(Self::new(sc._s3_bucket_name, sc.access_key, sc.secret_key, sc.region))
+                Ok(Self::new(sc._s3_bucket_name, sc.access_key, sc.secret_key, sc.region)
+                    .with_session(sc.session_token, sc.expiration)
+                    .with_endpoint(sc.endpoint, sc.addressing_style)
+                    .with_cache_control(sc.cache_control)
+                    .with_kind(sc.kind))
             },
             Err(_err) => {
                 let decoded1 = hex::decode(encoded)?;
                 let decoded = String::from_utf8(decoded1)?;
                 let deserialized =  serde_json::from_str::<SiteConfig>(&decoded)?;
-                Ok(Self::new(deserialized.s3_bucket_name(), deserialized.access_key, deserialized.secret_key, deserialized.region))
+                Ok(Self::new(deserialized.s3_bucket_name(), deserialized.access_key, deserialized.secret_key, deserialized.region)
+                    .with_session(deserialized.session_token, deserialized.expiration)
+                    .with_endpoint(deserialized.endpoint, deserialized.addressing_style)
+                    .with_cache_control(deserialized.cache_control)
+                    .with_kind(deserialized.kind))
             },
         }
     }
@@ -177,15 +348,30 @@ pub fn add_site_config(site_config: SiteConfig) -> Result<SiteConfig, ConfigErro
     Ok(site_config.clone())
 }
 
-pub trait SingleSiteRuntimeConfig {
-    fn get_current_config() -> Result<SiteConfig, ConfigError>;
+/// Adds a site, unless it carries temporary credentials for a bucket that is already
+/// configured. The existing site then takes the fresh credentials and keeps its id.
+pub fn add_or_refresh_site_config(site_config: SiteConfig) -> Result<SiteConfig, ConfigError> {
+    if site_config.session_token.is_none() {
+        return add_site_config(site_config);
+    }
+    let mut sites = get_sites()?;
+    match sites.iter_mut().find(|site| site.s3_bucket_name() == site_config.s3_bucket_name()) {
+        Some(site) => {
+            site.access_key = site_config.access_key;
+            site.secret_key = site_config.secret_key;
+            site.session_token = site_config.session_token;
+            site.expiration = site_config.expiration;
+            site.region = site_config.region;
+            let refreshed = site.clone();
+            let _ = LocalStorage::set(SITE_CONFIG, hex::encode(serde_json::to_string(&sites)?));
+            Ok(refreshed)
+        },
+        None => add_site_config(site_config),
+    }
 }
 
-pub trait MultiSiteRuntimeConfig {
-    fn set_current_site(&self, site_name: String) -> Result<SiteConfig, ConfigError>;
-    fn remove_site(&self, id: String) -> Result<(), ConfigError>;
-    fn get_sites(&self) -> Result<Vec<SiteConfig>, ConfigError>;
-    fn add_site_config(&self, site_config: SiteConfig) -> Result<SiteConfig, ConfigError>;
+pub trait SingleSiteRuntimeConfig {
+    fn get_current_config() -> Result<SiteConfig, ConfigError>;
 }
 
 pub struct HashRouteRuntimeConfig {
@@ -194,11 +380,12 @@ pub struct HashRouteRuntimeConfig {
 
 impl SingleSiteRuntimeConfig for HashRouteRuntimeConfig {
     fn get_current_config() -> Result<SiteConfig, ConfigError> {
-        let mut raw = leptos::window().location().hash()?;
-        match raw.len() > 20 {
+        let raw = leptos::window().location().hash()?;
+        // the app's route may follow the config, see `split_hash`
+        let (hash, _) = split_hash(raw.strip_prefix('#').unwrap_or(&raw));
+        match hash.len() > 20 {
             true => {
-                let hash = raw.split_off(1);
-                let config = SiteConfig::from_encoded(hash)?;
+                let config = SiteConfig::from_encoded(hash.to_string())?;
                 Ok(config)
             },
             false => {
@@ -208,27 +395,6 @@ impl SingleSiteRuntimeConfig for HashRouteRuntimeConfig {
     }
 }
 
-pub struct LocalStorageRuntimeConfig {
-
-}
-
-impl SingleSiteRuntimeConfig for LocalStorageRuntimeConfig {
-    fn get_current_config() -> Result<SiteConfig, ConfigError> {
-        let current_site = LocalStorage::get::<String>(CURRENT_SITE)?;
-        let sites = get_sites()?;
-        if sites.len() > 0 {
-            for site in &sites {
-                if site.id == current_site {
-                    return Ok(site.clone())
-                }
-            }
-            return Ok(sites.get(0).expect("element").clone())
-        } else {
-            Err(ConfigError::SiteNotFound)          
-        }
-    }
-}
-
 pub fn set_current_site(site_name: String) -> Result<SiteConfig, ConfigError> {
     let sites = get_sites()?;
     for site in sites {
@@ -297,3 +463,109 @@ pub fn get_current_config() -> Result<SiteConfig, ConfigError> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+    use time::macros::datetime;
+    use crate::local_config::{AddressingStyle, SiteConfig, SiteKind};
+
+    #[test]
+    fn test_permanent_credentials_encoding_unchanged() {
+        let config = SiteConfig::new("example.weblum.photos".to_string(), Some("AKIA".to_string()), Some("secret".to_string()), "us-west-2".to_string());
+        let json = String::from_utf8(hex::decode(config.encoded()).unwrap()).unwrap();
+        assert!(!json.contains("\"t\""));
+        assert!(!json.contains("\"x\""));
+        let decoded = SiteConfig::from_encoded(config.encoded()).unwrap();
+        assert_eq!(decoded.session_token, None);
+        assert!(decoded.signing_credentials().unwrap().session_token.is_none());
+    }
+
+    #[test]
+    fn test_session_credentials_round_trip() {
+        let config = SiteConfig::new("example.weblum.photos".to_string(), Some("ASIA".to_string()), Some("secret".to_string()), "us-west-2".to_string())
+            .with_session(Some("token".to_string()), Some(1697126400));
+        let decoded = SiteConfig::from_encoded(config.encoded()).unwrap();
+        assert_eq!(decoded.session_token, Some("token".to_string()));
+        assert_eq!(decoded.expiration, Some(1697126400));
+        assert_eq!(decoded.signing_credentials().unwrap().session_token, Some("token".to_string()));
+    }
+
+    #[test]
+    fn test_credentials_expiry() {
+        // expires 2023-10-12 16:00:00 UTC
+        let config = SiteConfig::new("example.weblum.photos".to_string(), Some("ASIA".to_string()), Some("secret".to_string()), "us-west-2".to_string())
+            .with_session(Some("token".to_string()), Some(1697126400));
+        let now = datetime!(2023-10-12 15:30:00 UTC);
+        assert!(!config.credentials_expired(now));
+        assert!(config.credentials_expire_within(now, Duration::hours(1)));
+        assert!(!config.credentials_expire_within(now, Duration::minutes(10)));
+        assert!(config.credentials_expired(datetime!(2023-10-12 16:00:00 UTC)));
+
+        let permanent = SiteConfig::new("example.weblum.photos".to_string(), Some("AKIA".to_string()), Some("secret".to_string()), "us-west-2".to_string());
+        assert!(!permanent.credentials_expired(now));
+    }
+
+    #[test]
+    fn test_aws_object_url() {
+        let config = SiteConfig::new("Example.weblum.photos".to_string(), None, None, "us-west-2".to_string());
+        assert_eq!(config.object_url("images/a.jpg"), "https://s3.us-west-2.amazonaws.com/example.weblum.photos/images/a.jpg");
+    }
+
+    #[test]
+    fn test_custom_endpoint_urls() {
+        let minio = SiteConfig::new("photos".to_string(), None, None, "us-east-1".to_string())
+            .with_endpoint(Some("http://localhost:9000/".to_string()), AddressingStyle::Path);
+        assert_eq!(minio.object_url("images/a.jpg"), "http://localhost:9000/photos/images/a.jpg");
+
+        let r2 = SiteConfig::new("photos".to_string(), None, None, "auto".to_string())
+            .with_endpoint(Some("https://0123456789abcdef.r2.cloudflarestorage.com".to_string()), AddressingStyle::VirtualHosted);
+        assert_eq!(r2.bucket_url(), "https://photos.0123456789abcdef.r2.cloudflarestorage.com");
+        assert_eq!(r2.object_url("images/a.jpg"), "https://photos.0123456789abcdef.r2.cloudflarestorage.com/images/a.jpg");
+    }
+
+    #[test]
+    fn test_endpoint_round_trip() {
+        let config = SiteConfig::new("photos".to_string(), None, None, "auto".to_string())
+            .with_endpoint(Some("https://s3.wasabisys.com".to_string()), AddressingStyle::VirtualHosted);
+        let json = String::from_utf8(hex::decode(config.encoded()).unwrap()).unwrap();
+        assert!(json.contains("\"v\":\"virtual\""));
+        let decoded = SiteConfig::from_encoded(config.encoded()).unwrap();
+        assert_eq!(decoded.endpoint, Some("https://s3.wasabisys.com".to_string()));
+        assert_eq!(decoded.addressing_style, AddressingStyle::VirtualHosted);
+    }
+
+    #[test]
+    fn test_image_cache_control() {
+        let config = SiteConfig::new("photos".to_string(), None, None, "us-east-1".to_string());
+        assert_eq!(config.image_cache_control(), "public, max-age=86400");
+        assert!(!String::from_utf8(hex::decode(config.encoded()).unwrap()).unwrap().contains("\"c\""));
+
+        let encoded = r#"{"id":"1","b":"photos","r":"us-east-1","c":"public, max-age=604800, immutable"}"#;
+        let decoded = SiteConfig::from_encoded(encoded.to_string()).unwrap();
+        assert_eq!(decoded.image_cache_control(), "public, max-age=604800, immutable");
+    }
+
+    #[test]
+    fn test_local_site() {
+        let local = SiteConfig::local("my photos".to_string());
+        assert!(local.can_write());
+        let decoded = SiteConfig::from_encoded(local.encoded()).unwrap();
+        assert_eq!(decoded.kind, SiteKind::Local);
+
+        let read_only = SiteConfig::new("example.weblum.photos".to_string(), None, None, "us-west-2".to_string());
+        assert!(!read_only.can_write());
+        let json = String::from_utf8(hex::decode(read_only.encoded()).unwrap()).unwrap();
+        assert!(!json.contains("\"k\""));
+    }
+
+    #[test]
+    fn test_webdav_site() {
+        let encoded = r#"{"id":"1","b":"photos","r":"","k":"webdav","e":"https://cloud.example.com/remote.php/dav/files/alice/weblum","a":"alice","s":"password"}"#;
+        let config = SiteConfig::from_encoded(encoded.to_string()).unwrap();
+        assert_eq!(config.kind, SiteKind::WebDav);
+        assert!(config.can_write());
+        // basic auth credentials are never used to sign S3 requests
+        assert!(config.signing_credentials().is_none());
+    }
+}
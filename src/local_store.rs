@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use js_sys::{Date, Promise, Uint8Array};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    Blob, DomException, FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetDirectoryOptions,
+    FileSystemGetFileOptions, FileSystemWritableFileStream,
+};
+
+use crate::api::Error;
+use crate::object_store::{content_e_tag, ObjectHead, ObjectPage, ObjectStore, StoreFuture, WriteCondition};
+use crate::{ImageInfo, S3ObjectInfo};
+
+// Keys returned in a page of a listing, as S3's default.
+const PAGE_SIZE: usize = 1000;
+// Lists the site's objects, so listing does not read every file.
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+struct LocalObject {
+    e_tag: String,
+    last_modified: String,
+    size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+}
+
+type Index = BTreeMap<String, LocalObject>;
+
+/// An `ObjectStore` in the browser's Origin Private File System, for sites tried without a bucket.
+/// Each site has a directory holding a file per object and an index of the objects.
+pub struct LocalStore {
+    site_id: String,
+}
+
+impl LocalStore {
+    pub fn new(site_id: String) -> Self {
+        Self { site_id }
+    }
+
+    async fn directory(&self) -> Result<FileSystemDirectoryHandle, Error> {
+        let root: FileSystemDirectoryHandle = resolve(leptos::window().navigator().storage().get_directory()).await?.unchecked_into();
+        let options = FileSystemGetDirectoryOptions::new();
+        options.set_create(true);
+        Ok(resolve(root.get_directory_handle_with_options(&format!("site-{}", self.site_id), &options)).await?.unchecked_into())
+    }
+
+    async fn index(&self, directory: &FileSystemDirectoryHandle) -> Result<Index, Error> {
+        match read_file(directory, INDEX_FILE).await? {
+            Some(bytes) => serde_json::from_slice(&bytes).map_err(|err| Error::LocalStorage(format!("unreadable index: {}", err))),
+            None => Ok(Index::new()),
+        }
+    }
+
+    async fn save_index(&self, directory: &FileSystemDirectoryHandle, index: &Index) -> Result<(), Error> {
+        let bytes = serde_json::to_vec(index).map_err(|err| Error::LocalStorage(format!("{}", err)))?;
+        write_file(directory, INDEX_FILE, &bytes).await
+    }
+}
+
+async fn resolve(promise: Promise) -> Result<JsValue, Error> {
+    JsFuture::from(promise).await.map_err(js_error)
+}
+
+fn js_error(err: JsValue) -> Error {
+    match err.dyn_ref::<DomException>() {
+        Some(exception) if exception.name() == "NotFoundError" => Error::NoSuchKey,
+        Some(exception) => Error::LocalStorage(exception.message()),
+        None => Error::LocalStorage(format!("{:?}", err)),
+    }
+}
+
+// Keys contain '/', which file names can not, and must not clash with the index.
+fn file_name(key: &str) -> String {
+    format!("o-{}", urlencoding::encode(key))
+}
+
+async fn read_file(directory: &FileSystemDirectoryHandle, name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let handle: FileSystemFileHandle = match resolve(directory.get_file_handle(name)).await {
+        Ok(handle) => handle.unchecked_into(),
+        Err(Error::NoSuchKey) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let file: Blob = resolve(handle.get_file()).await?.unchecked_into();
+    let buffer = resolve(file.array_buffer()).await?;
+    Ok(Some(Uint8Array::new(&buffer).to_vec()))
+}
+
+async fn write_file(directory: &FileSystemDirectoryHandle, name: &str, body: &[u8]) -> Result<(), Error> {
+    let options = FileSystemGetFileOptions::new();
+    options.set_create(true);
+    let handle: FileSystemFileHandle = resolve(directory.get_file_handle_with_options(name, &options)).await?.unchecked_into();
+    let writable: FileSystemWritableFileStream = resolve(handle.create_writable()).await?.unchecked_into();
+    resolve(writable.write_with_u8_array(body).map_err(js_error)?).await?;
+    resolve(writable.close()).await?;
+    Ok(())
+}
+
+fn index_page(index: &Index, prefix: &str, continuation: Option<String>) -> ObjectPage {
+    let start = continuation.unwrap_or_else(|| prefix.to_string());
+    let mut matching = index
+        .range::<str, _>((Bound::Excluded(start.as_str()), Bound::Unbounded))
+        .take_while(|(key, _)| key.starts_with(prefix));
+    let objects = matching.by_ref().take(PAGE_SIZE)
+        .map(|(key, object)| S3ObjectInfo::new(key.clone(), object.last_modified.clone(), object.e_tag.clone(), object.size as i64))
+        .collect::<Vec<S3ObjectInfo>>();
+    let next_token = match matching.next() {
+        Some(_) => objects.last().map(|object| object.key()),
+        None => None,
+    };
+    ObjectPage { objects, next_token }
+}
+
+// Fails as S3 does when `key` is missing or no longer has the ETag `if_match`.
+fn check(index: &Index, key: &str, if_match: Option<&str>) -> Result<LocalObject, Error> {
+    let object = index.get(key).cloned().ok_or(Error::NoSuchKey)?;
+    match if_match {
+        Some(e_tag) if e_tag != object.e_tag => Err(Error::PreconditionFailed),
+        _ => Ok(object),
+    }
+}
+
+fn now() -> String {
+    String::from(Date::new_0().to_iso_string())
+}
+
+impl ObjectStore for LocalStore {
+    fn list_page<'a>(&'a self, prefix: &'a str, continuation: Option<String>) -> StoreFuture<'a, ObjectPage> {
+        Box::pin(async move {
+            let index = self.index(&self.directory().await?).await?;
+            Ok(index_page(&index, prefix, continuation))
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            read_file(&self.directory().await?, &file_name(key)).await?.ok_or(Error::NoSuchKey)
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ObjectHead> {
+        Box::pin(async move {
+            let object = check(&self.index(&self.directory().await?).await?, key, None)?;
+            Ok(ObjectHead { e_tag: Some(object.e_tag), size: Some(object.size), content_type: None, id: object.id, checksum: None })
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let directory = self.directory().await?;
+            write_file(&directory, &file_name(key), &body).await?;
+            let mut index = self.index(&directory).await?;
+            index.insert(key.to_string(), LocalObject { e_tag: content_e_tag(&body), last_modified: now(), size: body.len() as u64, id: None });
+            self.save_index(&directory, &index).await
+        })
+    }
+
+    fn get_with_e_tag<'a>(&'a self, key: &'a str) -> StoreFuture<'a, (Vec<u8>, String)> {
+        Box::pin(async move {
+            let directory = self.directory().await?;
+            let object = check(&self.index(&directory).await?, key, None)?;
+            let body = read_file(&directory, &file_name(key)).await?.ok_or(Error::NoSuchKey)?;
+            Ok((body, object.e_tag))
+        })
+    }
+
+    fn put_if<'a>(&'a self, key: &'a str, body: Vec<u8>, condition: &'a WriteCondition) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let index = self.index(&self.directory().await?).await?;
+            if !condition.holds(index.get(key).map(|object| object.e_tag.as_str())) {
+                return Err(Error::PreconditionFailed);
+            }
+            let e_tag = content_e_tag(&body);
+            self.put(key, body).await?;
+            Ok(e_tag)
+        })
+    }
+
+    fn copy<'a>(&'a self, from: &'a str, to: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let directory = self.directory().await?;
+            let mut index = self.index(&directory).await?;
+            let mut object = check(&index, from, if_match)?;
+            let body = read_file(&directory, &file_name(from)).await?.ok_or(Error::NoSuchKey)?;
+            write_file(&directory, &file_name(to), &body).await?;
+            object.last_modified = now();
+            let e_tag = object.e_tag.clone();
+            index.insert(to.to_string(), object);
+            self.save_index(&directory, &index).await?;
+            Ok(e_tag)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let directory = self.directory().await?;
+            let mut index = self.index(&directory).await?;
+            // as S3, deleting a missing key succeeds unless a precondition was given
+            if index.contains_key(key) || if_match.is_some() {
+                check(&index, key, if_match)?;
+            }
+            if index.remove(key).is_some() {
+                self.save_index(&directory, &index).await?;
+            }
+            match resolve(directory.remove_entry(&file_name(key))).await {
+                Ok(_) | Err(Error::NoSuchKey) => Ok(()),
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn set_id<'a>(&'a self, key: &'a str, id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let directory = self.directory().await?;
+            let mut index = self.index(&directory).await?;
+            let mut object = check(&index, key, None)?;
+            object.id = Some(id.to_string());
+            let e_tag = object.e_tag.clone();
+            index.insert(key.to_string(), object);
+            self.save_index(&directory, &index).await?;
+            Ok(e_tag)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ImageInfo;
+    use crate::local_store::{check, file_name, index_page, Index, LocalObject};
+    use crate::api::Error;
+
+    fn index(keys: &[&str]) -> Index {
+        keys.iter().map(|key| (key.to_string(), LocalObject {
+            e_tag: "\"abc\"".to_string(),
+            last_modified: "2023-10-12T14:48:09.000Z".to_string(),
+            size: 1,
+            id: None,
+        })).collect()
+    }
+
+    #[test]
+    fn test_index_page() {
+        let index = index(&["admin/settings.json", "comments/\"abc\"", "images/a.jpg", "images/b.jpg"]);
+        let page = index_page(&index, "images/", None);
+        assert_eq!(page.objects.iter().map(|o| o.key()).collect::<Vec<String>>(), vec!["images/a.jpg", "images/b.jpg"]);
+        assert_eq!(page.next_token, None);
+        assert_eq!(index_page(&index, "", None).objects.len(), 4);
+    }
+
+    #[test]
+    fn test_check_precondition() {
+        let index = index(&["images/a.jpg"]);
+        assert!(check(&index, "images/a.jpg", Some("\"abc\"")).is_ok());
+        assert!(matches!(check(&index, "images/a.jpg", Some("\"def\"")), Err(Error::PreconditionFailed)));
+        assert!(matches!(check(&index, "images/b.jpg", None), Err(Error::NoSuchKey)));
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("images/a b.jpg"), "o-images%2Fa%20b.jpg");
+        assert_ne!(file_name("index.json"), "index.json");
+    }
+}
@@ -0,0 +1,386 @@
+use std::io::Cursor;
+
+use image::io::Reader;
+use rexif::ExifTag;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::{list_images, Error};
+use crate::object_store::{ObjectHead, ObjectStore};
+use crate::caption::Caption;
+use crate::{get_caption, ImageInfo, S3ObjectInfo};
+
+/// The site's gallery in one document, so it loads with a single request.
+pub const MANIFEST_KEY: &str = "admin/manifest.json";
+// 2: images have ids stored with them, and captions are keyed by them
+// 3: captions are documents, see `Caption`
+// 4: caption descriptions are rich text
+const MANIFEST_VERSION: u32 = 4;
+
+/// An image as recorded in the manifest.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ManifestImage {
+    pub key: String,
+    // stored with the image, see `ObjectStore::set_id`
+    pub id: String,
+    pub e_tag: String,
+    pub last_modified: String,
+    pub size: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<(u32, u32)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<Caption>,
+    // as recorded in the image's EXIF data, e.g. "2023:10:12 14:48:09"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_taken: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_key: Option<String>,
+}
+
+impl ManifestImage {
+    /// An image known only from a listing, its details are filled in when it is next uploaded.
+    pub fn from_listing(image: &S3ObjectInfo, id: String, caption: Option<Caption>) -> Self {
+        Self {
+            key: image.key(),
+            id,
+            e_tag: image.e_tag(),
+            last_modified: image.last_modified(),
+            size: image.size(),
+            dimensions: None,
+            caption,
+            date_taken: None,
+            thumbnail_key: None,
+        }
+    }
+
+    /// The image as the gallery lists it, with its caption, which is empty when it has none.
+    pub fn object_info(&self) -> S3ObjectInfo {
+        let mut info = S3ObjectInfo::new(self.key.clone(), self.last_modified.clone(), self.e_tag.clone(), self.size);
+        info.set_caption(Some(self.caption.clone().unwrap_or_default()));
+        info.set_id(Some(self.id.clone()));
+        info
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    pub images: Vec<ManifestImage>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self { version: MANIFEST_VERSION, images: vec![] }
+    }
+
+    /// The images, most recently modified first, as `list_images` returns them.
+    pub fn object_infos(&self) -> Vec<S3ObjectInfo> {
+        let mut infos = self.images.iter().map(|image| image.object_info()).collect::<Vec<S3ObjectInfo>>();
+        infos.sort_by_key(|b| std::cmp::Reverse(b.last_modified()));
+        infos
+    }
+
+    /// Adds `image`, replacing an image with the same key.
+    pub fn insert(&mut self, image: ManifestImage) {
+        match self.images.iter_mut().find(|existing| existing.key == image.key) {
+            Some(existing) => *existing = image,
+            None => self.images.push(image),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.images.retain(|image| image.key != key);
+    }
+
+    /// Moves the image at `from` to `to`, which now has the ETag `e_tag`.
+    pub fn rename(&mut self, from: &str, to: &str, e_tag: &str) {
+        self.remove(to);
+        if let Some(image) = self.images.iter_mut().find(|image| image.key == from) {
+            image.key = to.to_string();
+            image.e_tag = e_tag.to_string();
+        }
+    }
+
+    pub fn set_e_tag(&mut self, key: &str, e_tag: &str) {
+        if let Some(image) = self.images.iter_mut().find(|image| image.key == key) {
+            image.e_tag = e_tag.to_string();
+        }
+    }
+
+    /// Captions are kept with their image's id, as in `comments/<id>`.
+    pub fn set_caption(&mut self, id: &str, caption: &Caption) {
+        for image in self.images.iter_mut().filter(|image| image.id == id) {
+            image.caption = Some(caption.clone()).filter(|caption| !caption.is_empty());
+        }
+    }
+}
+
+/// Dimensions and the EXIF date taken of an image's body, when they can be read.
+pub fn image_details(body: &[u8]) -> (Option<(u32, u32)>, Option<String>) {
+    let dimensions = Reader::new(Cursor::new(body))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    let date_taken = match rexif::parse_buffer(body) {
+        Ok(exif) => exif.entries.into_iter()
+            .find(|entry| entry.tag == ExifTag::DateTimeOriginal)
+            .map(|entry| entry.value_more_readable.to_string()),
+        Err(_) => None,
+    };
+    (dimensions, date_taken)
+}
+
+/// The site's manifest, None when it has not been made yet. A manifest of an earlier version is
+/// None too, so it is rebuilt, giving images ids and reading their captions as documents.
+pub async fn load_manifest(store: &dyn ObjectStore) -> Result<Option<Manifest>, Error> {
+    match store.get(MANIFEST_KEY).await {
+        Ok(bytes) => serde_json::from_slice::<Manifest>(&bytes)
+            .map(|manifest| Some(manifest).filter(|manifest| manifest.version >= MANIFEST_VERSION))
+            .map_err(|err| Error::Fetch(format!("unreadable manifest: {}", err))),
+        Err(Error::NoSuchKey) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+async fn save_manifest(store: &dyn ObjectStore, manifest: &Manifest) -> Result<(), Error> {
+    let body = serde_json::to_vec(manifest).map_err(|err| Error::Fetch(format!("{}", err)))?;
+    store.put(MANIFEST_KEY, body).await
+}
+
+/// The id stored with the image at `key` and its ETag. An image stored before images had ids is
+/// given one, and its caption, which was keyed by its ETag, is moved to the id.
+pub async fn image_id(store: &dyn ObjectStore, key: &str) -> Result<(String, String), Error> {
+    let head = store.head(key).await?;
+    let e_tag = head.e_tag.ok_or(Error::Fetch(format!("no ETag for {}", key)))?;
+    if let Some(id) = head.id {
+        return Ok((id, e_tag));
+    }
+    let id = Uuid::new_v4().to_string();
+    let new_e_tag = store.set_id(key, &id).await?;
+    let old_caption = format!("comments/{}", e_tag);
+    match store.get(&old_caption).await {
+        Ok(caption) => {
+            store.put(&format!("comments/{}", id), caption).await?;
+            store.delete(&old_caption, None).await?;
+        },
+        Err(Error::NoSuchKey) => (),
+        Err(err) => return Err(err),
+    }
+    Ok((id, new_e_tag))
+}
+
+/// The id an image uploaded to `key` is stored with. An image replacing another keeps its id, and
+/// so its caption.
+pub async fn upload_id(store: &dyn ObjectStore, key: &str) -> String {
+    match store.head(key).await {
+        Ok(ObjectHead { id: Some(id), .. }) => id,
+        _ => Uuid::new_v4().to_string(),
+    }
+}
+
+/// The name `image`'s caption is stored under, its id, or its ETag until it has been given one.
+pub async fn caption_name(store: &dyn ObjectStore, image: &S3ObjectInfo) -> String {
+    match image.id() {
+        Some(id) => id,
+        None => match store.head(&image.key()).await {
+            Ok(ObjectHead { id: Some(id), .. }) => id,
+            _ => image.e_tag(),
+        },
+    }
+}
+
+/// A manifest of `images`, with their captions read from the store. Images without ids are given
+/// them, see `image_id`.
+pub async fn build_manifest(store: &dyn ObjectStore, images: &[S3ObjectInfo]) -> Result<Manifest, Error> {
+    let mut manifest = Manifest::new();
+    for image in images {
+        let (id, e_tag) = image_id(store, &image.key()).await?;
+        let caption = Some(get_caption(store, id.clone()).await).filter(|caption| !caption.is_empty());
+        manifest.images.push(ManifestImage { e_tag, ..ManifestImage::from_listing(image, id, caption) });
+    }
+    Ok(manifest)
+}
+
+/// Applies `change` to the site's manifest and saves it. A site without a manifest gets one
+/// built from a listing first.
+pub async fn update_manifest(store: &dyn ObjectStore, change: impl FnOnce(&mut Manifest)) -> Result<(), Error> {
+    let mut manifest = match load_manifest(store).await? {
+        Some(manifest) => manifest,
+        None => build_manifest(store, &list_images(store).await?).await?,
+    };
+    change(&mut manifest);
+    save_manifest(store, &manifest).await
+}
+
+/// Records the image stored at `key` in the manifest. Its body is read from the store unless given.
+pub async fn record_image(store: &dyn ObjectStore, key: &str, body: Option<Vec<u8>>, last_modified: String) -> Result<(), Error> {
+    let body = match body {
+        Some(body) => body,
+        None => store.get(key).await?,
+    };
+    let (id, e_tag) = image_id(store, key).await?;
+    let caption = Some(get_caption(store, id.clone()).await).filter(|caption| !caption.is_empty());
+    let (dimensions, date_taken) = image_details(&body);
+    let image = ManifestImage {
+        key: key.to_string(),
+        id,
+        e_tag,
+        last_modified,
+        size: body.len() as i64,
+        dimensions,
+        caption,
+        date_taken,
+        thumbnail_key: None,
+    };
+    update_manifest(store, |manifest| manifest.insert(image)).await
+}
+
+/// The site's images from its manifest, or from a listing when it has none. A site which can be
+/// written gets a manifest built from the listing, so later loads take one request. Hidden images
+/// are only listed for a site which can be written.
+pub async fn load_gallery(store: &dyn ObjectStore, can_write: bool) -> Result<Vec<S3ObjectInfo>, Error> {
+    if let Ok(Some(manifest)) = load_manifest(store).await {
+        return Ok(manifest.object_infos().into_iter()
+            .filter(|image| can_write || !image.get_caption().is_some_and(|caption| caption.hidden))
+            .collect());
+    }
+    let images = list_images(store).await?;
+    if can_write {
+        match build_manifest(store, &images).await {
+            Ok(manifest) => {
+                if let Err(err) = save_manifest(store, &manifest).await {
+                    log::info!("unable to save the manifest: {}", err);
+                }
+                return Ok(manifest.object_infos());
+            },
+            Err(err) => log::info!("unable to build the manifest: {}", err),
+        }
+    }
+    Ok(images)
+}
+
+/// The current time as S3 formats LastModified.
+pub fn now_iso() -> String {
+    String::from(js_sys::Date::new_0().to_iso_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ImageInfo;
+    use crate::caption::Caption;
+    use crate::manifest::*;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::{block_on, ObjectStore};
+
+    fn image(key: &str, e_tag: &str, last_modified: &str) -> ManifestImage {
+        ManifestImage {
+            key: key.to_string(),
+            id: format!("id-{}", key),
+            e_tag: e_tag.to_string(),
+            last_modified: last_modified.to_string(),
+            size: 1,
+            dimensions: None,
+            caption: None,
+            date_taken: None,
+            thumbnail_key: None,
+        }
+    }
+
+    #[test]
+    fn test_manifest_changes() {
+        let mut manifest = Manifest::new();
+        manifest.insert(image("images/a.jpg", "\"a\"", "2023-10-12T14:48:09.000Z"));
+        manifest.insert(image("images/b.jpg", "\"b\"", "2023-10-13T14:48:09.000Z"));
+        manifest.set_caption("id-images/a.jpg", &Caption::parse(b"a caption"));
+        assert_eq!(manifest.object_infos().iter().map(|i| i.key()).collect::<Vec<String>>(), vec!["images/b.jpg", "images/a.jpg"]);
+        assert_eq!(manifest.object_infos()[1].get_caption(), Some(Caption::parse(b"a caption")));
+        assert_eq!(manifest.object_infos()[0].get_caption(), Some(Caption::default()));
+        assert_eq!(manifest.object_infos()[0].id(), Some("id-images/b.jpg".to_string()));
+
+        manifest.insert(image("images/a.jpg", "\"c\"", "2023-10-14T14:48:09.000Z"));
+        assert_eq!(manifest.images[0].e_tag, "\"c\"");
+        assert_eq!(manifest.images.len(), 2);
+
+        manifest.rename("images/a.jpg", "images/b.jpg", "\"c\"");
+        assert_eq!(manifest.images, vec![ManifestImage { key: "images/b.jpg".to_string(), ..image("images/a.jpg", "\"c\"", "2023-10-14T14:48:09.000Z") }]);
+        manifest.remove("images/b.jpg");
+        assert!(manifest.images.is_empty());
+    }
+
+    #[test]
+    fn test_old_manifest_fields_default() {
+        let json = r#"{"version":4,"images":[{"key":"images/a.jpg","id":"1","e_tag":"\"a\"","last_modified":"2023-10-12T14:48:09.000Z","size":3}]}"#;
+        let manifest = serde_json::from_str::<Manifest>(json).unwrap();
+        assert_eq!(manifest.images[0].caption, None);
+        assert_eq!(serde_json::to_string(&manifest).unwrap(), json);
+    }
+
+    #[test]
+    fn test_update_builds_missing_manifest() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            store.put("images/b.jpg", vec![2]).await.unwrap();
+            let e_tag = store.head("images/a.jpg").await.unwrap().e_tag.unwrap();
+            store.put(&format!("comments/{}", e_tag), b"a caption".to_vec()).await.unwrap();
+            assert_eq!(load_manifest(&store).await.unwrap(), None);
+
+            update_manifest(&store, |manifest| manifest.remove("images/b.jpg")).await.unwrap();
+            let manifest = load_manifest(&store).await.unwrap().unwrap();
+            assert_eq!(manifest.images.len(), 1);
+            // captions stored as plain text are read as descriptions
+            assert_eq!(manifest.images[0].caption.as_ref().map(|caption| caption.description.as_str()), Some("<p>a caption</p>"));
+        });
+    }
+
+    #[test]
+    fn test_images_without_ids_are_migrated() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            let e_tag = store.head("images/a.jpg").await.unwrap().e_tag.unwrap();
+            store.put(&format!("comments/{}", e_tag), b"a caption".to_vec()).await.unwrap();
+            // a manifest from before images had ids
+            store.put(MANIFEST_KEY, br#"{"version":1,"images":[]}"#.to_vec()).await.unwrap();
+            assert_eq!(load_manifest(&store).await.unwrap(), None);
+
+            let gallery = load_gallery(&store, true).await.unwrap();
+            let id = store.head("images/a.jpg").await.unwrap().id.unwrap();
+            assert_eq!(gallery[0].id(), Some(id.clone()));
+            assert_eq!(gallery[0].get_caption(), Some(Caption::parse(b"a caption")));
+            assert_eq!(store.get(&format!("comments/{}", id)).await.unwrap(), b"a caption".to_vec());
+            assert!(store.get(&format!("comments/{}", e_tag)).await.is_err());
+
+            // a replacement keeps the id, which is kept again when the manifest is rebuilt
+            assert_eq!(upload_id(&store, "images/a.jpg").await, id);
+            store.upload("images/a.jpg", vec![2], &id, &|_, _| (), None).await.unwrap();
+            store.delete(MANIFEST_KEY, None).await.unwrap();
+            assert_eq!(load_gallery(&store, true).await.unwrap()[0].get_caption(), Some(Caption::parse(b"a caption")));
+            assert_ne!(upload_id(&store, "images/new.jpg").await, id);
+        });
+    }
+
+    #[test]
+    fn test_load_gallery() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            // visitors list the images without making a manifest
+            assert_eq!(load_gallery(&store, false).await.unwrap().len(), 1);
+            assert_eq!(load_manifest(&store).await.unwrap(), None);
+
+            assert_eq!(load_gallery(&store, true).await.unwrap().len(), 1);
+            record_image(&store, "images/a.jpg", None, "2023-10-12T14:48:09.000Z".to_string()).await.unwrap();
+            store.put("images/b.jpg", vec![2]).await.unwrap();
+            // images stored without the app are not in the manifest
+            let gallery = load_gallery(&store, true).await.unwrap();
+            assert_eq!(gallery.iter().map(|i| i.key()).collect::<Vec<String>>(), vec!["images/a.jpg"]);
+
+            // hidden images are listed for the owner only
+            let id = store.head("images/a.jpg").await.unwrap().id.unwrap();
+            update_manifest(&store, |manifest| manifest.set_caption(&id, &Caption { hidden: true, ..Caption::default() })).await.unwrap();
+            assert_eq!(load_gallery(&store, true).await.unwrap().len(), 1);
+            assert!(load_gallery(&store, false).await.unwrap().is_empty());
+        });
+    }
+}
@@ -0,0 +1,270 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+
+use time::OffsetDateTime;
+use time::macros::format_description;
+
+use crate::api::Error;
+use crate::object_store::{content_e_tag, ObjectHead, ObjectPage, ObjectStore, ObjectVersion, StoreFuture, WriteCondition};
+use crate::{ImageInfo, S3ObjectInfo};
+
+// Keys returned in a page of a listing, as S3's default.
+const PAGE_SIZE: usize = 1000;
+
+#[derive(Clone)]
+struct MemoryObject {
+    body: Vec<u8>,
+    e_tag: String,
+    last_modified: String,
+    id: Option<String>,
+}
+
+// A write kept as a version, `object` is `None` for a delete marker.
+struct MemoryVersion {
+    key: String,
+    version_id: String,
+    last_modified: String,
+    object: Option<MemoryObject>,
+}
+
+/// An `ObjectStore` kept in memory, for testing the gallery without a bucket. Every write is one
+/// second after the one before it, so listings are ordered the same way on every run. Every write
+/// is kept as a version, as in a bucket with versioning enabled.
+pub struct MemoryStore {
+    objects: RefCell<BTreeMap<String, MemoryObject>>,
+    versions: RefCell<Vec<MemoryVersion>>,
+    clock: Cell<i64>,
+    // reads which fail, see `fail_reads`
+    failed_reads: RefCell<BTreeMap<String, Error>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            objects: RefCell::new(BTreeMap::new()),
+            versions: RefCell::new(vec![]),
+            clock: Cell::new(1_700_000_000),
+            failed_reads: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Reads of `key` fail with `err` from now on, as they would for a corrupt object or when the
+    /// network fails.
+    pub fn fail_reads(&self, key: &str, err: Error) {
+        self.failed_reads.borrow_mut().insert(key.to_string(), err);
+    }
+
+    fn tick(&self) -> String {
+        self.clock.set(self.clock.get() + 1);
+        OffsetDateTime::from_unix_timestamp(self.clock.get())
+            .expect("valid timestamp")
+            .format(format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].000Z"))
+            .expect("formatted timestamp")
+    }
+
+    // Stores `object` at `key` as its new version, or removes it with a delete marker.
+    fn write(&self, key: &str, object: Option<MemoryObject>) {
+        let last_modified = match &object {
+            Some(object) => object.last_modified.clone(),
+            None => self.tick(),
+        };
+        let mut versions = self.versions.borrow_mut();
+        let version_id = format!("v{}", versions.len() + 1);
+        versions.push(MemoryVersion { key: key.to_string(), version_id, last_modified, object: object.clone() });
+        match object {
+            Some(object) => self.objects.borrow_mut().insert(key.to_string(), object),
+            None => self.objects.borrow_mut().remove(key),
+        };
+    }
+
+    fn version(&self, key: &str, version_id: &str) -> Result<MemoryObject, Error> {
+        self.versions.borrow().iter()
+            .find(|version| version.key == key && version.version_id == version_id)
+            .and_then(|version| version.object.clone())
+            .ok_or(Error::NoSuchKey)
+    }
+
+    fn object(&self, key: &str, if_match: Option<&str>) -> Result<MemoryObject, Error> {
+        let object = self.objects.borrow().get(key).cloned().ok_or(Error::NoSuchKey)?;
+        match if_match {
+            Some(e_tag) if e_tag != object.e_tag => Err(Error::PreconditionFailed),
+            _ => Ok(object),
+        }
+    }
+}
+
+impl ObjectStore for MemoryStore {
+    fn list_page<'a>(&'a self, prefix: &'a str, continuation: Option<String>) -> StoreFuture<'a, ObjectPage> {
+        Box::pin(async move {
+            let start = continuation.unwrap_or_else(|| prefix.to_string());
+            let objects = self.objects.borrow();
+            let mut matching = objects
+                .range::<str, _>((Bound::Excluded(start.as_str()), Bound::Unbounded))
+                .take_while(|(key, _)| key.starts_with(prefix));
+            let page = matching.by_ref().take(PAGE_SIZE)
+                .map(|(key, object)| S3ObjectInfo::new(key.clone(), object.last_modified.clone(), object.e_tag.clone(), object.body.len() as i64))
+                .collect::<Vec<S3ObjectInfo>>();
+            let next_token = match matching.next() {
+                Some(_) => page.last().map(|object| object.key()),
+                None => None,
+            };
+            Ok(ObjectPage { objects: page, next_token })
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            if let Some(err) = self.failed_reads.borrow().get(key) {
+                return Err(err.clone());
+            }
+            Ok(self.object(key, None)?.body)
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ObjectHead> {
+        Box::pin(async move {
+            let object = self.object(key, None)?;
+            Ok(ObjectHead { e_tag: Some(object.e_tag), size: Some(object.body.len() as u64), content_type: None, id: object.id, checksum: None })
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let object = MemoryObject { e_tag: content_e_tag(&body), body, last_modified: self.tick(), id: None };
+            self.write(key, Some(object));
+            Ok(())
+        })
+    }
+
+    fn get_with_e_tag<'a>(&'a self, key: &'a str) -> StoreFuture<'a, (Vec<u8>, String)> {
+        Box::pin(async move {
+            let object = self.object(key, None)?;
+            Ok((object.body, object.e_tag))
+        })
+    }
+
+    fn put_if<'a>(&'a self, key: &'a str, body: Vec<u8>, condition: &'a WriteCondition) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let e_tag = self.objects.borrow().get(key).map(|object| object.e_tag.clone());
+            if !condition.holds(e_tag.as_deref()) {
+                return Err(Error::PreconditionFailed);
+            }
+            self.put(key, body).await?;
+            Ok(self.object(key, None)?.e_tag)
+        })
+    }
+
+    fn copy<'a>(&'a self, from: &'a str, to: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let mut object = self.object(from, if_match)?;
+            object.last_modified = self.tick();
+            let e_tag = object.e_tag.clone();
+            self.write(to, Some(object));
+            Ok(e_tag)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            // as S3, deleting a missing key succeeds unless a precondition was given
+            if self.objects.borrow().contains_key(key) || if_match.is_some() {
+                self.object(key, if_match)?;
+                self.write(key, None);
+            }
+            Ok(())
+        })
+    }
+
+    fn set_id<'a>(&'a self, key: &'a str, id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let mut object = self.object(key, None)?;
+            object.id = Some(id.to_string());
+            let e_tag = object.e_tag.clone();
+            self.write(key, Some(object));
+            Ok(e_tag)
+        })
+    }
+
+    fn list_versions<'a>(&'a self, prefix: &'a str) -> StoreFuture<'a, Vec<ObjectVersion>> {
+        Box::pin(async move {
+            let mut latest_keys = HashSet::new();
+            let mut versions = self.versions.borrow().iter().rev()
+                .filter(|version| version.key.starts_with(prefix))
+                .map(|version| ObjectVersion {
+                    key: version.key.clone(),
+                    version_id: version.version_id.clone(),
+                    last_modified: version.last_modified.clone(),
+                    e_tag: version.object.as_ref().map(|object| object.e_tag.clone()),
+                    size: version.object.as_ref().map(|object| object.body.len() as u64).unwrap_or_default(),
+                    is_latest: latest_keys.insert(version.key.clone()),
+                    is_delete_marker: version.object.is_none(),
+                })
+                .collect::<Vec<ObjectVersion>>();
+            // stable, so each key's newest version stays first
+            versions.sort_by(|a, b| a.key.cmp(&b.key));
+            Ok(versions)
+        })
+    }
+
+    fn get_version<'a>(&'a self, key: &'a str, version_id: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move { Ok(self.version(key, version_id)?.body) })
+    }
+
+    fn restore_version<'a>(&'a self, key: &'a str, version_id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let mut object = self.version(key, version_id)?;
+            object.last_modified = self.tick();
+            let e_tag = object.e_tag.clone();
+            self.write(key, Some(object));
+            Ok(e_tag)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Error;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::{block_on, ObjectStore, WriteCondition};
+
+    #[test]
+    fn test_copy_keeps_e_tag() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1, 2, 3]).await.unwrap();
+            store.set_id("images/a.jpg", "a-id").await.unwrap();
+            let head = store.head("images/a.jpg").await.unwrap();
+            let e_tag = store.copy("images/a.jpg", "images/b.jpg", head.e_tag.as_deref()).await.unwrap();
+            assert_eq!(Some(e_tag), head.e_tag);
+            assert_eq!(store.get("images/b.jpg").await.unwrap(), vec![1, 2, 3]);
+            assert_eq!(store.head("images/b.jpg").await.unwrap().id, Some("a-id".to_string()));
+        });
+    }
+
+    #[test]
+    fn test_conditional_delete() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            assert!(matches!(store.delete("images/a.jpg", Some("\"stale\"")).await, Err(Error::PreconditionFailed)));
+            assert!(store.get("images/a.jpg").await.is_ok());
+            assert!(store.delete("images/missing.jpg", None).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_conditional_put() {
+        let store = MemoryStore::new();
+        block_on(async {
+            let e_tag = store.put_if("comments/a", vec![1], &WriteCondition::Absent).await.unwrap();
+            assert!(matches!(store.put_if("comments/a", vec![2], &WriteCondition::Absent).await, Err(Error::PreconditionFailed)));
+            assert_eq!(store.get_with_e_tag("comments/a").await.unwrap(), (vec![1], e_tag.clone()));
+
+            let new_e_tag = store.put_if("comments/a", vec![3], &WriteCondition::Matches(e_tag.clone())).await.unwrap();
+            assert!(matches!(store.put_if("comments/a", vec![4], &WriteCondition::Matches(e_tag)).await, Err(Error::PreconditionFailed)));
+            assert_eq!(store.get_with_e_tag("comments/a").await.unwrap(), (vec![3], new_e_tag));
+            assert!(matches!(store.put_if("comments/b", vec![5], &WriteCondition::Matches("\"b\"".to_string())).await, Err(Error::PreconditionFailed)));
+        });
+    }
+}
@@ -0,0 +1,387 @@
+use std::ops::Range;
+
+use gloo_net::http::Method;
+use gloo_storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+use web_sys::AbortSignal;
+
+use crate::api::{object_url, send_signed_url_request, send_signed_url_request_with_headers, Error};
+use crate::checksum::{sha256, verify, S3_CHECKSUM_HEADER};
+
+// S3 requires every part but the last to be at least 5 MiB.
+pub const PART_SIZE: usize = 5 * 1024 * 1024;
+const PENDING_UPLOADS: &str = "WM_PENDING_UPLOADS";
+
+#[derive(Deserialize)]
+struct InitiateMultipartUploadResult {
+    #[serde(rename(deserialize = "UploadId"))]
+    upload_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct UploadedPart {
+    #[serde(rename(deserialize = "PartNumber"))]
+    part_number: u32,
+    #[serde(rename(deserialize = "ETag"))]
+    e_tag: String,
+    // the part's SHA-256, S3 lists it for uploads created with a checksum algorithm
+    #[serde(rename(deserialize = "ChecksumSHA256"))]
+    checksum: Option<String>,
+    #[serde(rename(deserialize = "Size"))]
+    size: usize,
+}
+
+fn default_parts() -> Vec<UploadedPart> {
+    vec![]
+}
+
+#[derive(Deserialize)]
+struct ListPartsResult {
+    #[serde(rename(deserialize = "IsTruncated"))]
+    is_truncated: bool,
+    #[serde(rename(deserialize = "NextPartNumberMarker"))]
+    next_part_number_marker: Option<u32>,
+    #[serde(rename(deserialize = "Part"), default = "default_parts")]
+    parts: Vec<UploadedPart>,
+}
+
+// An interrupted upload, remembered so the same image can continue where it stopped.
+#[derive(Clone, Deserialize, Serialize)]
+struct PendingUpload {
+    key: String,
+    upload_id: String,
+    // the body's checksum, an upload of another body to the key is aborted
+    fingerprint: String,
+}
+
+// A part of the body, its number, byte range and checksum.
+type BodyPart = (u32, Range<usize>, String);
+
+/// Part numbers and byte ranges for a body of `total` bytes.
+pub fn part_ranges(total: usize, part_size: usize) -> Vec<(u32, Range<usize>)> {
+    (0..total.div_ceil(part_size))
+        .map(|i| ((i + 1) as u32, (i * part_size)..((i + 1) * part_size).min(total)))
+        .collect()
+}
+
+// S3's upload ids may hold characters which must be encoded in a query.
+fn upload_query(upload_id: &str) -> String {
+    format!("uploadId={}", urlencoding::encode(upload_id))
+}
+
+// Counts a multipart upload's bytes as whole parts, once S3 has them. Fetch does not report the
+// bytes sent within a request, so progress moves a part at a time.
+struct PartProgress<'a> {
+    sent: usize,
+    total: usize,
+    on_progress: &'a dyn Fn(usize, usize),
+}
+
+impl<'a> PartProgress<'a> {
+    // starts from the parts uploaded before the upload was resumed
+    fn new(uploaded: &[UploadedPart], total: usize, on_progress: &'a dyn Fn(usize, usize)) -> Self {
+        let sent = uploaded.iter().map(|p| p.size).sum();
+        on_progress(sent, total);
+        Self { sent, total, on_progress }
+    }
+
+    fn part_sent(&mut self, size: usize) {
+        self.sent += size;
+        (self.on_progress)(self.sent, self.total);
+    }
+}
+
+// The listed parts which match the ranges and checksums of the body's parts.
+fn resumable_parts(body_parts: &[BodyPart], listed: Vec<UploadedPart>) -> Vec<UploadedPart> {
+    listed.into_iter()
+        .filter(|p| body_parts.iter().any(|(n, r, checksum)| *n == p.part_number && r.len() == p.size && p.checksum.as_ref() == Some(checksum)))
+        .collect()
+}
+
+fn complete_multipart_body(parts: &[UploadedPart]) -> String {
+    let parts_xml = parts.iter()
+        .map(|p| match &p.checksum {
+            Some(checksum) => format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag><ChecksumSHA256>{}</ChecksumSHA256></Part>", p.part_number, p.e_tag, checksum),
+            None => format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", p.part_number, p.e_tag),
+        })
+        .collect::<String>();
+    format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts_xml)
+}
+
+fn pending_uploads() -> Vec<PendingUpload> {
+    LocalStorage::get::<Vec<PendingUpload>>(PENDING_UPLOADS).unwrap_or_default()
+}
+
+fn pending_upload(key: &str) -> Option<PendingUpload> {
+    pending_uploads().into_iter().find(|p| p.key == key)
+}
+
+fn save_pending_upload(pending: PendingUpload) {
+    let mut uploads = pending_uploads();
+    uploads.retain(|p| p.key != pending.key);
+    uploads.push(pending);
+    let _ = LocalStorage::set(PENDING_UPLOADS, uploads);
+}
+
+fn remove_pending_upload(key: &str) {
+    let mut uploads = pending_uploads();
+    uploads.retain(|p| p.key != key);
+    let _ = LocalStorage::set(PENDING_UPLOADS, uploads);
+}
+
+fn parse_xml<T: for<'de> Deserialize<'de>>(xml: &str) -> Result<T, Error> {
+    quick_xml::de::from_str(xml).map_err(|err| Error::UploadError(format!("unexpected response: {}", err)))
+}
+
+// The object's metadata and headers are sent when the upload is created, each part is then sent
+// with its checksum.
+async fn create_upload(url: &str, headers: &[(String, String)]) -> Result<String, Error> {
+    let mut headers = headers.to_vec();
+    headers.push(("x-amz-checksum-algorithm".to_string(), "SHA256".to_string()));
+    let response = send_signed_url_request_with_headers(Method::POST, format!("{}?uploads", url), &headers, None, None).await?;
+    let xml = response.text().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+    Ok(parse_xml::<InitiateMultipartUploadResult>(&xml)?.upload_id)
+}
+
+async fn list_parts(url: &str, upload_id: &str) -> Result<Vec<UploadedPart>, Error> {
+    let mut parts: Vec<UploadedPart> = vec![];
+    let mut marker: u32 = 0;
+    loop {
+        let list_url = format!("{}?{}&part-number-marker={}", url, upload_query(upload_id), marker);
+        let response = send_signed_url_request(Method::GET, list_url, None, None).await?;
+        let xml = response.text().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+        let mut result = parse_xml::<ListPartsResult>(&xml)?;
+        parts.append(&mut result.parts);
+        match (result.is_truncated, result.next_part_number_marker) {
+            (true, Some(next)) => marker = next,
+            _ => return Ok(parts),
+        }
+    }
+}
+
+async fn upload_part(url: &str, upload_id: &str, part_number: u32, body: &[u8], checksum: &str, abort: Option<&AbortSignal>) -> Result<String, Error> {
+    let part_url = format!("{}?partNumber={}&{}", url, part_number, upload_query(upload_id));
+    let headers = [(S3_CHECKSUM_HEADER.to_string(), checksum.to_string())];
+    // transient failures are retried by the api
+    let response = send_signed_url_request_with_headers(Method::PUT, part_url.clone(), &headers, Some(body.to_vec()), abort).await?;
+    verify(&part_url, checksum, response.headers().get(S3_CHECKSUM_HEADER).as_deref())?;
+    // the bucket's CORS configuration must expose the ETag header
+    response.headers().get("ETag")
+        .ok_or(Error::UploadError("ETag header not exposed by the bucket".to_string()))
+}
+
+async fn complete_upload(url: &str, upload_id: &str, parts: &[UploadedPart]) -> Result<(), Error> {
+    let complete_url = format!("{}?{}", url, upload_query(upload_id));
+    let body = complete_multipart_body(parts).into_bytes();
+    let response = send_signed_url_request(Method::POST, complete_url, Some(body), None).await?;
+    // S3 can report a failure in the body of a 200 response
+    let text = response.text().await.unwrap_or_default();
+    match text.contains("<Error>") {
+        true => Err(Error::UploadError(text)),
+        false => Ok(()),
+    }
+}
+
+async fn abort_upload(url: &str, upload_id: &str) {
+    let abort_url = format!("{}?{}", url, upload_query(upload_id));
+    let _ = send_signed_url_request(Method::DELETE, abort_url, None, None).await;
+}
+
+async fn upload_multipart(key: String, body: Vec<u8>, headers: &[(String, String)], on_progress: &dyn Fn(usize, usize), abort: Option<AbortSignal>) -> Result<(), Error> {
+    let total = body.len();
+    let url = object_url(&key);
+    let body_fingerprint = sha256(&body);
+    let body_parts = part_ranges(total, PART_SIZE).into_iter()
+        .map(|(part_number, range)| {
+            let checksum = sha256(&body[range.clone()]);
+            (part_number, range, checksum)
+        })
+        .collect::<Vec<BodyPart>>();
+
+    let resumed = match pending_upload(&key) {
+        Some(pending) if pending.fingerprint == body_fingerprint => match list_parts(&url, &pending.upload_id).await {
+            Ok(parts) if parts.iter().all(|p| p.checksum.is_some()) => Some((pending.upload_id, resumable_parts(&body_parts, parts))),
+            // parts uploaded before they had checksums can not be completed along with those which do
+            Ok(_) => {
+                abort_upload(&url, &pending.upload_id).await;
+                None
+            },
+            // the upload was completed or aborted elsewhere
+            Err(_) => None,
+        },
+        // another body, or one fingerprinted before bodies had checksums, is not resumed
+        Some(pending) => {
+            abort_upload(&url, &pending.upload_id).await;
+            None
+        },
+        None => None,
+    };
+    let (upload_id, mut completed) = match resumed {
+        Some(resumed) => resumed,
+        None => (create_upload(&url, headers).await?, vec![]),
+    };
+    save_pending_upload(PendingUpload {
+        key: key.clone(),
+        upload_id: upload_id.clone(),
+        fingerprint: body_fingerprint,
+    });
+
+    let mut progress = PartProgress::new(&completed, total, on_progress);
+
+    for (part_number, range, checksum) in body_parts {
+        if completed.iter().any(|p| p.part_number == part_number) {
+            continue;
+        }
+        let cancelled = abort.as_ref().map(|signal| signal.aborted()).unwrap_or(false);
+        let result = match cancelled {
+            true => Err(Error::Cancelled),
+            false => upload_part(&url, &upload_id, part_number, &body[range.clone()], &checksum, abort.as_ref()).await,
+        };
+        match result {
+            Ok(e_tag) => {
+                completed.push(UploadedPart { part_number, e_tag, checksum: Some(checksum), size: range.len() });
+                progress.part_sent(range.len());
+            },
+            Err(Error::Cancelled) => {
+                abort_upload(&url, &upload_id).await;
+                remove_pending_upload(&key);
+                return Err(Error::Cancelled);
+            },
+            // the upload is left in place so it can be resumed
+            Err(err) => return Err(err),
+        }
+    }
+
+    completed.sort_by_key(|p| p.part_number);
+    complete_upload(&url, &upload_id, &completed).await?;
+    remove_pending_upload(&key);
+    Ok(())
+}
+
+/// Uploads `body` to `key` with `headers`, its metadata and content headers, using a multipart
+/// upload for bodies larger than one part. An interrupted multipart upload of the same body to the
+/// same key is resumed, the object then has the headers it was first uploaded with. S3 verifies the
+/// body, or each part, against its SHA-256. Progress is reported as each part completes, or once
+/// for a body sent whole.
+pub async fn upload_with_progress(key: String, body: Vec<u8>, headers: &[(String, String)], on_progress: &dyn Fn(usize, usize), abort: Option<AbortSignal>) -> Result<(), Error> {
+    let total = body.len();
+    on_progress(0, total);
+    if total > PART_SIZE {
+        return upload_multipart(key, body, headers, on_progress, abort).await;
+    }
+    let checksum = sha256(&body);
+    let mut headers = headers.to_vec();
+    headers.push((S3_CHECKSUM_HEADER.to_string(), checksum.clone()));
+    let response = send_signed_url_request_with_headers(Method::PUT, object_url(&key), &headers, Some(body), abort.as_ref()).await?;
+    verify(&key, &checksum, response.headers().get(S3_CHECKSUM_HEADER).as_deref())?;
+    on_progress(total, total);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::multipart_upload::*;
+
+    #[test]
+    fn test_part_ranges() {
+        assert_eq!(part_ranges(10, 4), vec![(1, 0..4), (2, 4..8), (3, 8..10)]);
+        assert_eq!(part_ranges(8, 4), vec![(1, 0..4), (2, 4..8)]);
+        assert_eq!(part_ranges(0, 4), vec![]);
+    }
+
+    #[test]
+    fn test_upload_query() {
+        assert_eq!(upload_query("abc-123"), "uploadId=abc-123");
+        assert_eq!(upload_query("x+y/z=="), "uploadId=x%2By%2Fz%3D%3D");
+    }
+
+    #[test]
+    fn test_part_progress() {
+        let reported = std::cell::RefCell::new(vec![]);
+        let on_progress = |sent, total| reported.borrow_mut().push((sent, total));
+        let resumed = vec![UploadedPart { part_number: 1, e_tag: "\"a\"".to_string(), checksum: None, size: 4 }];
+        let mut progress = PartProgress::new(&resumed, 10, &on_progress);
+        progress.part_sent(4);
+        progress.part_sent(2);
+        assert_eq!(*reported.borrow(), vec![(4, 10), (8, 10), (10, 10)]);
+    }
+
+    #[test]
+    fn test_parse_initiate_result() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<InitiateMultipartUploadResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Bucket>example-bucket</Bucket>
+  <Key>images/example.jpg</Key>
+  <UploadId>VXBsb2FkIElEIGZvciA2aWWpbmcncyBteS1tb3ZpZS5tMnRzIHVwbG9hZA</UploadId>
+</InitiateMultipartUploadResult>"#;
+        let result = parse_xml::<InitiateMultipartUploadResult>(xml).unwrap();
+        assert_eq!(result.upload_id, "VXBsb2FkIElEIGZvciA2aWWpbmcncyBteS1tb3ZpZS5tMnRzIHVwbG9hZA");
+    }
+
+    #[test]
+    fn test_parse_list_parts() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListPartsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Bucket>example-bucket</Bucket>
+  <Key>images/example.jpg</Key>
+  <UploadId>XXBsb2FkIElEIGZvciBlbHZpbmcncyVcdS1tb3ZpZS5tMnRzEEEwbG9hZA</UploadId>
+  <PartNumberMarker>0</PartNumberMarker>
+  <NextPartNumberMarker>2</NextPartNumberMarker>
+  <MaxParts>1000</MaxParts>
+  <IsTruncated>true</IsTruncated>
+  <Part>
+    <PartNumber>1</PartNumber>
+    <LastModified>2010-11-10T20:48:34.000Z</LastModified>
+    <ETag>"7778aef83f66abc1fa1e8477f296d394"</ETag>
+    <Size>5242880</Size>
+  </Part>
+  <Part>
+    <PartNumber>2</PartNumber>
+    <LastModified>2010-11-10T20:48:33.000Z</LastModified>
+    <ETag>"aaaa18db4cc2f85cedef654fccc4a4x8"</ETag>
+    <ChecksumSHA256>47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=</ChecksumSHA256>
+    <Size>1024</Size>
+  </Part>
+</ListPartsResult>"#;
+        let result = parse_xml::<ListPartsResult>(xml).unwrap();
+        assert!(result.is_truncated);
+        assert_eq!(result.next_part_number_marker, Some(2));
+        assert_eq!(result.parts.len(), 2);
+        assert_eq!(result.parts[0], UploadedPart {
+            part_number: 1,
+            e_tag: "\"7778aef83f66abc1fa1e8477f296d394\"".to_string(),
+            checksum: None,
+            size: 5242880,
+        });
+        assert_eq!(result.parts[1].checksum.as_deref(), Some("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="));
+    }
+
+    #[test]
+    fn test_resumable_parts() {
+        let body = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let body_parts = part_ranges(body.len(), 4).into_iter()
+            .map(|(n, r)| (n, r.clone(), sha256(&body[r])))
+            .collect::<Vec<BodyPart>>();
+        let part = |part_number: u32, checksum: String, size: usize| UploadedPart { part_number, e_tag: format!("\"{}\"", part_number), checksum: Some(checksum), size };
+        let listed = vec![
+            part(1, sha256(&body[0..4]), 4),
+            // a part of another body
+            part(2, sha256(&[0, 0, 0, 0]), 4),
+            // a part of a different size
+            part(3, sha256(&body[8..10]), 4),
+        ];
+        assert_eq!(resumable_parts(&body_parts, listed), vec![part(1, sha256(&body[0..4]), 4)]);
+    }
+
+    #[test]
+    fn test_complete_multipart_body() {
+        let parts = vec![
+            UploadedPart { part_number: 1, e_tag: "\"a54357aff0632cce46d942af68356b38\"".to_string(), checksum: Some("ZLz9CFSFw0KNJjVX2gm7GSvRcjO2DhD7nlESZtrJo1U=".to_string()), size: 5242880 },
+            UploadedPart { part_number: 2, e_tag: "\"0c78aef83f66abc1fa1e8477f296d394\"".to_string(), checksum: None, size: 10 },
+        ];
+        assert_eq!(
+            complete_multipart_body(&parts),
+            "<CompleteMultipartUpload><Part><PartNumber>1</PartNumber><ETag>\"a54357aff0632cce46d942af68356b38\"</ETag><ChecksumSHA256>ZLz9CFSFw0KNJjVX2gm7GSvRcjO2DhD7nlESZtrJo1U=</ChecksumSHA256></Part><Part><PartNumber>2</PartNumber><ETag>\"0c78aef83f66abc1fa1e8477f296d394\"</ETag></Part></CompleteMultipartUpload>"
+        );
+    }
+}
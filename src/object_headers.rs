@@ -0,0 +1,139 @@
+use crate::api::Error;
+use crate::manifest::{update_manifest, MANIFEST_KEY};
+use crate::object_store::{list_all, ObjectStore};
+use crate::ImageInfo;
+
+/// How long images are cached unless the site's config sets another `Cache-Control`, see
+/// `SiteConfig::image_cache_control`. Replaced images can be shown from caches until it expires.
+pub const DEFAULT_IMAGE_CACHE_CONTROL: &str = "public, max-age=86400";
+
+// Captions, albums and settings are revalidated, so edits are seen as soon as they are saved.
+const DOCUMENT_CACHE_CONTROL: &str = "no-cache";
+
+/// Enough of an object's first bytes for `content_type` to recognize an image.
+pub const SNIFFED_BYTES: usize = 64;
+
+// The prefixes of the objects `fix_site_headers` stores again, the manifest is rewritten after them.
+const FIXED_PREFIXES: [&str; 4] = ["images/", "comments/", "albums/", "admin/"];
+
+/// The MIME type of `body`, recognized from its bytes when it is an image and otherwise from
+/// `key`. Captions are JSON documents, see `Caption::to_bytes`.
+pub fn content_type(key: &str, body: &[u8]) -> &'static str {
+    if let Ok(format) = image::guess_format(body) {
+        return format.to_mime_type();
+    }
+    if key.starts_with("comments/") {
+        return "application/json";
+    }
+    let extension = key.rsplit_once('.').map(|(_, extension)| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("json") => "application/json",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Shows the object in the browser when it is opened, and saves it with its own name. Names
+/// which are not ASCII are sent encoded as `filename*`, with an ASCII stand-in as `filename`.
+pub fn content_disposition(key: &str) -> String {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let ascii = name.chars()
+        .map(|c| match c.is_ascii_graphic() || c == ' ' {
+            true if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    match ascii == name {
+        true => format!("inline; filename=\"{}\"", name),
+        false => format!("inline; filename=\"{}\"; filename*=UTF-8''{}", ascii, urlencoding::encode(name)),
+    }
+}
+
+/// The `Content-Type`, `Cache-Control` and `Content-Disposition` the object at `key` is stored
+/// with, `body` is the object or at least its first `SNIFFED_BYTES`.
+pub fn object_headers(key: &str, body: &[u8], image_cache_control: &str) -> Vec<(String, String)> {
+    let content_type = content_type(key, body);
+    let cache_control = match content_type.starts_with("image/") {
+        true => image_cache_control,
+        false => DOCUMENT_CACHE_CONTROL,
+    };
+    vec![
+        ("Content-Type".to_string(), content_type.to_string()),
+        ("Cache-Control".to_string(), cache_control.to_string()),
+        ("Content-Disposition".to_string(), content_disposition(key)),
+    ]
+}
+
+/// Stores every image, caption, album and settings object again with the headers it is given
+/// when it is uploaded now, see `ObjectStore::fix_headers`, reporting the objects fixed so far.
+/// Returns the number of objects fixed.
+pub async fn fix_site_headers(store: &dyn ObjectStore, on_progress: &dyn Fn(usize, usize)) -> Result<usize, Error> {
+    let mut keys = vec![];
+    for prefix in FIXED_PREFIXES {
+        keys.extend(list_all(store, prefix).await?.into_iter().map(|object| object.key()).filter(|key| key != MANIFEST_KEY));
+    }
+    let total = keys.len();
+    // the manifest lists images with their ETags, which change when their headers do
+    let mut image_e_tags = vec![];
+    for (fixed, key) in keys.into_iter().enumerate() {
+        let e_tag = store.fix_headers(&key).await?;
+        if key.starts_with("images/") {
+            image_e_tags.push((key, e_tag));
+        }
+        on_progress(fixed + 1, total);
+    }
+    update_manifest(store, |manifest| {
+        for (key, e_tag) in &image_e_tags {
+            manifest.set_e_tag(key, e_tag);
+        }
+    }).await?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::object_headers::*;
+    use crate::manifest::{load_gallery, record_image};
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::block_on;
+
+    const PNG: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(content_type("images/photo.jpg", &PNG), "image/png");
+        assert_eq!(content_type("images/photo.JPG", &[1, 2]), "image/jpeg");
+        assert_eq!(content_type("comments/a-id", b"{}"), "application/json");
+        assert_eq!(content_type("admin/settings.json", b"{}"), "application/json");
+        assert_eq!(content_type("images/notes", &[1, 2]), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_object_headers() {
+        assert_eq!(object_headers("images/beach day.png", &PNG, DEFAULT_IMAGE_CACHE_CONTROL), vec![
+            ("Content-Type".to_string(), "image/png".to_string()),
+            ("Cache-Control".to_string(), "public, max-age=86400".to_string()),
+            ("Content-Disposition".to_string(), "inline; filename=\"beach day.png\"".to_string()),
+        ]);
+        assert_eq!(object_headers("comments/a-id", b"{}", "max-age=60")[1].1, "no-cache");
+        assert_eq!(content_disposition("images/bl\u{e5}b\u{e6}r \"1\".jpg"), "inline; filename=\"bl_b_r _1_.jpg\"; filename*=UTF-8''bl%C3%A5b%C3%A6r%20%221%22.jpg");
+    }
+
+    #[test]
+    fn test_fix_site_headers() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            record_image(&store, "images/a.jpg", None, "2023-10-12T14:48:09.000Z".to_string()).await.unwrap();
+            store.put("comments/a-id", b"{}".to_vec()).await.unwrap();
+            let progress = std::cell::Cell::new((0, 0));
+            assert_eq!(fix_site_headers(&store, &|fixed, total| progress.set((fixed, total))).await.unwrap(), 2);
+            assert_eq!(progress.get(), (2, 2));
+            let e_tag = store.head("images/a.jpg").await.unwrap().e_tag.unwrap();
+            assert_eq!(load_gallery(&store, true).await.unwrap()[0].e_tag(), e_tag);
+        });
+    }
+}
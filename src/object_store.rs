@@ -0,0 +1,389 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use js_sys::{Array, Uint8Array};
+use leptos::*;
+use sha2::{Digest, Sha256};
+use web_sys::{AbortSignal, Blob, BlobPropertyBag, Url};
+
+use crate::api::Error;
+use crate::batch_delete::DeleteOutcome;
+use crate::local_config::{SiteConfig, SiteKind};
+use crate::local_store::LocalStore;
+use crate::manifest::MANIFEST_KEY;
+use crate::s3_store::S3Store;
+use crate::webdav_store::WebDavStore;
+use crate::{ImageInfo, S3ObjectInfo};
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + 'a>>;
+
+/// One page of a listing, `next_token` continues it.
+pub struct ObjectPage {
+    pub objects: Vec<S3ObjectInfo>,
+    pub next_token: Option<String>,
+}
+
+/// What is known about an object without fetching it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjectHead {
+    pub e_tag: Option<String>,
+    pub size: Option<u64>,
+    pub content_type: Option<String>,
+    // the image's stable id, see `ObjectStore::set_id`
+    pub id: Option<String>,
+    // the checksum of its body when it was uploaded, see `checksum::CHECKSUM_HEADER`
+    pub checksum: Option<String>,
+}
+
+/// A condition on a write, so it does not replace changes made since the object was read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WriteCondition {
+    // the object still has the ETag, sent as `If-Match`
+    Matches(String),
+    // there is no object yet, sent as `If-None-Match: *`
+    Absent,
+}
+
+impl WriteCondition {
+    /// The condition for replacing an object read with `e_tag`, or found missing without one.
+    pub fn for_e_tag(e_tag: Option<String>) -> Self {
+        match e_tag {
+            Some(e_tag) => Self::Matches(e_tag),
+            None => Self::Absent,
+        }
+    }
+
+    /// Whether the condition holds for an object with `e_tag`, or for a missing object.
+    pub fn holds(&self, e_tag: Option<&str>) -> bool {
+        match (self, e_tag) {
+            (Self::Matches(expected), Some(e_tag)) => expected == e_tag,
+            (Self::Absent, None) => true,
+            _ => false,
+        }
+    }
+
+    /// The request header which sends the condition.
+    pub fn header(&self) -> (&'static str, String) {
+        match self {
+            Self::Matches(e_tag) => ("If-Match", e_tag.clone()),
+            Self::Absent => ("If-None-Match", "*".to_string()),
+        }
+    }
+}
+
+/// A version of an object, kept by stores with versioning such as a bucket with S3 versioning
+/// enabled. A bucket without it lists each object once, with the version id "null".
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectVersion {
+    pub key: String,
+    pub version_id: String,
+    pub last_modified: String,
+    pub e_tag: Option<String>,
+    pub size: u64,
+    // the object's current version
+    pub is_latest: bool,
+    // deleting the object adds a delete marker, which hides its versions without removing them
+    pub is_delete_marker: bool,
+}
+
+/// Where a site's images, captions and settings are kept. Keys and ETags follow S3's conventions,
+/// so ETags are quoted and a copy keeps the ETag of a single part object.
+pub trait ObjectStore {
+    /// Objects whose key starts with, but is not, `prefix`, in key order.
+    fn list_page<'a>(&'a self, prefix: &'a str, continuation: Option<String>) -> StoreFuture<'a, ObjectPage>;
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>>;
+    fn head<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ObjectHead>;
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> StoreFuture<'a, ()>;
+    /// The object with its ETag, which a later `put_if` can be conditional on.
+    fn get_with_e_tag<'a>(&'a self, key: &'a str) -> StoreFuture<'a, (Vec<u8>, String)>;
+    /// Stores `body` at `key` only while `condition` holds, failing with `PreconditionFailed` when
+    /// it does not. Returns the ETag of the stored object.
+    fn put_if<'a>(&'a self, key: &'a str, body: Vec<u8>, condition: &'a WriteCondition) -> StoreFuture<'a, String>;
+    /// Copies within the store, only if `from` still has the ETag `if_match` when given.
+    /// Returns the ETag of the copy.
+    fn copy<'a>(&'a self, from: &'a str, to: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, String>;
+    /// Deletes `key`, only if it still has the ETag `if_match` when given.
+    fn delete<'a>(&'a self, key: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, ()>;
+    /// Stores `id` with the object as its stable id, which `head` returns and copies keep, S3 keeps it
+    /// as `x-amz-meta-weblum-id`. Returns the object's ETag, which can change.
+    fn set_id<'a>(&'a self, key: &'a str, id: &'a str) -> StoreFuture<'a, String>;
+
+    /// Stores the object at `key` again with the `Content-Type`, `Cache-Control` and
+    /// `Content-Disposition` of `object_headers`, keeping its id. Returns the object's ETag, which
+    /// can change. Stores which keep no headers leave the object as it is.
+    fn fix_headers<'a>(&'a self, key: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move { self.head(key).await?.e_tag.ok_or(Error::Fetch(format!("no ETag for {}", key))) })
+    }
+
+    /// Moves `from` to `to`, the original is only removed once the copy exists. Stores with a move
+    /// of their own override this.
+    fn move_object<'a>(&'a self, from: &'a str, to: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.copy(from, to, None).await?;
+            self.delete(from, None).await
+        })
+    }
+
+    /// Stores `body` at `key` with the stable id `id`, reporting the bytes sent so far. Stores which
+    /// send in parts override this.
+    fn upload<'a>(&'a self, key: &'a str, body: Vec<u8>, id: &'a str, on_progress: &'a dyn Fn(usize, usize), _abort: Option<AbortSignal>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let total = body.len();
+            self.put(key, body).await?;
+            self.set_id(key, id).await?;
+            on_progress(total, total);
+            Ok(())
+        })
+    }
+
+    /// A URL the browser can show the object with. By default the object is read into a blob URL,
+    /// which lasts until it is revoked.
+    fn display_url<'a>(&'a self, key: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move { blob_url(key, &self.get(key).await?) })
+    }
+
+    /// The versions of the objects whose key starts with `prefix`, each key's newest first. Stores
+    /// which keep versions override this, others have none.
+    fn list_versions<'a>(&'a self, _prefix: &'a str) -> StoreFuture<'a, Vec<ObjectVersion>> {
+        Box::pin(async move { Ok(vec![]) })
+    }
+
+    /// The body of a version of `key`, see `list_versions`.
+    fn get_version<'a>(&'a self, _key: &'a str, _version_id: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move { Err(Error::NoSuchKey) })
+    }
+
+    /// Makes a version of `key` current again, as a new version copied from it with its metadata,
+    /// so the versions after it are kept. Returns the ETag of the new version.
+    fn restore_version<'a>(&'a self, _key: &'a str, _version_id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move { Err(Error::NoSuchKey) })
+    }
+
+    /// A URL the browser can show a version of `key` with, a blob URL as for `display_url`.
+    fn version_display_url<'a>(&'a self, key: &'a str, version_id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move { blob_url(key, &self.get_version(key, version_id).await?) })
+    }
+
+    /// Deletes each of `keys`, stores with a bulk delete override this.
+    fn delete_many<'a>(&'a self, keys: Vec<String>) -> Pin<Box<dyn Future<Output = Vec<DeleteOutcome>> + 'a>> {
+        Box::pin(async move {
+            let mut outcomes = vec![];
+            for key in keys {
+                let error = self.delete(&key, None).await.err().map(|err| format!("{}", err));
+                outcomes.push(DeleteOutcome { key, error });
+            }
+            outcomes
+        })
+    }
+}
+
+// Shows `body` with a blob URL, which lasts until it is revoked.
+fn blob_url(key: &str, body: &[u8]) -> Result<String, Error> {
+    let content_type = image::guess_format(body).map(|format| format.to_mime_type()).unwrap_or("application/octet-stream");
+    let bytes = Uint8Array::new_with_length(body.len() as u32);
+    bytes.copy_from(body);
+    let options = BlobPropertyBag::new();
+    options.set_type(content_type);
+    Blob::new_with_u8_array_sequence_and_options(&Array::of1(&bytes), &options)
+        .and_then(|blob| Url::create_object_url_with_blob(&blob))
+        .map_err(|_| Error::Fetch(format!("unable to show {}", key)))
+}
+
+/// The ETag of a stored body for stores which make their own. S3's ETag of a single part upload is
+/// the quoted MD5 of the body, a SHA-256 prefix stands in for it.
+pub fn content_e_tag(body: &[u8]) -> String {
+    format!("\"{}\"", &hex::encode(Sha256::digest(body))[..32])
+}
+
+/// Every object under `prefix`, most recently modified first.
+pub async fn list_all(store: &dyn ObjectStore, prefix: &str) -> Result<Vec<S3ObjectInfo>, Error> {
+    let mut contents: Vec<S3ObjectInfo> = vec![];
+    let mut continuation = None;
+    loop {
+        let mut page = store.list_page(prefix, continuation).await?;
+        contents.append(&mut page.objects);
+        match page.next_token {
+            Some(token) => continuation = Some(token),
+            None => break,
+        }
+    }
+    contents.sort_by_key(|b| std::cmp::Reverse(b.last_modified()));
+    Ok(contents)
+}
+
+/// Copies every object of `from` into `to`, e.g. to publish a local site to a bucket. Images keep
+/// their ids, so captions keyed by them are copied as they are. Captions keyed by an ETag, from
+/// before images had ids, are copied to the ETag the image has in `to`. Returns the number of
+/// objects copied.
+pub async fn copy_site(from: &dyn ObjectStore, to: &dyn ObjectStore, on_progress: &dyn Fn(usize, usize)) -> Result<usize, Error> {
+    // the manifest records `from`'s ETags, `to` builds its own
+    let objects = list_all(from, "").await?.into_iter().filter(|object| object.key() != MANIFEST_KEY).collect::<Vec<S3ObjectInfo>>();
+    let (captions, others): (Vec<S3ObjectInfo>, Vec<S3ObjectInfo>) = objects.into_iter().partition(|object| object.key().starts_with("comments/"));
+    let total = captions.len() + others.len();
+    // caption names in `from` and the names they have in `to`
+    let mut caption_names = std::collections::HashMap::new();
+    for (copied, object) in others.iter().enumerate() {
+        let key = object.key();
+        to.put(&key, from.get(&key).await?).await?;
+        let e_tag = match from.head(&key).await?.id {
+            Some(id) => {
+                caption_names.insert(id.clone(), id.clone());
+                Some(to.set_id(&key, &id).await?)
+            },
+            None => to.head(&key).await?.e_tag,
+        };
+        if let Some(e_tag) = e_tag {
+            caption_names.insert(object.e_tag(), e_tag);
+        }
+        on_progress(copied + 1, total);
+    }
+    for (copied, caption) in captions.iter().enumerate() {
+        let name = caption.key().replacen("comments/", "", 1);
+        // a caption without an image is left behind
+        if let Some(to_name) = caption_names.get(&name) {
+            to.put(&format!("comments/{}", to_name), from.get(&caption.key()).await?).await?;
+        }
+        on_progress(others.len() + copied + 1, total);
+    }
+    Ok(total)
+}
+
+/// The store for `config`, an S3 store when there is no config.
+pub fn site_store(config: Option<SiteConfig>) -> Rc<dyn ObjectStore> {
+    match config {
+        Some(config) => match config.kind {
+            SiteKind::Local => Rc::new(LocalStore::new(config.id)),
+            SiteKind::WebDav => Rc::new(WebDavStore::new(config)),
+            SiteKind::S3 => Rc::new(S3Store::new(Some(config))),
+        },
+        None => Rc::new(S3Store::new(None)),
+    }
+}
+
+#[derive(Clone)]
+struct ObjectStoreContext(Rc<dyn ObjectStore>);
+
+pub fn provide_object_store(store: Rc<dyn ObjectStore>) {
+    provide_context(ObjectStoreContext(store));
+}
+
+/// The store provided by the App, for use while a component is created.
+pub fn use_object_store() -> StoredValue<Rc<dyn ObjectStore>> {
+    store_value(expect_context::<ObjectStoreContext>().0)
+}
+
+/// The URL `key` is shown with, empty until the store has provided it. Blob URLs are revoked
+/// when the calling component is removed.
+pub fn use_display_url(store: StoredValue<Rc<dyn ObjectStore>>, key: String) -> ReadSignal<String> {
+    use_version_display_url(store, key, None)
+}
+
+/// The URL a version of `key` is shown with, or its current version without `version_id`, see
+/// `use_display_url`.
+pub fn use_version_display_url(store: StoredValue<Rc<dyn ObjectStore>>, key: String, version_id: Option<String>) -> ReadSignal<String> {
+    let (url, set_url) = create_signal(String::new());
+    spawn_local(async move {
+        let store = store.get_value();
+        let result = match &version_id {
+            Some(version_id) => store.version_display_url(&key, version_id).await,
+            None => store.display_url(&key).await,
+        };
+        match result {
+            Ok(u) => set_url.set(u),
+            Err(err) => log::info!("unable to show {}: {}", key, err),
+        }
+    });
+    on_cleanup(move || {
+        let u = url.get_untracked();
+        if u.starts_with("blob:") {
+            let _ = Url::revoke_object_url(&u);
+        }
+    });
+    url
+}
+
+// Drives a future which never waits, as the futures of the in memory store.
+#[cfg(test)]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, Waker};
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ImageInfo;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::{block_on, copy_site, list_all, ObjectStore};
+
+    #[test]
+    fn test_list_all_pages() {
+        let store = MemoryStore::new();
+        block_on(async {
+            for i in 0..1005 {
+                store.put(&format!("images/{:04}.jpg", i), vec![1]).await.unwrap();
+            }
+            store.put("comments/\"abc\"", vec![1]).await.unwrap();
+            let first_page = store.list_page("images/", None).await.unwrap();
+            assert_eq!(first_page.objects.len(), 1000);
+            assert!(first_page.next_token.is_some());
+
+            let all = list_all(&store, "images/").await.unwrap();
+            assert_eq!(all.len(), 1005);
+            // most recently modified first
+            assert_eq!(all[0].key(), "images/1004.jpg");
+        });
+    }
+
+    #[test]
+    fn test_copy_site_moves_captions() {
+        let from = MemoryStore::new();
+        let to = MemoryStore::new();
+        block_on(async {
+            from.put("images/a.jpg", vec![1]).await.unwrap();
+            from.put("admin/settings.json", b"{}".to_vec()).await.unwrap();
+            let e_tag = from.head("images/a.jpg").await.unwrap().e_tag.unwrap();
+            from.put(&format!("comments/{}", e_tag), b"a caption".to_vec()).await.unwrap();
+            from.put("comments/\"orphan\"", b"lost".to_vec()).await.unwrap();
+            from.put("images/b.jpg", vec![2]).await.unwrap();
+            from.set_id("images/b.jpg", "b-id").await.unwrap();
+            from.put("comments/b-id", b"b caption".to_vec()).await.unwrap();
+
+            assert_eq!(copy_site(&from, &to, &|_, _| ()).await.unwrap(), 6);
+            assert_eq!(to.get("images/a.jpg").await.unwrap(), vec![1]);
+            assert_eq!(to.get("admin/settings.json").await.unwrap(), b"{}".to_vec());
+            assert_eq!(to.get(&format!("comments/{}", e_tag)).await.unwrap(), b"a caption".to_vec());
+            assert!(to.get("comments/\"orphan\"").await.is_err());
+            assert_eq!(to.head("images/b.jpg").await.unwrap().id, Some("b-id".to_string()));
+            assert_eq!(to.get("comments/b-id").await.unwrap(), b"b caption".to_vec());
+        });
+    }
+
+    #[test]
+    fn test_default_move_object() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("inbox/a.jpg", vec![1]).await.unwrap();
+            store.move_object("inbox/a.jpg", "images/a.jpg").await.unwrap();
+            assert!(store.get("inbox/a.jpg").await.is_err());
+            assert_eq!(store.get("images/a.jpg").await.unwrap(), vec![1]);
+        });
+    }
+
+    #[test]
+    fn test_default_delete_many() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            let outcomes = store.delete_many(vec!["images/a.jpg".to_string()]).await;
+            assert_eq!(outcomes[0].error, None);
+            assert!(store.get("images/a.jpg").await.is_err());
+        });
+    }
+}
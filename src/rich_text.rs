@@ -0,0 +1,199 @@
+use quick_xml::escape::escape;
+
+// the elements the caption editor makes, with the rest of its starter kit's marks and blocks
+const ALLOWED_TAGS: &[&str] = &[
+    "p", "br", "strong", "b", "em", "i", "s", "u", "code", "pre", "blockquote", "ul", "ol", "li",
+    "h1", "h2", "h3", "h4", "h5", "h6", "hr", "mark", "a",
+];
+// dropped with everything inside them
+const DROPPED_TAGS: &[&str] = &["script", "style", "iframe", "object", "embed", "template", "noscript", "textarea", "title", "svg", "math"];
+const VOID_TAGS: &[&str] = &["br", "hr"];
+const LINK_SCHEMES: &[&str] = &["http://", "https://", "mailto:"];
+
+/// Plain text as rich text, a paragraph for each line.
+pub fn text_html(text: &str) -> String {
+    match text.trim().is_empty() {
+        true => String::new(),
+        false => text.lines().map(|line| format!("<p>{}</p>", escape(line))).collect(),
+    }
+}
+
+/// Rich text keeping only the allow listed elements, without any of their attributes except the
+/// `href` of links to web and mail addresses. Web addresses in the text become links.
+pub fn sanitize_html(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+    // the dropped element being skipped
+    let mut dropping: Option<String> = None;
+    let mut open_links = 0;
+    while let Some(start) = rest.find('<') {
+        if dropping.is_none() {
+            push_text(&mut out, &rest[..start], open_links > 0);
+        }
+        let markup = &rest[start..];
+        if let Some(comment) = markup.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let is_tag = markup[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!' || c == '?');
+        let end = match (is_tag, markup.find('>')) {
+            (true, Some(end)) => end,
+            // a '<' which starts no tag is text, an unfinished tag is dropped
+            (false, _) => {
+                if dropping.is_none() {
+                    out.push_str("&lt;");
+                }
+                rest = &markup[1..];
+                continue;
+            },
+            (true, None) => {
+                rest = "";
+                break;
+            },
+        };
+        let tag = &markup[1..end];
+        rest = &markup[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let tag = tag.trim_start_matches('/');
+        let name_end = tag.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        let attributes = &tag[name_end..];
+
+        if let Some(dropped) = &dropping {
+            if closing && *dropped == name {
+                dropping = None;
+            }
+            continue;
+        }
+        if DROPPED_TAGS.contains(&name.as_str()) {
+            if !closing && !attributes.trim_end().ends_with('/') {
+                dropping = Some(name);
+            }
+            continue;
+        }
+        if !ALLOWED_TAGS.contains(&name.as_str()) {
+            continue;
+        }
+        match (name.as_str(), closing) {
+            (_, true) if VOID_TAGS.contains(&name.as_str()) => (),
+            ("a", true) => {
+                if open_links > 0 {
+                    open_links -= 1;
+                    out.push_str("</a>");
+                }
+            },
+            ("a", false) => {
+                open_links += 1;
+                match attribute(attributes, "href").filter(|href| is_safe_link(href)) {
+                    Some(href) => push_link(&mut out, href.trim()),
+                    None => out.push_str("<a>"),
+                }
+            },
+            (_, true) => out.push_str(&format!("</{}>", name)),
+            (_, false) => out.push_str(&format!("<{}>", name)),
+        }
+    }
+    if dropping.is_none() {
+        push_text(&mut out, rest, open_links > 0);
+    }
+    out
+}
+
+/// Links are kept to web and mail addresses, other schemes such as `javascript:` are dropped.
+pub fn is_safe_link(href: &str) -> bool {
+    let href = href.trim().to_ascii_lowercase();
+    LINK_SCHEMES.iter().any(|scheme| href.starts_with(scheme))
+}
+
+fn push_link(out: &mut String, href: &str) {
+    out.push_str(&format!("<a href=\"{}\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">", href.replace('"', "&quot;")));
+}
+
+// Text between tags, which has no '<'. Web addresses become links unless already in one.
+fn push_text(out: &mut String, text: &str, in_link: bool) {
+    let text = text.replace('>', "&gt;");
+    let mut rest = text.as_str();
+    if !in_link {
+        while let Some(start) = ["https://", "http://"].iter().filter_map(|scheme| rest.find(scheme)).min() {
+            let length = rest[start..].find(|c: char| c.is_whitespace() || c == '"').unwrap_or(rest.len() - start);
+            let url = rest[start..start + length].trim_end_matches(|c: char| ".,;:!?)'".contains(c));
+            out.push_str(&rest[..start]);
+            push_link(out, url);
+            out.push_str(url);
+            out.push_str("</a>");
+            rest = &rest[start + url.len()..];
+        }
+    }
+    out.push_str(rest);
+}
+
+// The value of the attribute `name`, from a tag's attributes, e.g. ` href="https://example.com"`.
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest.find(|c: char| c == '=' || c == '/' || c.is_whitespace()).unwrap_or(rest.len());
+        let attribute_name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(quoted) => {
+                let quoted = quoted.trim_start();
+                let (value, remaining) = match quoted.chars().next() {
+                    Some(quote) if quote == '"' || quote == '\'' => match quoted[1..].find(quote) {
+                        Some(end) => (&quoted[1..end + 1], &quoted[end + 2..]),
+                        None => (&quoted[1..], ""),
+                    },
+                    _ => {
+                        let end = quoted.find(char::is_whitespace).unwrap_or(quoted.len());
+                        (&quoted[..end], &quoted[end..])
+                    },
+                };
+                rest = remaining;
+                Some(value)
+            },
+            None => None,
+        };
+        if attribute_name.eq_ignore_ascii_case(name) {
+            return value;
+        }
+        rest = rest.trim_start_matches(|c: char| c == '/' || c.is_whitespace());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rich_text::*;
+
+    #[test]
+    fn test_text_html() {
+        assert_eq!(text_html("a < b\nc & d"), "<p>a &lt; b</p><p>c &amp; d</p>");
+        assert_eq!(text_html(" "), "");
+    }
+
+    #[test]
+    fn test_editor_html_is_kept() {
+        let html = "<p><strong>Bold</strong> and <em>italic</em></p><ul><li><p>one</p></li></ul><ol><li><p>two</p></li></ol><p>a<br>b &amp; c</p>";
+        assert_eq!(sanitize_html(html), html);
+    }
+
+    #[test]
+    fn test_sanitize_html() {
+        assert_eq!(sanitize_html("<p onclick=\"alert(1)\">hi<script>alert(2)</script></p>"), "<p>hi</p>");
+        assert_eq!(sanitize_html("<img src=x onerror=alert(1)><div><b>x</b></div>"), "<b>x</b>");
+        assert_eq!(sanitize_html("<style>p { color: red }</style><!-- <script> -->a"), "a");
+        assert_eq!(sanitize_html("<a href=\"javascript:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(sanitize_html("<a href='https://example.com/a?b=1&amp;c=2' title=t>x</a>"),
+            "<a href=\"https://example.com/a?b=1&amp;c=2\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">x</a>");
+        assert_eq!(sanitize_html("1 < 2 > 0<p"), "1 &lt; 2 &gt; 0");
+        assert_eq!(sanitize_html("</a></p></br>"), "</p>");
+    }
+
+    #[test]
+    fn test_addresses_become_links() {
+        assert_eq!(sanitize_html("<p>See https://example.com/x.</p>"),
+            "<p>See <a href=\"https://example.com/x\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">https://example.com/x</a>.</p>");
+        assert_eq!(sanitize_html("<a href=\"https://example.com\">https://example.com</a>"),
+            "<a href=\"https://example.com\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">https://example.com</a>");
+    }
+}
@@ -0,0 +1,115 @@
+use serde::Deserialize;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+use crate::api::Error;
+
+// Delay before the first retry, doubled for each attempt after that.
+const BASE_RETRY_DELAY_MS: u32 = 250;
+
+/// The body S3 sends with a failed request, e.g.
+/// `<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>`
+#[derive(Deserialize)]
+struct S3ErrorBody {
+    #[serde(rename(deserialize = "Code"))]
+    code: String,
+    #[serde(rename(deserialize = "Message"), default)]
+    message: String,
+    #[serde(rename(deserialize = "BucketName"), default)]
+    bucket_name: Option<String>,
+    #[serde(rename(deserialize = "ServerTime"), default)]
+    server_time: Option<String>,
+}
+
+// S3's clock as unix milliseconds, sent with RequestTimeTooSkewed.
+fn server_time_millis(server_time: &str) -> Option<i64> {
+    let time = OffsetDateTime::parse(server_time, &Rfc3339).ok()?;
+    Some((time.unix_timestamp_nanos() / 1_000_000) as i64)
+}
+
+/// Maps a failed response to an `Error`, bodies that are not S3 errors keep their status.
+pub fn error_from_response(status: u16, body: &str) -> Error {
+    match quick_xml::de::from_str::<S3ErrorBody>(body) {
+        Ok(e) => match e.code.as_str() {
+            "AccessDenied" => Error::AccessDenied(e.message),
+            "NoSuchBucket" => Error::NoSuchBucket(e.bucket_name.unwrap_or_default()),
+            "NoSuchKey" => Error::NoSuchKey,
+            "SlowDown" => Error::SlowDown,
+            "RequestTimeTooSkewed" => Error::RequestTimeTooSkewed(e.server_time.as_deref().and_then(server_time_millis)),
+            "ExpiredToken" => Error::ExpiredToken,
+            // a conditional write raced another write to the object
+            "PreconditionFailed" | "ConditionalRequestConflict" => Error::PreconditionFailed,
+            // the body S3 received does not match the checksum sent with it
+            "BadDigest" => Error::ChecksumMismatch,
+            _ => Error::S3 { status, code: e.code, message: e.message },
+        },
+        Err(_) => Error::Status(status, body.to_string()),
+    }
+}
+
+/// Exponential backoff with jitter, `random` is in [0, 1).
+pub fn retry_delay_ms(attempt: u32, random: f64) -> u32 {
+    let delay = BASE_RETRY_DELAY_MS * 2u32.pow(attempt.saturating_sub(1));
+    delay + (delay as f64 * random) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Error;
+    use crate::s3_error::{error_from_response, retry_delay_ms};
+
+    #[test]
+    fn test_access_denied() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error><Code>AccessDenied</Code><Message>Access Denied</Message><RequestId>656c76696e6727732072657175657374</RequestId><HostId>Uuag1LuByRx9e6j5Onimru9pO4ZVKnJ2Qz7/C1NPcfTWAtRPfTaOFg==</HostId></Error>"#;
+        let err = error_from_response(403, body);
+        assert!(matches!(err, Error::AccessDenied(ref message) if message == "Access Denied"));
+        assert!(!err.is_transient());
+    }
+
+    #[test]
+    fn test_no_such_bucket() {
+        let body = "<Error><Code>NoSuchBucket</Code><Message>The specified bucket does not exist</Message><BucketName>example.weblum.photos</BucketName></Error>";
+        assert!(matches!(error_from_response(404, body), Error::NoSuchBucket(ref bucket) if bucket == "example.weblum.photos"));
+    }
+
+    #[test]
+    fn test_request_time_too_skewed() {
+        let body = "<Error><Code>RequestTimeTooSkewed</Code><Message>The difference between the request time and the current time is too large.</Message><RequestTime>20231012T144809Z</RequestTime><ServerTime>2023-10-12T15:48:09Z</ServerTime><MaxAllowedSkewMilliseconds>900000</MaxAllowedSkewMilliseconds></Error>";
+        let err = error_from_response(403, body);
+        assert!(matches!(err, Error::RequestTimeTooSkewed(Some(1697125689000))));
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    fn test_transient_errors() {
+        assert!(error_from_response(503, "<Error><Code>SlowDown</Code><Message>Reduce your request rate.</Message></Error>").is_transient());
+        assert!(error_from_response(500, "<Error><Code>InternalError</Code><Message>We encountered an internal error.</Message></Error>").is_transient());
+        assert!(!error_from_response(400, "<Error><Code>ExpiredToken</Code><Message>The provided token has expired.</Message></Error>").is_transient());
+    }
+
+    #[test]
+    fn test_write_conflicts() {
+        assert!(matches!(error_from_response(412, "<Error><Code>PreconditionFailed</Code><Message>At least one of the pre-conditions you specified did not hold</Message></Error>"), Error::PreconditionFailed));
+        assert!(matches!(error_from_response(409, "<Error><Code>ConditionalRequestConflict</Code><Message>A conflicting conditional operation is currently in progress against this resource.</Message></Error>"), Error::PreconditionFailed));
+    }
+
+    #[test]
+    fn test_bad_digest() {
+        let err = error_from_response(400, "<Error><Code>BadDigest</Code><Message>The SHA256 you specified did not match the calculated checksum.</Message></Error>");
+        assert!(matches!(err, Error::ChecksumMismatch));
+        assert!(!err.is_transient());
+    }
+
+    #[test]
+    fn test_non_xml_body() {
+        assert!(matches!(error_from_response(502, "Bad Gateway"), Error::Status(502, _)));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay_ms(1, 0.0), 250);
+        assert_eq!(retry_delay_ms(2, 0.0), 500);
+        assert_eq!(retry_delay_ms(3, 0.5), 1500);
+    }
+}
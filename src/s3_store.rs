@@ -0,0 +1,398 @@
+use gloo_net::http::{Method, Response};
+use web_sys::AbortSignal;
+use serde::Deserialize;
+use url::form_urlencoded::byte_serialize;
+
+use crate::api::{send_site_request, site_read_url, Error};
+use crate::batch_delete::{delete_objects, DeleteOutcome};
+use crate::checksum::{sha256, verify, CHECKSUM_HEADER, S3_CHECKSUM_HEADER};
+use crate::local_config::SiteConfig;
+use crate::multipart_upload::upload_with_progress;
+use crate::object_headers::{object_headers, SNIFFED_BYTES};
+use crate::object_store::{ObjectHead, ObjectPage, ObjectStore, ObjectVersion, StoreFuture, WriteCondition};
+use crate::ListBucketResult;
+
+/// The metadata header holding an image's stable id, the bucket's CORS configuration must expose it.
+pub const ID_HEADER: &str = "x-amz-meta-weblum-id";
+
+#[derive(Deserialize)]
+struct CopyObjectResult {
+    #[serde(rename(deserialize = "ETag"))]
+    e_tag: String,
+}
+
+#[derive(Deserialize)]
+struct VersionEntry {
+    #[serde(rename(deserialize = "Key"))]
+    key: String,
+    #[serde(rename(deserialize = "VersionId"))]
+    version_id: String,
+    #[serde(rename(deserialize = "IsLatest"))]
+    is_latest: bool,
+    #[serde(rename(deserialize = "LastModified"))]
+    last_modified: String,
+    #[serde(rename(deserialize = "ETag"), default)]
+    e_tag: Option<String>,
+    #[serde(rename(deserialize = "Size"), default)]
+    size: u64,
+}
+
+// Versions and delete markers are listed in one sequence, in key order with each key's newest first.
+#[derive(Deserialize)]
+enum ListVersionsElement {
+    Version(VersionEntry),
+    DeleteMarker(VersionEntry),
+    IsTruncated(bool),
+    NextKeyMarker(String),
+    NextVersionIdMarker(String),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct ListVersionsResult {
+    #[serde(rename(deserialize = "$value"), default)]
+    elements: Vec<ListVersionsElement>,
+}
+
+// the key and version id a truncated list of versions continues after
+type VersionMarkers = (String, String);
+
+/// A page of a ListObjectVersions response, with the markers which continue it when truncated.
+fn parse_versions(xml: &str) -> Result<(Vec<ObjectVersion>, Option<VersionMarkers>), Error> {
+    let result: ListVersionsResult = quick_xml::de::from_str(xml)
+        .map_err(|err| Error::Fetch(format!("unexpected versions response: {}", err)))?;
+    let mut versions = vec![];
+    let (mut truncated, mut next_key, mut next_version) = (false, None, None);
+    for element in result.elements {
+        let (entry, is_delete_marker) = match element {
+            ListVersionsElement::Version(entry) => (entry, false),
+            ListVersionsElement::DeleteMarker(entry) => (entry, true),
+            ListVersionsElement::IsTruncated(is_truncated) => {
+                truncated = is_truncated;
+                continue;
+            },
+            ListVersionsElement::NextKeyMarker(key) => {
+                next_key = Some(key);
+                continue;
+            },
+            ListVersionsElement::NextVersionIdMarker(version_id) => {
+                next_version = Some(version_id);
+                continue;
+            },
+            ListVersionsElement::Other => continue,
+        };
+        versions.push(ObjectVersion {
+            key: entry.key,
+            version_id: entry.version_id,
+            last_modified: entry.last_modified,
+            e_tag: entry.e_tag,
+            size: entry.size,
+            is_latest: entry.is_latest,
+            is_delete_marker,
+        });
+    }
+    let next = match truncated {
+        true => next_key.zip(next_version),
+        false => None,
+    };
+    Ok((versions, next))
+}
+
+/// An `ObjectStore` for a site's bucket, on AWS or the site's custom endpoint.
+pub struct S3Store {
+    config: Option<SiteConfig>,
+}
+
+impl S3Store {
+    /// A store for `config`, without a config every request fails with `LocalConfigError`.
+    pub fn new(config: Option<SiteConfig>) -> Self {
+        Self { config }
+    }
+
+    fn config(&self) -> Result<&SiteConfig, Error> {
+        self.config.as_ref().ok_or(Error::LocalConfigError)
+    }
+
+    async fn send(&self, method: Method, key: &str, headers: &[(String, String)], body: Option<Vec<u8>>) -> Result<Response, Error> {
+        let config = self.config()?;
+        send_site_request(config, method, config.object_url(key), headers, body, None).await
+    }
+
+    // Keys in the x-amz-copy-source header are URL encoded, keeping the '/' separators.
+    fn copy_source(&self, key: &str) -> Result<String, Error> {
+        let encoded_key = key.split('/').map(|segment| urlencoding::encode(segment).into_owned()).collect::<Vec<String>>().join("/");
+        Ok(format!("/{}/{}", self.config()?.s3_bucket_name(), encoded_key))
+    }
+
+    // The headers `key` is stored with, see `object_headers`.
+    fn headers_for(&self, key: &str, body: &[u8]) -> Result<Vec<(String, String)>, Error> {
+        Ok(object_headers(key, body, self.config()?.image_cache_control()))
+    }
+
+    // The object's first bytes, enough to recognize its type, an empty object has none.
+    async fn first_bytes(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let range = ("Range".to_string(), format!("bytes=0-{}", SNIFFED_BYTES - 1));
+        match self.send(Method::GET, key, &[range], None).await {
+            Ok(response) => response.binary().await.map_err(|err| Error::Fetch(format!("{}", err))),
+            Err(Error::Status(416, _)) => Ok(vec![]),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Metadata can only be changed by copying the object onto itself, replacing its metadata. The
+    // headers it is stored with are replaced along with it, and `id` and `checksum` are kept.
+    async fn replace_metadata(&self, key: &str, id: Option<&str>, checksum: Option<&str>) -> Result<String, Error> {
+        let mut headers = vec![
+            ("x-amz-copy-source".to_string(), self.copy_source(key)?),
+            ("x-amz-metadata-directive".to_string(), "REPLACE".to_string()),
+        ];
+        headers.extend(self.headers_for(key, &self.first_bytes(key).await?)?);
+        if let Some(id) = id {
+            headers.push((ID_HEADER.to_string(), id.to_string()));
+        }
+        if let Some(checksum) = checksum {
+            headers.push((CHECKSUM_HEADER.to_string(), checksum.to_string()));
+        }
+        self.send_copy(key, &headers).await
+    }
+
+    // A copy succeeds when S3 responds with a CopyObjectResult, returns the ETag of the copy.
+    async fn send_copy(&self, to: &str, headers: &[(String, String)]) -> Result<String, Error> {
+        let response = self.send(Method::PUT, to, headers, None).await?;
+        // a copy can fail after S3 has responded with 200, the error is then in the body
+        let text = response.text().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+        match quick_xml::de::from_str::<CopyObjectResult>(&text) {
+            Ok(result) => Ok(result.e_tag),
+            Err(_) => Err(Error::Status(response.status(), text)),
+        }
+    }
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response.headers().get(name)
+}
+
+// S3 verifies the body against the checksum and records it with the object.
+fn checksum_headers(checksum: &str) -> [(String, String); 2] {
+    [
+        (S3_CHECKSUM_HEADER.to_string(), checksum.to_string()),
+        (CHECKSUM_HEADER.to_string(), checksum.to_string()),
+    ]
+}
+
+// The body of `key`, verified against the checksum it was uploaded with.
+async fn verified_body(key: &str, response: Response) -> Result<Vec<u8>, Error> {
+    let body = response.binary().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+    verify(key, &sha256(&body), header(&response, CHECKSUM_HEADER).as_deref())?;
+    Ok(body)
+}
+
+impl ObjectStore for S3Store {
+    fn list_page<'a>(&'a self, prefix: &'a str, continuation: Option<String>) -> StoreFuture<'a, ObjectPage> {
+        Box::pin(async move {
+            let config = self.config()?;
+            let encoded_prefix = byte_serialize(prefix.as_bytes()).collect::<String>();
+            let url = match continuation {
+                Some(token) => format!("{}?list-type=2&continuation-token={}&prefix={}&start-after={}",
+                    config.bucket_url(), byte_serialize(token.as_bytes()).collect::<String>(), encoded_prefix, encoded_prefix),
+                None => format!("{}?list-type=2&prefix={}&start-after={}", config.bucket_url(), encoded_prefix, encoded_prefix),
+            };
+            let response = send_site_request(config, Method::GET, url, &[], None, None).await?;
+            let xml = response.text().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+            let result: ListBucketResult = quick_xml::de::from_str(&xml)
+                .map_err(|err| Error::Fetch(format!("unexpected list response: {}", err)))?;
+            let next_token = match result.is_truncated {
+                true => result.next_continuation_token,
+                false => None,
+            };
+            Ok(ObjectPage { objects: result.contents, next_token })
+        })
+    }
+
+    /// The object, verified against the checksum it was uploaded with, see `checksum::verify`.
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let response = self.send(Method::GET, key, &[], None).await?;
+            verified_body(key, response).await
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ObjectHead> {
+        Box::pin(async move {
+            let response = self.send(Method::HEAD, key, &[], None).await?;
+            Ok(ObjectHead {
+                e_tag: header(&response, "ETag"),
+                size: header(&response, "Content-Length").and_then(|size| size.parse().ok()),
+                content_type: header(&response, "Content-Type"),
+                id: header(&response, ID_HEADER),
+                checksum: header(&response, CHECKSUM_HEADER),
+            })
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let checksum = sha256(&body);
+            let mut headers = self.headers_for(key, &body)?;
+            headers.extend(checksum_headers(&checksum));
+            let response = self.send(Method::PUT, key, &headers, Some(body)).await?;
+            verify(key, &checksum, header(&response, S3_CHECKSUM_HEADER).as_deref())
+        })
+    }
+
+    fn get_with_e_tag<'a>(&'a self, key: &'a str) -> StoreFuture<'a, (Vec<u8>, String)> {
+        Box::pin(async move {
+            let response = self.send(Method::GET, key, &[], None).await?;
+            let e_tag = header(&response, "ETag").ok_or(Error::Fetch(format!("no ETag for {}", key)))?;
+            let body = verified_body(key, response).await?;
+            Ok((body, e_tag))
+        })
+    }
+
+    /// S3 refuses a write whose `If-Match` names a missing object with NoSuchKey, which is reported
+    /// as the failed precondition it is.
+    fn put_if<'a>(&'a self, key: &'a str, body: Vec<u8>, condition: &'a WriteCondition) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let (name, value) = condition.header();
+            let checksum = sha256(&body);
+            let mut headers = self.headers_for(key, &body)?;
+            headers.extend(checksum_headers(&checksum));
+            headers.push((name.to_string(), value));
+            let response = match self.send(Method::PUT, key, &headers, Some(body)).await {
+                Err(Error::NoSuchKey) => return Err(Error::PreconditionFailed),
+                result => result?,
+            };
+            verify(key, &checksum, header(&response, S3_CHECKSUM_HEADER).as_deref())?;
+            match header(&response, "ETag") {
+                Some(e_tag) => Ok(e_tag),
+                None => self.head(key).await?.e_tag.ok_or(Error::Fetch(format!("no ETag for {}", key))),
+            }
+        })
+    }
+
+    /// A server side copy, the content type and metadata are copied with the object.
+    fn copy<'a>(&'a self, from: &'a str, to: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let mut headers = vec![
+                ("x-amz-copy-source".to_string(), self.copy_source(from)?),
+                ("x-amz-metadata-directive".to_string(), "COPY".to_string()),
+            ];
+            if let Some(e_tag) = if_match {
+                headers.push(("x-amz-copy-source-if-match".to_string(), e_tag.to_string()));
+            }
+            self.send_copy(to, &headers).await
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let headers = match if_match {
+                Some(e_tag) => vec![("If-Match".to_string(), e_tag.to_string())],
+                None => vec![],
+            };
+            self.send(Method::DELETE, key, &headers, None).await?;
+            Ok(())
+        })
+    }
+
+    fn set_id<'a>(&'a self, key: &'a str, id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let checksum = self.head(key).await?.checksum;
+            self.replace_metadata(key, Some(id), checksum.as_deref()).await
+        })
+    }
+
+    fn fix_headers<'a>(&'a self, key: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let head = self.head(key).await?;
+            self.replace_metadata(key, head.id.as_deref(), head.checksum.as_deref()).await
+        })
+    }
+
+    /// Sends large bodies in parts, see `upload_with_progress`. The id, checksum and the object's
+    /// headers are sent with its first request, so they are stored with the object.
+    fn upload<'a>(&'a self, key: &'a str, body: Vec<u8>, id: &'a str, on_progress: &'a dyn Fn(usize, usize), abort: Option<AbortSignal>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let mut headers = self.headers_for(key, &body)?;
+            headers.push((ID_HEADER.to_string(), id.to_string()));
+            headers.push((CHECKSUM_HEADER.to_string(), sha256(&body)));
+            upload_with_progress(key.to_string(), body, &headers, on_progress, abort).await
+        })
+    }
+
+    /// The object's URL, presigned when the site has credentials so private buckets can be shown.
+    fn display_url<'a>(&'a self, key: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move { Ok(site_read_url(self.config()?, key)) })
+    }
+
+    /// ListObjectVersions, a bucket without versioning lists each object's only version.
+    fn list_versions<'a>(&'a self, prefix: &'a str) -> StoreFuture<'a, Vec<ObjectVersion>> {
+        Box::pin(async move {
+            let config = self.config()?;
+            let mut versions = vec![];
+            let mut markers: Option<VersionMarkers> = None;
+            loop {
+                let mut url = format!("{}?versions&prefix={}", config.bucket_url(), urlencoding::encode(prefix));
+                if let Some((key, version_id)) = &markers {
+                    url.push_str(&format!("&key-marker={}&version-id-marker={}", urlencoding::encode(key), urlencoding::encode(version_id)));
+                }
+                let response = send_site_request(config, Method::GET, url, &[], None, None).await?;
+                let xml = response.text().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+                let (page, next) = parse_versions(&xml)?;
+                versions.extend(page);
+                match next {
+                    Some(next) => markers = Some(next),
+                    None => return Ok(versions),
+                }
+            }
+        })
+    }
+
+    fn get_version<'a>(&'a self, key: &'a str, version_id: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let config = self.config()?;
+            let url = format!("{}?versionId={}", config.object_url(key), urlencoding::encode(version_id));
+            let response = send_site_request(config, Method::GET, url, &[], None, None).await?;
+            verified_body(key, response).await
+        })
+    }
+
+    /// A server side copy of the version onto its key, which keeps the version's metadata.
+    fn restore_version<'a>(&'a self, key: &'a str, version_id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let headers = vec![
+                ("x-amz-copy-source".to_string(), format!("{}?versionId={}", self.copy_source(key)?, urlencoding::encode(version_id))),
+                ("x-amz-metadata-directive".to_string(), "COPY".to_string()),
+            ];
+            self.send_copy(key, &headers).await
+        })
+    }
+
+    fn delete_many<'a>(&'a self, keys: Vec<String>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Vec<DeleteOutcome>> + 'a>> {
+        Box::pin(async move {
+            match self.config() {
+                Ok(config) => delete_objects(config, keys).await,
+                Err(err) => keys.into_iter().map(|key| DeleteOutcome { key, error: Some(format!("{}", err)) }).collect(),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::s3_store::*;
+
+    #[test]
+    fn test_parse_versions() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListVersionsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Name>photos</Name><Prefix>images/</Prefix><KeyMarker></KeyMarker><VersionIdMarker></VersionIdMarker><NextKeyMarker>images/b.jpg</NextKeyMarker><NextVersionIdMarker>3/L4kqtJl</NextVersionIdMarker><MaxKeys>3</MaxKeys><IsTruncated>true</IsTruncated><DeleteMarker><Key>images/a.jpg</Key><VersionId>null</VersionId><IsLatest>true</IsLatest><LastModified>2023-10-13T14:48:09.000Z</LastModified><Owner><ID>75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a</ID></Owner></DeleteMarker><Version><Key>images/a.jpg</Key><VersionId>3HL4kqtJlcpXroDTDmJ+rmSpXd3dIbrHY</VersionId><IsLatest>false</IsLatest><LastModified>2023-10-12T14:48:09.000Z</LastModified><ETag>"fba9dede5f27731c9771645a39863328"</ETag><Size>434234</Size><StorageClass>STANDARD</StorageClass></Version><Version><Key>images/b.jpg</Key><VersionId>3/L4kqtJl</VersionId><IsLatest>true</IsLatest><LastModified>2023-10-11T14:48:09.000Z</LastModified><ETag>"abc"</ETag><Size>10</Size><StorageClass>STANDARD</StorageClass></Version></ListVersionsResult>"#;
+        let (versions, next) = parse_versions(xml).unwrap();
+        assert_eq!(versions.len(), 3);
+        assert!(versions[0].is_delete_marker && versions[0].is_latest && versions[0].e_tag.is_none());
+        assert_eq!(versions[1].version_id, "3HL4kqtJlcpXroDTDmJ+rmSpXd3dIbrHY");
+        assert_eq!(versions[1].size, 434234);
+        assert_eq!(versions[2].e_tag.as_deref(), Some("\"abc\""));
+        assert_eq!(next, Some(("images/b.jpg".to_string(), "3/L4kqtJl".to_string())));
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+
+use time::macros::format_description;
+use time::{Duration, OffsetDateTime};
+
+use crate::api::Error;
+use crate::batch_delete::DeleteOutcome;
+use crate::manifest::{image_id, record_image, update_manifest};
+use crate::object_store::{list_all, ObjectStore};
+use crate::ImageInfo;
+
+/// Deleted images are kept as `trash/<deleted>-<id>/images/<name>`, along with their caption as
+/// `trash/<deleted>-<id>/comments/<id>`, until they are purged.
+pub const TRASH_PREFIX: &str = "trash/";
+
+/// An image in the trash, with every object deleted with it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrashedImage {
+    // when it was deleted, see `trash_stamp`
+    pub deleted: String,
+    // the key the image is restored to, e.g. `images/a.jpg`
+    pub key: String,
+    // the trashed image and caption
+    pub trash_keys: Vec<String>,
+}
+
+impl TrashedImage {
+    pub fn trash_key(&self) -> String {
+        self.trash_keys.iter().find(|key| key.ends_with(&self.key)).cloned().unwrap_or_default()
+    }
+
+    /// When the image was deleted, e.g. `2023-10-12 14:48:09 UTC`.
+    pub fn deleted_label(&self) -> String {
+        let stamp = &self.deleted;
+        match stamp.len() == 16 {
+            true => format!("{}-{}-{} {}:{}:{} UTC", &stamp[0..4], &stamp[4..6], &stamp[6..8], &stamp[9..11], &stamp[11..13], &stamp[13..15]),
+            false => stamp.clone(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.key.replacen("images/", "", 1)
+    }
+}
+
+/// `time` as it is kept in trash keys, e.g. `20231012T144809Z`, which sorts as the time does.
+pub fn trash_stamp(time: OffsetDateTime) -> String {
+    time.format(format_description!("[year][month][day]T[hour][minute][second]Z")).unwrap_or_default()
+}
+
+// Moves the image at `key` and its caption to the trash, leaving the gallery as it is.
+async fn move_to_trash(store: &dyn ObjectStore, key: &str, deleted: OffsetDateTime) -> Result<(), Error> {
+    let (id, _) = image_id(store, key).await?;
+    let folder = format!("{}{}-{}/", TRASH_PREFIX, trash_stamp(deleted), id);
+    let caption = format!("comments/{}", id);
+    match store.move_object(&caption, &format!("{}{}", folder, caption)).await {
+        Ok(()) | Err(Error::NoSuchKey) => (),
+        Err(err) => return Err(err),
+    }
+    store.move_object(key, &format!("{}{}", folder, key)).await
+}
+
+/// Moves the image at `key` and its caption to the trash, deleted at `deleted`, and removes it
+/// from the gallery.
+pub async fn trash_image(store: &dyn ObjectStore, key: &str, deleted: OffsetDateTime) -> Result<(), Error> {
+    move_to_trash(store, key, deleted).await?;
+    update_manifest(store, |manifest| manifest.remove(key)).await
+}
+
+/// Moves the images at `keys` and their captions to the trash, returning the outcome for each
+/// image. The gallery is updated once, for every image moved.
+pub async fn trash_images(store: &dyn ObjectStore, keys: Vec<String>, deleted: OffsetDateTime) -> Vec<DeleteOutcome> {
+    let mut outcomes = vec![];
+    for key in keys {
+        let error = move_to_trash(store, &key, deleted).await.err().map(|err| format!("{}", err));
+        outcomes.push(DeleteOutcome { key, error });
+    }
+    let trashed = outcomes.iter().filter(|o| o.error.is_none()).map(|o| o.key.clone()).collect::<Vec<String>>();
+    // the images are in the trash either way, a failed update is reported against each of them
+    if let Err(err) = update_manifest(store, |manifest| trashed.iter().for_each(|key| manifest.remove(key))).await {
+        for outcome in outcomes.iter_mut().filter(|o| o.error.is_none()) {
+            outcome.error = Some(format!("moved to the trash, but the gallery could not be updated: {}", err));
+        }
+    }
+    outcomes
+}
+
+/// The images in the trash, most recently deleted first.
+pub async fn list_trash(store: &dyn ObjectStore) -> Result<Vec<TrashedImage>, Error> {
+    let mut folders: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for object in list_all(store, TRASH_PREFIX).await? {
+        let key = object.key();
+        if let Some((folder, _)) = key[TRASH_PREFIX.len()..].split_once('/') {
+            folders.entry(folder.to_string()).or_default().push(key);
+        }
+    }
+    Ok(folders.into_iter().rev().filter_map(|(folder, trash_keys)| {
+        let deleted = folder.split_once('-').map_or(folder.as_str(), |(stamp, _)| stamp).to_string();
+        let prefix = format!("{}{}/", TRASH_PREFIX, folder);
+        let key = trash_keys.iter().find_map(|key| key.strip_prefix(&prefix).filter(|key| key.starts_with("images/")))?.to_string();
+        Some(TrashedImage { deleted, key, trash_keys })
+    }).collect())
+}
+
+/// Moves `image` and its caption out of the trash, recording it in the gallery as modified at
+/// `last_modified`. An image of the same name in the gallery is not replaced.
+pub async fn restore_trashed(store: &dyn ObjectStore, image: &TrashedImage, last_modified: String) -> Result<(), Error> {
+    match store.head(&image.key).await {
+        Ok(_) => return Err(Error::Fetch(format!("'{}' is in the gallery, rename it before restoring the deleted image", image.name()))),
+        Err(Error::NoSuchKey) => (),
+        Err(err) => return Err(err),
+    }
+    // the caption is moved back first, so it is in place when the image is recorded
+    let mut trash_keys = image.trash_keys.clone();
+    trash_keys.sort_by_key(|key| key.ends_with(&image.key));
+    for trash_key in trash_keys {
+        if let Some((_, key)) = trash_key[TRASH_PREFIX.len()..].split_once('/') {
+            store.move_object(&trash_key, key).await?;
+        }
+    }
+    record_image(store, &image.key, None, last_modified).await
+}
+
+/// Deletes `image` and its caption from the trash for good.
+pub async fn purge(store: &dyn ObjectStore, image: &TrashedImage) -> Result<(), Error> {
+    let failed = store.delete_many(image.trash_keys.clone()).await.into_iter().find_map(|outcome| outcome.error);
+    match failed {
+        Some(err) => Err(Error::Fetch(err)),
+        None => Ok(()),
+    }
+}
+
+/// Purges the images deleted more than `days` days before `now`, returning how many were purged.
+pub async fn purge_older_than(store: &dyn ObjectStore, days: i64, now: OffsetDateTime) -> Result<usize, Error> {
+    let cutoff = trash_stamp(now - Duration::days(days));
+    let mut purged = 0;
+    for image in list_trash(store).await?.into_iter().filter(|image| image.deleted < cutoff) {
+        purge(store, &image).await?;
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use crate::trash::*;
+    use crate::api::update_comment;
+    use crate::caption::Caption;
+    use crate::manifest::load_gallery;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::{block_on, WriteCondition};
+
+    #[test]
+    fn test_trash_stamp() {
+        assert_eq!(trash_stamp(datetime!(2023-10-12 14:48:09 UTC)), "20231012T144809Z");
+        let image = TrashedImage { deleted: "20231012T144809Z".to_string(), key: "images/a.jpg".to_string(), trash_keys: vec![] };
+        assert_eq!(image.deleted_label(), "2023-10-12 14:48:09 UTC");
+    }
+
+    #[test]
+    fn test_trash_and_restore() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            store.set_id("images/a.jpg", "a-id").await.unwrap();
+            let caption = Caption { title: "kept".to_string(), ..Caption::default() };
+            update_comment(&store, &caption, "images/a.jpg", &WriteCondition::Absent).await.unwrap();
+
+            trash_image(&store, "images/a.jpg", datetime!(2023-10-12 14:48:09 UTC)).await.unwrap();
+            assert!(load_gallery(&store, true).await.unwrap().is_empty());
+            assert!(matches!(store.get("comments/a-id").await, Err(Error::NoSuchKey)));
+            let trashed = list_trash(&store).await.unwrap();
+            assert_eq!(trashed.len(), 1);
+            assert_eq!(trashed[0].key, "images/a.jpg");
+            assert_eq!(trashed[0].trash_key(), "trash/20231012T144809Z-a-id/images/a.jpg");
+            assert_eq!(trashed[0].trash_keys.len(), 2);
+
+            store.put("images/a.jpg", vec![2]).await.unwrap();
+            assert!(restore_trashed(&store, &trashed[0], "2023-10-13T14:48:09.000Z".to_string()).await.is_err());
+            store.delete("images/a.jpg", None).await.unwrap();
+            restore_trashed(&store, &trashed[0], "2023-10-13T14:48:09.000Z".to_string()).await.unwrap();
+            assert!(list_trash(&store).await.unwrap().is_empty());
+            let gallery = load_gallery(&store, true).await.unwrap();
+            assert_eq!(gallery[0].id(), Some("a-id".to_string()));
+            assert_eq!(gallery[0].get_caption(), Some(caption));
+        });
+    }
+
+    #[test]
+    fn test_trash_images() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            store.set_id("images/a.jpg", "a-id").await.unwrap();
+            store.put("comments/a-id", b"a caption".to_vec()).await.unwrap();
+            store.put("images/b.jpg", vec![2]).await.unwrap();
+            store.put("images/c.jpg", vec![3]).await.unwrap();
+            load_gallery(&store, true).await.unwrap();
+
+            let keys = vec!["images/a.jpg".to_string(), "images/missing.jpg".to_string(), "images/b.jpg".to_string()];
+            let outcomes = trash_images(&store, keys, datetime!(2023-10-12 14:48:09 UTC)).await;
+            assert_eq!(outcomes.iter().map(|o| o.error.is_none()).collect::<Vec<bool>>(), vec![true, false, true]);
+            assert!(matches!(store.get("comments/a-id").await, Err(Error::NoSuchKey)));
+            assert_eq!(load_gallery(&store, true).await.unwrap().iter().map(|image| image.key()).collect::<Vec<String>>(), vec!["images/c.jpg"]);
+
+            let trashed = list_trash(&store).await.unwrap();
+            assert_eq!(trashed.len(), 2);
+            let a = trashed.iter().find(|image| image.key == "images/a.jpg").unwrap();
+            assert_eq!(a.trash_key(), "trash/20231012T144809Z-a-id/images/a.jpg");
+            assert!(a.trash_keys.contains(&"trash/20231012T144809Z-a-id/comments/a-id".to_string()));
+            assert!(trashed.iter().any(|image| image.key == "images/b.jpg"));
+        });
+    }
+
+    #[test]
+    fn test_purge_older_than() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/old.jpg", vec![1]).await.unwrap();
+            store.put("images/new.jpg", vec![2]).await.unwrap();
+            trash_image(&store, "images/old.jpg", datetime!(2023-10-01 09:00:00 UTC)).await.unwrap();
+            trash_image(&store, "images/new.jpg", datetime!(2023-10-12 09:00:00 UTC)).await.unwrap();
+            assert_eq!(list_trash(&store).await.unwrap().iter().map(|image| image.name()).collect::<Vec<String>>(), vec!["new.jpg", "old.jpg"]);
+
+            assert_eq!(purge_older_than(&store, 7, datetime!(2023-10-14 09:00:00 UTC)).await.unwrap(), 1);
+            let trashed = list_trash(&store).await.unwrap();
+            assert_eq!(trashed.len(), 1);
+            assert_eq!(trashed[0].name(), "new.jpg");
+
+            purge(&store, &trashed[0]).await.unwrap();
+            assert!(list_trash(&store).await.unwrap().is_empty());
+        });
+    }
+}
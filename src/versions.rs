@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+use crate::api::Error;
+use crate::caption::Caption;
+use crate::manifest::{record_image, update_manifest};
+use crate::object_store::{ObjectStore, ObjectVersion};
+
+/// Captions are stored as `comments/<id>`, see `get_caption`.
+const CAPTION_PREFIX: &str = "comments/";
+
+/// A version shown in place of the current image, or of its caption.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VersionPreview {
+    Image(ObjectVersion),
+    Caption { version: ObjectVersion, caption: Caption },
+}
+
+impl VersionPreview {
+    pub fn version(&self) -> &ObjectVersion {
+        match self {
+            Self::Image(version) | Self::Caption { version, .. } => version,
+        }
+    }
+}
+
+/// When a version was stored, as it is listed, e.g. `2023-10-12 14:48:09 UTC (current)`.
+pub fn version_label(version: &ObjectVersion) -> String {
+    let stored = version.last_modified.get(..19).unwrap_or(&version.last_modified).replace('T', " ");
+    match version.is_latest {
+        true => format!("{} UTC (current)", stored),
+        false => format!("{} UTC", stored),
+    }
+}
+
+/// The versions of the object at `key`, newest first, the current version included. A deleted
+/// object's versions are listed after its delete marker, which is left out.
+pub async fn history(store: &dyn ObjectStore, key: &str) -> Result<Vec<ObjectVersion>, Error> {
+    Ok(store.list_versions(key).await?.into_iter()
+        .filter(|version| version.key == key && !version.is_delete_marker)
+        .collect())
+}
+
+/// The newest version of each deleted image, the version which restores it.
+pub async fn deleted_images(store: &dyn ObjectStore) -> Result<Vec<ObjectVersion>, Error> {
+    let versions = store.list_versions("images/").await?;
+    let deleted = versions.iter()
+        .filter(|version| version.is_latest && version.is_delete_marker)
+        .map(|version| version.key.clone())
+        .collect::<HashSet<String>>();
+    let mut restorable = HashSet::new();
+    Ok(versions.into_iter()
+        .filter(|version| !version.is_delete_marker && deleted.contains(&version.key) && restorable.insert(version.key.clone()))
+        .collect())
+}
+
+/// A version of a caption, as it would be shown.
+pub async fn caption_version(store: &dyn ObjectStore, version: &ObjectVersion) -> Result<Caption, Error> {
+    Ok(Caption::parse(&store.get_version(&version.key, &version.version_id).await?))
+}
+
+/// Makes `version` of an image current again, restoring a deleted image, and records it in the
+/// manifest as modified at `last_modified`. The version keeps its id, so its caption returns with it.
+pub async fn restore_image(store: &dyn ObjectStore, version: &ObjectVersion, last_modified: String) -> Result<(), Error> {
+    store.restore_version(&version.key, &version.version_id).await?;
+    record_image(store, &version.key, None, last_modified).await
+}
+
+/// Makes `version` of a caption current again, returns the restored caption.
+pub async fn restore_caption(store: &dyn ObjectStore, version: &ObjectVersion) -> Result<Caption, Error> {
+    let id = version.key.strip_prefix(CAPTION_PREFIX).ok_or(Error::NoSuchKey)?;
+    let caption = caption_version(store, version).await?;
+    store.restore_version(&version.key, &version.version_id).await?;
+    update_manifest(store, |manifest| manifest.set_caption(id, &caption)).await?;
+    Ok(caption)
+}
+
+/// The preview of `version`, an image's or a caption's.
+pub async fn preview(store: &dyn ObjectStore, version: ObjectVersion) -> Result<VersionPreview, Error> {
+    match version.key.starts_with(CAPTION_PREFIX) {
+        true => Ok(VersionPreview::Caption { caption: caption_version(store, &version).await?, version }),
+        false => Ok(VersionPreview::Image(version)),
+    }
+}
+
+/// Restores `version`, an image's or a caption's, returns the caption when it was a caption's.
+pub async fn restore(store: &dyn ObjectStore, version: &ObjectVersion, last_modified: String) -> Result<Option<Caption>, Error> {
+    match version.key.starts_with(CAPTION_PREFIX) {
+        true => Ok(Some(restore_caption(store, version).await?)),
+        false => restore_image(store, version, last_modified).await.map(|_| None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::versions::*;
+    use crate::api::update_comment;
+    use crate::manifest::load_gallery;
+    use crate::memory_store::MemoryStore;
+    use crate::object_store::{block_on, WriteCondition};
+    use crate::ImageInfo;
+
+    #[test]
+    fn test_image_history() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            store.put("images/a.jpg", vec![2]).await.unwrap();
+            store.put("images/ab.jpg", vec![3]).await.unwrap();
+            let versions = history(&store, "images/a.jpg").await.unwrap();
+            assert_eq!(versions.len(), 2);
+            assert!(versions[0].is_latest && !versions[1].is_latest);
+
+            restore_image(&store, &versions[1], "2023-10-12T14:48:09.000Z".to_string()).await.unwrap();
+            assert_eq!(store.get("images/a.jpg").await.unwrap(), vec![1]);
+            assert_eq!(history(&store, "images/a.jpg").await.unwrap().len(), 4);
+        });
+    }
+
+    #[test]
+    fn test_restore_deleted_image() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            store.set_id("images/a.jpg", "a-id").await.unwrap();
+            store.put("images/b.jpg", vec![2]).await.unwrap();
+            let caption = Caption { title: "kept".to_string(), ..Caption::default() };
+            update_comment(&store, &caption, "images/a.jpg", &WriteCondition::Absent).await.unwrap();
+            store.delete("images/a.jpg", None).await.unwrap();
+            assert!(history(&store, "images/a.jpg").await.unwrap().iter().all(|version| !version.is_latest));
+
+            let deleted = deleted_images(&store).await.unwrap();
+            assert_eq!(deleted.len(), 1);
+            assert_eq!(deleted[0].key, "images/a.jpg");
+            restore_image(&store, &deleted[0], "2023-10-12T14:48:09.000Z".to_string()).await.unwrap();
+            assert!(deleted_images(&store).await.unwrap().is_empty());
+
+            let gallery = load_gallery(&store, true).await.unwrap();
+            let restored = gallery.iter().find(|image| image.key() == "images/a.jpg").unwrap();
+            assert_eq!(restored.id(), Some("a-id".to_string()));
+            assert_eq!(restored.get_caption(), Some(caption));
+        });
+    }
+
+    #[test]
+    fn test_restore_caption() {
+        let store = MemoryStore::new();
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            store.set_id("images/a.jpg", "a-id").await.unwrap();
+            let first = Caption { title: "first".to_string(), ..Caption::default() };
+            let second = Caption { title: "second".to_string(), ..Caption::default() };
+            let e_tag = update_comment(&store, &first, "images/a.jpg", &WriteCondition::Absent).await.unwrap();
+            update_comment(&store, &second, "images/a.jpg", &WriteCondition::Matches(e_tag)).await.unwrap();
+
+            let versions = history(&store, "comments/a-id").await.unwrap();
+            assert_eq!(version_label(&versions[0]), "2023-11-14 22:13:24 UTC (current)");
+            assert_eq!(preview(&store, versions[1].clone()).await.unwrap(), VersionPreview::Caption { version: versions[1].clone(), caption: first.clone() });
+            assert_eq!(restore(&store, &versions[1], "2023-10-12T14:48:09.000Z".to_string()).await.unwrap(), Some(first.clone()));
+            assert_eq!(load_gallery(&store, true).await.unwrap()[0].get_caption(), Some(first));
+        });
+    }
+}
@@ -0,0 +1,299 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use quick_xml::escape::{escape, unescape};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc2822;
+
+use crate::object_store::StoreFuture;
+use crate::s3_error::error_from_response;
+use crate::webdav_store::{DavReply, DavRequest, DavTransport};
+
+/// The URL of the stand-in's share, a site's endpoint.
+pub const SHARE_URL: &str = "http://dav.test/weblum";
+const SHARE_PATH: &str = "/weblum/";
+
+#[derive(Clone)]
+struct DavFile {
+    body: Vec<u8>,
+    e_tag: String,
+    last_modified: String,
+    content_type: Option<String>,
+    // a dead property, kept by COPY and MOVE
+    weblum_id: Option<String>,
+}
+
+#[derive(Clone)]
+enum Resource {
+    Collection,
+    File(DavFile),
+}
+
+// A response's status, ETag and body.
+type Reply = (u16, Option<String>, Vec<u8>);
+
+fn status(status: u16) -> Reply {
+    (status, None, vec![])
+}
+
+fn header<'a>(request: &'a DavRequest, name: &str) -> Option<&'a str> {
+    request.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+// The key of a URL on the share, without a trailing '/', the share itself is "".
+fn share_key(url: &str) -> Option<String> {
+    let path = url.strip_prefix(SHARE_URL)?;
+    Some(urlencoding::decode(path).ok()?.trim_matches('/').to_string())
+}
+
+fn parent(key: &str) -> &str {
+    key.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+fn within(key: &str, collection: &str) -> bool {
+    key == collection || key.starts_with(&format!("{}/", collection))
+}
+
+fn href(key: &str, resource: &Resource) -> String {
+    let encoded = key.split('/').map(|segment| urlencoding::encode(segment).into_owned()).collect::<Vec<String>>().join("/");
+    match (key.is_empty(), resource) {
+        (true, _) => SHARE_PATH.to_string(),
+        (false, Resource::Collection) => format!("{}{}/", SHARE_PATH, encoded),
+        (false, Resource::File(_)) => format!("{}{}", SHARE_PATH, encoded),
+    }
+}
+
+// The PROPFIND response for one resource, properties it does not have are reported as not found.
+fn response_xml(key: &str, resource: &Resource) -> String {
+    let found = match resource {
+        Resource::Collection => "<d:resourcetype><d:collection/></d:resourcetype>".to_string(),
+        Resource::File(file) => format!(
+            "<d:resourcetype/><d:getetag>{}</d:getetag><d:getlastmodified>{}</d:getlastmodified><d:getcontentlength>{}</d:getcontentlength>{}{}",
+            escape(&file.e_tag), file.last_modified, file.body.len(),
+            file.content_type.as_ref().map(|t| format!("<d:getcontenttype>{}</d:getcontenttype>", escape(t))).unwrap_or_default(),
+            file.weblum_id.as_ref().map(|id| format!("<w:weblum-id>{}</w:weblum-id>", escape(id))).unwrap_or_default(),
+        ),
+    };
+    let missing = match resource {
+        Resource::File(DavFile { weblum_id: None, .. }) =>
+            "<d:propstat><d:prop><w:weblum-id/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>",
+        _ => "",
+    };
+    format!(
+        "<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>{}</d:response>",
+        href(key, resource), found, missing,
+    )
+}
+
+fn multistatus(responses: String) -> Reply {
+    let xml = format!(r#"<?xml version="1.0" encoding="utf-8"?><d:multistatus xmlns:d="DAV:" xmlns:w="urn:weblum">{}</d:multistatus>"#, responses);
+    (207, None, xml.into_bytes())
+}
+
+/// A WebDAV server kept in memory, for testing `WebDavStore` without one. Requests must have the
+/// basic auth of the user and password it is created with. As some servers do, a collection is
+/// listed after its members, and writes to a missing collection fail with 409 Conflict.
+pub struct WebDavStandIn {
+    authorization: String,
+    resources: RefCell<BTreeMap<String, Resource>>,
+    clock: Cell<i64>,
+}
+
+impl WebDavStandIn {
+    pub fn new(user: &str, password: &str) -> Self {
+        Self {
+            authorization: format!("Basic {}", STANDARD.encode(format!("{}:{}", user, password))),
+            resources: RefCell::new(BTreeMap::from([("".to_string(), Resource::Collection)])),
+            clock: Cell::new(1_700_000_000),
+        }
+    }
+
+    // A new ETag and modified time for a write.
+    fn tick(&self) -> (String, String) {
+        self.clock.set(self.clock.get() + 1);
+        let last_modified = OffsetDateTime::from_unix_timestamp(self.clock.get())
+            .expect("valid timestamp")
+            .format(&Rfc2822)
+            .expect("formatted timestamp");
+        (format!("\"{:x}\"", self.clock.get()), last_modified)
+    }
+
+    fn resource(&self, key: &str) -> Option<Resource> {
+        self.resources.borrow().get(key).cloned()
+    }
+
+    fn is_collection(&self, key: &str) -> bool {
+        matches!(self.resource(key), Some(Resource::Collection))
+    }
+
+    // `If-Match` names the resource's current ETag, `If-None-Match: *` that there is none.
+    fn preconditions_hold(&self, request: &DavRequest, key: &str) -> bool {
+        let e_tag = match self.resource(key) {
+            Some(Resource::File(file)) => Some(file.e_tag),
+            _ => None,
+        };
+        match (header(request, "If-Match"), header(request, "If-None-Match")) {
+            (Some(expected), _) => e_tag.as_deref() == Some(expected),
+            (_, Some("*")) => self.resource(key).is_none(),
+            _ => true,
+        }
+    }
+
+    fn remove_within(&self, key: &str) {
+        self.resources.borrow_mut().retain(|existing, _| !within(existing, key));
+    }
+
+    fn handle(&self, request: &DavRequest) -> Reply {
+        if header(request, "Authorization") != Some(self.authorization.as_str()) {
+            return status(401);
+        }
+        let key = match share_key(&request.url) {
+            Some(key) => key,
+            None => return status(404),
+        };
+        match request.method.as_str() {
+            "GET" => match self.resource(&key) {
+                Some(Resource::File(file)) => (200, Some(file.e_tag), file.body),
+                Some(Resource::Collection) => status(405),
+                None => status(404),
+            },
+            "PUT" => self.put(request, &key),
+            "MKCOL" => match (self.resource(&key), self.is_collection(parent(&key))) {
+                (Some(_), _) => status(405),
+                (None, false) => status(409),
+                (None, true) => {
+                    self.resources.borrow_mut().insert(key, Resource::Collection);
+                    status(201)
+                },
+            },
+            "DELETE" => match (self.resource(&key), self.preconditions_hold(request, &key)) {
+                (None, _) => status(404),
+                (Some(_), false) => status(412),
+                (Some(_), true) => {
+                    self.remove_within(&key);
+                    status(204)
+                },
+            },
+            "COPY" | "MOVE" => self.copy(request, &key),
+            "PROPPATCH" => self.proppatch(request, &key),
+            "PROPFIND" => self.propfind(request, &key),
+            _ => status(405),
+        }
+    }
+
+    fn put(&self, request: &DavRequest, key: &str) -> Reply {
+        if !self.is_collection(parent(key)) {
+            return status(409);
+        }
+        if !self.preconditions_hold(request, key) {
+            return status(412);
+        }
+        let weblum_id = match self.resource(key) {
+            Some(Resource::Collection) => return status(405),
+            Some(Resource::File(file)) => file.weblum_id,
+            None => None,
+        };
+        let (e_tag, last_modified) = self.tick();
+        let file = DavFile {
+            body: request.body.clone().unwrap_or_default(),
+            e_tag: e_tag.clone(),
+            last_modified,
+            content_type: header(request, "Content-Type").map(str::to_string),
+            weblum_id,
+        };
+        self.resources.borrow_mut().insert(key.to_string(), Resource::File(file));
+        (201, Some(e_tag), vec![])
+    }
+
+    // COPY and MOVE of a resource and, for a collection, its members.
+    fn copy(&self, request: &DavRequest, key: &str) -> Reply {
+        let destination = match header(request, "Destination").and_then(share_key) {
+            Some(destination) => destination,
+            None => return status(400),
+        };
+        if self.resource(key).is_none() {
+            return status(404);
+        }
+        if !self.preconditions_hold(request, key) {
+            return status(412);
+        }
+        if !self.is_collection(parent(&destination)) {
+            return status(409);
+        }
+        let replaced = self.resource(&destination).is_some();
+        if replaced && header(request, "Overwrite") == Some("F") {
+            return status(412);
+        }
+        let copied = self.resources.borrow().iter()
+            .filter(|(existing, _)| within(existing, key))
+            .map(|(existing, resource)| (format!("{}{}", destination, &existing[key.len()..]), resource.clone()))
+            .collect::<Vec<(String, Resource)>>();
+        self.remove_within(&destination);
+        if request.method == "MOVE" {
+            self.remove_within(key);
+        }
+        for (copy_key, resource) in copied {
+            let resource = match resource {
+                // a copy is a new resource, with its own ETag
+                Resource::File(file) if request.method == "COPY" => {
+                    let (e_tag, last_modified) = self.tick();
+                    Resource::File(DavFile { e_tag, last_modified, ..file })
+                },
+                resource => resource,
+            };
+            self.resources.borrow_mut().insert(copy_key, resource);
+        }
+        status(if replaced { 204 } else { 201 })
+    }
+
+    // Only the app's `weblum-id` property is set.
+    fn proppatch(&self, request: &DavRequest, key: &str) -> Reply {
+        let mut file = match self.resource(key) {
+            Some(Resource::File(file)) => file,
+            Some(Resource::Collection) => return status(403),
+            None => return status(404),
+        };
+        let body = String::from_utf8_lossy(request.body.as_deref().unwrap_or_default()).into_owned();
+        let id = body.split_once("weblum-id>").and_then(|(_, rest)| rest.split_once("</")).map(|(id, _)| id);
+        file.weblum_id = match id.map(unescape) {
+            Some(Ok(id)) => Some(id.into_owned()),
+            _ => return status(400),
+        };
+        self.resources.borrow_mut().insert(key.to_string(), Resource::File(file));
+        multistatus(format!(
+            "<d:response><d:href>{}</d:href><d:propstat><d:prop><w:weblum-id/></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+            SHARE_PATH,
+        ))
+    }
+
+    fn propfind(&self, request: &DavRequest, key: &str) -> Reply {
+        let resource = match self.resource(key) {
+            Some(resource) => resource,
+            None => return status(404),
+        };
+        let mut responses = String::new();
+        if matches!(resource, Resource::Collection) && header(request, "Depth") != Some("0") {
+            for (member, member_resource) in self.resources.borrow().iter() {
+                if !member.is_empty() && member != key && parent(member) == key {
+                    responses.push_str(&response_xml(member, member_resource));
+                }
+            }
+        }
+        responses.push_str(&response_xml(key, &resource));
+        multistatus(responses)
+    }
+}
+
+/// Answers as a server would, failing unsuccessful responses as `send_with_retry` does.
+impl DavTransport for WebDavStandIn {
+    fn send(&self, request: DavRequest) -> StoreFuture<'_, DavReply> {
+        let (status, e_tag, body) = self.handle(&request);
+        Box::pin(async move {
+            match (200..300).contains(&status) {
+                true => Ok(DavReply { e_tag, body }),
+                false => Err(error_from_response(status, &String::from_utf8_lossy(&body))),
+            }
+        })
+    }
+}
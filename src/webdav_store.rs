@@ -0,0 +1,557 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use gloo_net::http::{Method, RequestBuilder};
+use js_sys::Uint8Array;
+use quick_xml::escape::escape;
+use serde::Deserialize;
+use time::OffsetDateTime;
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+
+use crate::api::{is_idempotent, send_with_retry, Error};
+use crate::local_config::SiteConfig;
+use crate::object_headers::content_type;
+use crate::object_store::{ObjectHead, ObjectPage, ObjectStore, StoreFuture, WriteCondition};
+use crate::S3ObjectInfo;
+
+// Asks only for what a listing needs, servers otherwise send every property they have.
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getetag/><d:getlastmodified/><d:getcontentlength/></d:prop></d:propfind>"#;
+// HEAD does not return properties, an object's id is a dead property in the app's own namespace.
+const HEAD_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:" xmlns:w="urn:weblum"><d:prop><d:getetag/><d:getcontentlength/><d:getcontenttype/><w:weblum-id/></d:prop></d:propfind>"#;
+
+/// The body of a PROPFIND response, namespace prefixes such as `d:` are ignored when parsing.
+#[derive(Deserialize)]
+struct Multistatus {
+    #[serde(rename(deserialize = "response"), default)]
+    responses: Vec<DavResponse>,
+}
+
+#[derive(Deserialize)]
+struct DavResponse {
+    href: String,
+    #[serde(default)]
+    propstat: Vec<PropStat>,
+}
+
+#[derive(Deserialize)]
+struct PropStat {
+    prop: Prop,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct Prop {
+    getetag: Option<String>,
+    getlastmodified: Option<String>,
+    // empty in the propstat of properties the server does not have
+    getcontentlength: Option<String>,
+    getcontenttype: Option<String>,
+    #[serde(rename(deserialize = "weblum-id"))]
+    weblum_id: Option<String>,
+    resourcetype: Option<ResourceType>,
+}
+
+#[derive(Deserialize)]
+struct ResourceType {
+    collection: Option<()>,
+}
+
+// A member of a collection, from one PROPFIND response.
+#[derive(Debug, PartialEq)]
+enum DavEntry {
+    Collection(String),
+    Object(S3ObjectInfo),
+}
+
+/// A request to a WebDAV server, with the store's authorization among its headers.
+pub struct DavRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+/// The parts of a successful response the store reads.
+pub struct DavReply {
+    pub e_tag: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl DavReply {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Sends a `WebDavStore`'s requests. Responses which are not successful fail as they do in
+/// `send_with_retry`, e.g. with `Error::Status`, which the store maps to its own errors.
+pub trait DavTransport {
+    fn send(&self, request: DavRequest) -> StoreFuture<'_, DavReply>;
+}
+
+// Sends requests with the browser's fetch, retrying transient failures.
+struct FetchTransport;
+
+impl DavTransport for FetchTransport {
+    fn send(&self, request: DavRequest) -> StoreFuture<'_, DavReply> {
+        Box::pin(async move {
+            let method = Method::from_bytes(request.method.as_bytes()).map_err(|err| Error::Fetch(format!("{}", err)))?;
+            let build = || {
+                let mut builder = RequestBuilder::new(&request.url).method(method.clone());
+                for (name, value) in &request.headers {
+                    builder = builder.header(name, value);
+                }
+                match &request.body {
+                    Some(bytes) => {
+                        let u = Uint8Array::new_with_length(bytes.len() as u32);
+                        u.copy_from(bytes);
+                        builder.body(u)
+                    },
+                    None => builder.build(),
+                }.map_err(|err| Error::Fetch(format!("{}", err)))
+            };
+            let response = send_with_retry(build, is_idempotent(&request.method, &request.headers), None).await?;
+            let e_tag = response.headers().get("ETag");
+            let body = response.binary().await.map_err(|err| Error::Fetch(format!("{}", err)))?;
+            Ok(DavReply { e_tag, body })
+        })
+    }
+}
+
+/// An `ObjectStore` on a WebDAV share, such as a Nextcloud folder. The site's endpoint is the URL of
+/// the folder and its access and secret keys are the user name and password, sent with basic auth.
+pub struct WebDavStore {
+    config: SiteConfig,
+    transport: Box<dyn DavTransport>,
+}
+
+impl WebDavStore {
+    pub fn new(config: SiteConfig) -> Self {
+        Self::with_transport(config, Box::new(FetchTransport))
+    }
+
+    /// A store sending its requests with `transport`, e.g. to a stand-in for a WebDAV server.
+    pub fn with_transport(config: SiteConfig, transport: Box<dyn DavTransport>) -> Self {
+        Self { config, transport }
+    }
+
+    fn root(&self) -> String {
+        self.config.endpoint.clone().unwrap_or_default().trim_end_matches('/').to_string()
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.root(), encode_key(key))
+    }
+
+    fn authorization(&self) -> Option<String> {
+        let user = self.config.access_key.as_ref()?;
+        let password = self.config.secret_key.as_deref().unwrap_or_default();
+        Some(format!("Basic {}", STANDARD.encode(format!("{}:{}", user, password))))
+    }
+
+    async fn send(&self, method: &str, url: &str, headers: &[(&str, String)], body: Option<&[u8]>) -> Result<DavReply, Error> {
+        let mut request_headers = vec![];
+        if let Some(authorization) = self.authorization() {
+            request_headers.push(("Authorization".to_string(), authorization));
+        }
+        request_headers.extend(headers.iter().map(|(name, value)| (name.to_string(), value.clone())));
+        let request = DavRequest { method: method.to_string(), url: url.to_string(), headers: request_headers, body: body.map(<[u8]>::to_vec) };
+        self.transport.send(request).await.map_err(dav_error)
+    }
+
+    // PUT, COPY and MOVE fail with 409 Conflict when the parent collection is missing.
+    async fn create_parents(&self, key: &str) -> Result<(), Error> {
+        let segments = key.split('/').collect::<Vec<&str>>();
+        for depth in 1..segments.len() {
+            let collection = format!("{}/", segments[..depth].join("/"));
+            match self.send("MKCOL", &self.url(&collection), &[], None).await {
+                // 405 Method Not Allowed, the collection already exists
+                Ok(_) | Err(Error::Status(405, _)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    // Sends a request which writes `key`, creating its collections when they are missing.
+    async fn send_write(&self, method: &str, url: &str, key: &str, headers: &[(&str, String)], body: Option<&[u8]>) -> Result<DavReply, Error> {
+        match self.send(method, url, headers, body).await {
+            Err(Error::Status(409, _)) => {
+                self.create_parents(key).await?;
+                self.send(method, url, headers, body).await
+            },
+            result => result,
+        }
+    }
+
+    async fn list_collection(&self, collection: &str) -> Result<Vec<DavEntry>, Error> {
+        let headers = [("Depth", "1".to_string()), ("Content-Type", "application/xml".to_string())];
+        let response = match self.send("PROPFIND", &self.url(collection), &headers, Some(PROPFIND_BODY.as_bytes())).await {
+            Ok(response) => response,
+            // nothing has been stored under the prefix yet
+            Err(Error::NoSuchKey) => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        parse_multistatus(&response.text(), &url::Url::parse(&self.root()).map(|u| u.path().to_string()).unwrap_or_default(), collection)
+    }
+}
+
+// S3's errors are XML bodies, a WebDAV server's are mostly its status.
+fn dav_error(err: Error) -> Error {
+    match err {
+        Error::Status(404, _) => Error::NoSuchKey,
+        Error::Status(412, _) => Error::PreconditionFailed,
+        Error::Status(401, message) | Error::Status(403, message) => Error::AccessDenied(message),
+        err => err,
+    }
+}
+
+// Each segment of the key is URL encoded, keeping the '/' separators.
+fn encode_key(key: &str) -> String {
+    key.split('/').map(|segment| urlencoding::encode(segment).into_owned()).collect::<Vec<String>>().join("/")
+}
+
+// Some servers send an ETag without quotes, S3 and the captions keyed by it always have them.
+fn quoted(e_tag: &str) -> String {
+    match e_tag.starts_with('"') || e_tag.starts_with("W/") {
+        true => e_tag.to_string(),
+        false => format!("\"{}\"", e_tag),
+    }
+}
+
+// WebDAV dates are as in HTTP headers, listings are sorted by S3's format.
+fn s3_date(http_date: &str) -> String {
+    OffsetDateTime::parse(http_date, &Rfc2822)
+        .ok()
+        .and_then(|date| date.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+/// The entries of a PROPFIND response, keyed relative to `root_path`, the path of the share's URL.
+/// The response for `collection`, the collection listed, is left out wherever the server puts it.
+fn parse_multistatus(xml: &str, root_path: &str, collection: &str) -> Result<Vec<DavEntry>, Error> {
+    let multistatus: Multistatus = quick_xml::de::from_str(xml)
+        .map_err(|err| Error::Fetch(format!("unexpected PROPFIND response: {}", err)))?;
+    let root = format!("{}/", root_path.trim_end_matches('/'));
+    let mut entries = vec![];
+    for response in multistatus.responses {
+        // hrefs are absolute paths or full URLs
+        let path = match url::Url::parse(&response.href) {
+            Ok(u) => u.path().to_string(),
+            Err(_) => response.href.clone(),
+        };
+        let path = urlencoding::decode(&path).map(|p| p.into_owned()).unwrap_or(path);
+        let key = match path.strip_prefix(&root) {
+            Some(key) if key.trim_end_matches('/') != collection.trim_end_matches('/') => key.to_string(),
+            _ => continue,
+        };
+        let prop = match response.propstat.into_iter().find(|p| p.status.contains(" 200 ")) {
+            Some(propstat) => propstat.prop,
+            None => continue,
+        };
+        let is_collection = prop.resourcetype.as_ref().and_then(|r| r.collection).is_some();
+        entries.push(match is_collection {
+            true => DavEntry::Collection(format!("{}/", key.trim_end_matches('/'))),
+            false => DavEntry::Object(S3ObjectInfo::new(
+                key,
+                prop.getlastmodified.as_deref().map(s3_date).unwrap_or_default(),
+                prop.getetag.as_deref().map(quoted).unwrap_or_default(),
+                prop.getcontentlength.and_then(|length| length.parse().ok()).unwrap_or_default(),
+            )),
+        });
+    }
+    Ok(entries)
+}
+
+/// What a Depth 0 PROPFIND of an object says about it, from the properties the server has.
+fn parse_head(xml: &str) -> Result<ObjectHead, Error> {
+    let multistatus: Multistatus = quick_xml::de::from_str(xml)
+        .map_err(|err| Error::Fetch(format!("unexpected PROPFIND response: {}", err)))?;
+    let prop = multistatus.responses.into_iter().next()
+        .and_then(|response| response.propstat.into_iter().find(|p| p.status.contains(" 200 ")))
+        .map(|propstat| propstat.prop)
+        .ok_or(Error::NoSuchKey)?;
+    Ok(ObjectHead {
+        e_tag: prop.getetag.as_deref().map(quoted),
+        size: prop.getcontentlength.and_then(|length| length.parse().ok()),
+        content_type: prop.getcontenttype,
+        id: prop.weblum_id,
+        checksum: None,
+    })
+}
+
+impl ObjectStore for WebDavStore {
+    /// Lists the prefix's collection and those within it, in one page.
+    fn list_page<'a>(&'a self, prefix: &'a str, _continuation: Option<String>) -> StoreFuture<'a, ObjectPage> {
+        Box::pin(async move {
+            let start = match prefix.rfind('/') {
+                Some(i) => prefix[..=i].to_string(),
+                None => "".to_string(),
+            };
+            let mut collections = vec![start];
+            let mut objects = vec![];
+            while let Some(collection) = collections.pop() {
+                for entry in self.list_collection(&collection).await? {
+                    match entry {
+                        DavEntry::Collection(key) => collections.push(key),
+                        DavEntry::Object(object) => objects.push(object),
+                    }
+                }
+            }
+            objects.retain(|object| {
+                let key = crate::ImageInfo::key(object);
+                key.starts_with(prefix) && key != prefix
+            });
+            objects.sort_by_key(crate::ImageInfo::key);
+            Ok(ObjectPage { objects, next_token: None })
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            Ok(self.send("GET", &self.url(key), &[], None).await?.body)
+        })
+    }
+
+    fn head<'a>(&'a self, key: &'a str) -> StoreFuture<'a, ObjectHead> {
+        Box::pin(async move {
+            let headers = [("Depth", "0".to_string()), ("Content-Type", "application/xml".to_string())];
+            let response = self.send("PROPFIND", &self.url(key), &headers, Some(HEAD_BODY.as_bytes())).await?;
+            parse_head(&response.text())
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let headers = [("Content-Type", content_type(key, &body).to_string())];
+            self.send_write("PUT", &self.url(key), key, &headers, Some(&body)).await?;
+            Ok(())
+        })
+    }
+
+    fn get_with_e_tag<'a>(&'a self, key: &'a str) -> StoreFuture<'a, (Vec<u8>, String)> {
+        Box::pin(async move {
+            let response = self.send("GET", &self.url(key), &[], None).await?;
+            let e_tag = response.e_tag.as_deref().map(quoted).ok_or(Error::Fetch(format!("no ETag for {}", key)))?;
+            Ok((response.body, e_tag))
+        })
+    }
+
+    /// WebDAV servers honour `If-Match` and `If-None-Match` on PUT, as S3 does.
+    fn put_if<'a>(&'a self, key: &'a str, body: Vec<u8>, condition: &'a WriteCondition) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let (name, value) = condition.header();
+            let headers = [(name, value), ("Content-Type", content_type(key, &body).to_string())];
+            let response = self.send_write("PUT", &self.url(key), key, &headers, Some(&body)).await?;
+            match response.e_tag {
+                Some(e_tag) => Ok(quoted(&e_tag)),
+                None => self.head(key).await?.e_tag.ok_or(Error::Fetch(format!("no ETag for {}", key))),
+            }
+        })
+    }
+
+    fn copy<'a>(&'a self, from: &'a str, to: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let mut headers = vec![("Destination", self.url(to)), ("Overwrite", "T".to_string())];
+            if let Some(e_tag) = if_match {
+                headers.push(("If-Match", e_tag.to_string()));
+            }
+            self.send_write("COPY", &self.url(from), to, &headers, None).await?;
+            // the copy's ETag is not in the response to COPY
+            self.head(to).await?.e_tag.ok_or(Error::Fetch(format!("no ETag for {}", to)))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str, if_match: Option<&'a str>) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let headers = match if_match {
+                Some(e_tag) => vec![("If-Match", e_tag.to_string())],
+                None => vec![],
+            };
+            match self.send("DELETE", &self.url(key), &headers, None).await {
+                // as S3, deleting a missing key succeeds unless a precondition was given
+                Err(Error::NoSuchKey) if if_match.is_none() => Ok(()),
+                result => result.map(|_| ()),
+            }
+        })
+    }
+
+    /// COPY and MOVE keep dead properties, so the id stays with the object.
+    fn set_id<'a>(&'a self, key: &'a str, id: &'a str) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let body = format!(
+                r#"<?xml version="1.0" encoding="utf-8"?><d:propertyupdate xmlns:d="DAV:" xmlns:w="urn:weblum"><d:set><d:prop><w:weblum-id>{}</w:weblum-id></d:prop></d:set></d:propertyupdate>"#,
+                escape(id),
+            );
+            let headers = [("Content-Type", "application/xml".to_string())];
+            self.send("PROPPATCH", &self.url(key), &headers, Some(body.as_bytes())).await?;
+            self.head(key).await?.e_tag.ok_or(Error::Fetch(format!("no ETag for {}", key)))
+        })
+    }
+
+    fn move_object<'a>(&'a self, from: &'a str, to: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let headers = [("Destination", self.url(to)), ("Overwrite", "T".to_string())];
+            self.send_write("MOVE", &self.url(from), to, &headers, None).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ImageInfo, S3ObjectInfo};
+    use crate::api::Error;
+    use crate::local_config::{AddressingStyle, SiteConfig, SiteKind};
+    use crate::object_store::{block_on, list_all, ObjectHead, ObjectStore, WriteCondition};
+    use crate::webdav_stand_in::{WebDavStandIn, SHARE_URL};
+    use crate::webdav_store::{encode_key, parse_head, parse_multistatus, quoted, DavEntry, WebDavStore};
+
+    // A store on a stand-in share of alice's, with `password`.
+    fn stand_in_store(password: &str) -> WebDavStore {
+        let config = SiteConfig::new("photos".to_string(), Some("alice".to_string()), Some(password.to_string()), "".to_string())
+            .with_kind(SiteKind::WebDav)
+            .with_endpoint(Some(SHARE_URL.to_string()), AddressingStyle::Path);
+        WebDavStore::with_transport(config, Box::new(WebDavStandIn::new("alice", "secret")))
+    }
+
+    #[test]
+    fn test_put_list_and_get() {
+        let store = stand_in_store("secret");
+        block_on(async {
+            // the collections are made when the first write to them fails with 409 Conflict
+            store.put("images/2023/red door.jpg", vec![1, 2]).await.unwrap();
+            store.put("images/a.jpg", vec![3]).await.unwrap();
+            let keys = list_all(&store, "images/").await.unwrap().iter().map(|object| object.key()).collect::<Vec<String>>();
+            assert_eq!(keys, vec!["images/a.jpg", "images/2023/red door.jpg"]);
+            assert!(list_all(&store, "albums/").await.unwrap().is_empty());
+            assert_eq!(store.get("images/2023/red door.jpg").await.unwrap(), vec![1, 2]);
+            let head = store.head("images/a.jpg").await.unwrap();
+            assert_eq!((head.size, head.content_type.as_deref(), head.id), (Some(1), Some("image/jpeg"), None));
+            assert!(matches!(store.get("images/b.jpg").await, Err(Error::NoSuchKey)));
+            assert!(matches!(store.head("images/b.jpg").await, Err(Error::NoSuchKey)));
+        });
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let store = stand_in_store("secret");
+        block_on(async {
+            let e_tag = store.put_if("comments/a-id", b"{}".to_vec(), &WriteCondition::Absent).await.unwrap();
+            assert!(matches!(store.put_if("comments/a-id", b"{}".to_vec(), &WriteCondition::Absent).await, Err(Error::PreconditionFailed)));
+            assert_eq!(store.get_with_e_tag("comments/a-id").await.unwrap(), (b"{}".to_vec(), e_tag.clone()));
+            let newer = store.put_if("comments/a-id", b"[]".to_vec(), &WriteCondition::Matches(e_tag.clone())).await.unwrap();
+            assert!(matches!(store.put_if("comments/a-id", b"{}".to_vec(), &WriteCondition::Matches(e_tag.clone())).await, Err(Error::PreconditionFailed)));
+
+            assert!(matches!(store.delete("comments/a-id", Some(&e_tag)).await, Err(Error::PreconditionFailed)));
+            store.delete("comments/a-id", Some(&newer)).await.unwrap();
+            // as on S3, deleting a missing key succeeds unless a precondition was given
+            store.delete("comments/a-id", None).await.unwrap();
+            assert!(matches!(store.delete("comments/a-id", Some(&newer)).await, Err(Error::NoSuchKey)));
+        });
+    }
+
+    #[test]
+    fn test_copy_move_and_ids() {
+        let store = stand_in_store("secret");
+        block_on(async {
+            store.put("images/a.jpg", vec![1]).await.unwrap();
+            let e_tag = store.set_id("images/a.jpg", "a-id").await.unwrap();
+            assert_eq!(store.head("images/a.jpg").await.unwrap().id.as_deref(), Some("a-id"));
+
+            let copied = store.copy("images/a.jpg", "inbox/a.jpg", Some(&e_tag)).await.unwrap();
+            assert_ne!(copied, e_tag);
+            assert!(matches!(store.copy("images/a.jpg", "inbox/b.jpg", Some("\"stale\"")).await, Err(Error::PreconditionFailed)));
+
+            // the id moves with the object, over the one it replaces
+            store.put("images/b.jpg", vec![2]).await.unwrap();
+            store.move_object("inbox/a.jpg", "images/b.jpg").await.unwrap();
+            assert_eq!(store.get("images/b.jpg").await.unwrap(), vec![1]);
+            assert_eq!(store.head("images/b.jpg").await.unwrap().id.as_deref(), Some("a-id"));
+            assert!(matches!(store.head("inbox/a.jpg").await, Err(Error::NoSuchKey)));
+        });
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let store = stand_in_store("guess");
+        block_on(async {
+            assert!(matches!(store.get("images/a.jpg").await, Err(Error::AccessDenied(_))));
+            assert!(matches!(store.put("images/a.jpg", vec![1]).await, Err(Error::AccessDenied(_))));
+        });
+    }
+
+    #[test]
+    fn test_parse_nextcloud_multistatus() {
+        let xml = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/alice/weblum/images/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype><d:getetag>"65281a1b"</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+    <d:propstat><d:prop><d:getcontentlength/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/alice/weblum/images/red%20door.jpg</d:href>
+    <d:propstat><d:prop><d:resourcetype/><d:getetag>&quot;3a8d1f0e&quot;</d:getetag><d:getlastmodified>Thu, 12 Oct 2023 14:48:09 GMT</d:getlastmodified><d:getcontentlength>48213</d:getcontentlength></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/alice/weblum/images/2023/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        assert_eq!(parse_multistatus(xml, "/remote.php/dav/files/alice/weblum", "images/").unwrap(), vec![
+            DavEntry::Object(S3ObjectInfo::new("images/red door.jpg".to_string(), "2023-10-12T14:48:09Z".to_string(), "\"3a8d1f0e\"".to_string(), 48213)),
+            DavEntry::Collection("images/2023/".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_full_url_hrefs() {
+        let xml = r#"<D:multistatus xmlns:D="DAV:">
+  <D:response><D:href>http://localhost:6065/weblum/</D:href><D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+  <D:response><D:href>http://localhost:6065/weblum/comments/%2265281a1b%22</D:href><D:propstat><D:prop><D:resourcetype></D:resourcetype><D:getetag>65281a1c</D:getetag></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>
+</D:multistatus>"#;
+        assert_eq!(parse_multistatus(xml, "/weblum/", "").unwrap(), vec![
+            DavEntry::Object(S3ObjectInfo::new("comments/\"65281a1b\"".to_string(), "".to_string(), "\"65281a1c\"".to_string(), 0)),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_collection_listed_last() {
+        let xml = r#"<d:multistatus xmlns:d="DAV:">
+  <d:response><d:href>/weblum/images/2023/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+  <d:response><d:href>/weblum/images</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+</d:multistatus>"#;
+        assert_eq!(parse_multistatus(xml, "/weblum", "images/").unwrap(), vec![DavEntry::Collection("images/2023/".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_head() {
+        let xml = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:w="urn:weblum">
+  <d:response>
+    <d:href>/remote.php/dav/files/alice/weblum/images/red%20door.jpg</d:href>
+    <d:propstat><d:prop><d:getetag>"3a8d1f0e"</d:getetag><d:getcontentlength>48213</d:getcontentlength><d:getcontenttype>image/jpeg</d:getcontenttype><w:weblum-id>7c9e6679-7425-40de-944b-e07fc1f90ae7</w:weblum-id></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        assert_eq!(parse_head(xml).unwrap(), ObjectHead {
+            e_tag: Some("\"3a8d1f0e\"".to_string()),
+            size: Some(48213),
+            content_type: Some("image/jpeg".to_string()),
+            id: Some("7c9e6679-7425-40de-944b-e07fc1f90ae7".to_string()),
+            checksum: None,
+        });
+
+        // an object stored before it had an id
+        let xml = r#"<d:multistatus xmlns:d="DAV:"><d:response><d:href>/weblum/images/a.jpg</d:href>
+<d:propstat><d:prop><d:getetag>"3a8d1f0e"</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+<d:propstat><d:prop><w:weblum-id xmlns:w="urn:weblum"/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>
+</d:response></d:multistatus>"#;
+        assert_eq!(parse_head(xml).unwrap().id, None);
+    }
+
+    #[test]
+    fn test_keys_and_e_tags() {
+        assert_eq!(encode_key("comments/\"abc\""), "comments/%22abc%22");
+        assert_eq!(quoted("abc"), "\"abc\"");
+        assert_eq!(quoted("W/\"abc\""), "W/\"abc\"");
+    }
+}
@@ -2,11 +2,11 @@ use leptos::*;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_test::*;
 use js_sys::Date;
-use gloo_net::http::RequestBuilder;
-use gloo_net::http::Method;
 
 use crate::awssigv4::generate_headers;
-use crate::awssigv4::_canonicalize_request;
+use crate::awssigv4::generate_signed_headers;
+use crate::awssigv4::SigningCredentials;
+use crate::awssigv4::generate_presigned_url;
 
 
 #[path="../src/awssigv4.rs"]
@@ -33,10 +33,27 @@ fn test_generate_headers() {
 }
 
 #[wasm_bindgen_test]
-async fn test_canonical_request() {
-    let headers = generate_headers(&Date::new(&JsValue::from(1697122089958.0)));
+fn test_generate_signed_headers() {
+    let credentials = SigningCredentials::new(
+        "AKIDEXAMPLE".to_string(),
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".parse().unwrap(),
+        "us-west-2".to_string(),
+    );
     let url = "http://bucketname.s3.amazonaws.com/images/image.jpeg";
-    let request = RequestBuilder::new(&url).headers(headers).method(Method::PUT).body("file").expect("request expected");
-    let _result = _canonicalize_request(request).await.unwrap();
-    //assert_eq!(result, "foo");
+    let result = generate_signed_headers(&Date::new(&JsValue::from(1697122089958.0)), &credentials, "PUT", url, &[], Some("file".as_bytes())).unwrap();
+    assert_eq!(result.get("X-Amz-Date").unwrap(), "20231012T144809Z");
+    assert!(result.get("Authorization").unwrap().starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20231012/us-west-2/s3/aws4_request"));
+}
+
+#[wasm_bindgen_test]
+fn test_generate_presigned_url() {
+    let credentials = SigningCredentials::new(
+        "AKIDEXAMPLE".to_string(),
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".parse().unwrap(),
+        "us-west-2".to_string(),
+    );
+    let url = "https://bucketname.s3.amazonaws.com/images/image.jpeg";
+    let result = generate_presigned_url(&Date::new(&JsValue::from(1697122089958.0)), &credentials, "GET", url, 3600).unwrap();
+    assert!(result.contains("X-Amz-Date=20231012T144809Z&X-Amz-Expires=3600"));
+    assert!(result.contains("&X-Amz-Signature="));
 }